@@ -2,59 +2,487 @@
 
 use std::num::NonZero;
 
-use nalgebra::{Matrix4, Point3, Translation3, UnitQuaternion, Vector3};
+use nalgebra::{Isometry3, Matrix4, Point3, Translation3, Unit, UnitQuaternion, Vector3, Vector4};
+use winit::dpi::PhysicalPosition;
 
-use crate::scene::TransformComponent;
+use crate::{
+    culling::Frustum,
+    scene::{GameObjectKey, TransformComponent},
+    texture::TextureIndex,
+};
 
-#[derive(Debug)]
+use effect::CameraEffects;
+
+pub mod controller;
+pub mod effect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 描画先の大きさ (物理ピクセル)
 pub struct Viewport {
     pub width: NonZero<u32>,
     pub height: NonZero<u32>,
 }
 
-#[derive(Debug)]
-pub enum Camera {
-    Orthographic(OrthographicCamera),
-    Perspective(PerspectiveCamera),
+impl Viewport {
+    pub const fn new(width: NonZero<u32>, height: NonZero<u32>) -> Self {
+        Self { width, height }
+    }
+
+    /// 幅 / 高さ
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width.get() as f32 / self.height.get() as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 描画先の大きさを 1.0 とした矩形。左上が原点で、Y 軸は下向き。
+///
+/// 画面分割やミニマップのために、カメラが描画する範囲を指定する。
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for ViewportRect {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+impl ViewportRect {
+    /// 描画先全体
+    pub const FULL: Self = Self::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// 描画先の大きさ `viewport` に合わせて物理ピクセル単位の矩形に変換する
+    ///
+    /// 描画先からはみ出す部分は切り取られる。幅と高さは 1 ピクセル以上になる。
+    pub fn to_pixels(&self, viewport: &Viewport) -> PixelRect {
+        let width = viewport.width.get() as f32;
+        let height = viewport.height.get() as f32;
+        let left = (self.x * width).clamp(0.0, width - 1.0);
+        let top = (self.y * height).clamp(0.0, height - 1.0);
+        let right = ((self.x + self.width) * width).clamp(left + 1.0, width);
+        let bottom = ((self.y + self.height) * height).clamp(top + 1.0, height);
+        PixelRect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    /// 描画先全体を覆うかどうか
+    pub fn is_full(&self) -> bool {
+        self.x <= 0.0 && self.y <= 0.0 && self.x + self.width >= 1.0 && self.y + self.height >= 1.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 物理ピクセル単位の矩形。左上が原点で、Y 軸は下向き。
+pub struct PixelRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PixelRect {
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width / self.height
+    }
+
+    /// スクリーン座標 (物理ピクセル) を正規化デバイス座標の XY に変換する
+    fn screen_to_ndc(&self, screen: PhysicalPosition<f64>) -> (f32, f32) {
+        let x = (screen.x as f32 - self.x) / self.width;
+        let y = (screen.y as f32 - self.y) / self.height;
+        (x.mul_add(2.0, -1.0), y.mul_add(-2.0, 1.0))
+    }
+
+    /// 正規化デバイス座標の XY をスクリーン座標 (物理ピクセル) に変換する
+    fn ndc_to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            ((x + 1.0) * 0.5).mul_add(self.width, self.x),
+            ((1.0 - y) * 0.5).mul_add(self.height, self.y),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// カメラが描画を始める前に、自身の描画範囲をどう消去するか
+pub enum ClearMode {
+    /// [`Scene::skybox`](crate::scene::Scene::skybox) の色と深度を消去する
+    Skybox,
+    /// 指定した色と深度を消去する
+    Color(wgpu::Color),
+    /// 深度だけを消去する。前のカメラの描画結果の上に重ねて描く。
+    DepthOnly,
+    /// 何も消去しない。描画先の最初のカメラであれば、前のフレームの内容が残る。
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// カメラの描画先
+pub enum CameraTarget {
+    /// ウィンドウ
+    #[default]
+    Surface,
+    /// [`TextureRegistry::create_render_target`](crate::texture::TextureRegistry::create_render_target)
+    /// で作ったテクスチャ
+    Texture(TextureIndex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// カメラが描画するレイヤーの集合。32 個のレイヤーを扱える。
+pub struct LayerMask(pub u32);
+
+impl Default for LayerMask {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl LayerMask {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+    /// ゲームオブジェクトが最初に属するレイヤー 0
+    pub const DEFAULT: Self = Self::layer(0);
+
+    /// レイヤー `layer` だけを含む。`layer` は 0 から 31 で、範囲外の場合は [`LayerMask::NONE`] になる。
+    pub const fn layer(layer: u32) -> Self {
+        Self(bit(layer))
+    }
+
+    /// レイヤー `layer` を加えたもの。`layer` が範囲外の場合はそのまま。
+    pub const fn with(self, layer: u32) -> Self {
+        Self(self.0 | bit(layer))
+    }
+
+    /// レイヤー `layer` を除いたもの
+    pub const fn without(self, layer: u32) -> Self {
+        Self(self.0 & !bit(layer))
+    }
+
+    pub const fn contains(self, layer: u32) -> bool {
+        self.0 & bit(layer) != 0
+    }
+
+    /// 共通のレイヤーがあるかどうか
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// レイヤー `layer` のビット。範囲外の場合は 0。
+const fn bit(layer: u32) -> u32 {
+    match 1_u32.checked_shl(layer) {
+        Some(bit) => bit,
+        None => 0,
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 半直線
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Unit<Vector3<f32>>,
+}
+
+impl Ray {
+    /// 始点から `t` だけ進んだ位置
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction.into_inner() * t
+    }
+}
+
+#[derive(Debug, Clone)]
+/// カメラ
+///
+/// ワールド座標系での姿勢 (`transform`) と投影の方法 (`projection`) を別々に持つ。
+/// 投影の方法を切り替えてもカメラの位置と向きは変わらない。
+///
+/// カメラは自身のローカル座標系の +Z 軸方向を向き、+Y 軸方向を上とする。
+/// `transform` の `scale` は無視される。
+pub struct Camera {
+    /// ワールド座標系でのカメラの位置と向き
+    pub transform: TransformComponent,
+    pub projection: Projection,
+    /// `Some` の場合、描画の前にこのゲームオブジェクトのワールド座標系での位置と向きを `transform` にコピーする
+    pub attached_to: Option<GameObjectKey>,
+    pub target: CameraTarget,
+    /// 描画先のうち、このカメラが描画する範囲
+    pub viewport: ViewportRect,
+    pub clear: ClearMode,
+    /// 描画の順番。値の小さいカメラから順に描画する。
+    pub priority: i32,
+    /// このカメラが描画するゲームオブジェクトのレイヤー
+    pub layers: LayerMask,
+    /// 揺れやズームなどの演出。行列を計算するときに適用される。
+    pub effects: CameraEffects,
+}
+
+slotmap::new_key_type! { pub struct CameraKey; }
+
 impl Camera {
+    pub const fn new(transform: TransformComponent, projection: Projection) -> Self {
+        Self {
+            transform,
+            projection,
+            attached_to: None,
+            target: CameraTarget::Surface,
+            viewport: ViewportRect::FULL,
+            clear: ClearMode::Skybox,
+            priority: 0,
+            layers: LayerMask::ALL,
+            effects: CameraEffects::new(),
+        }
+    }
+
+    /// `eye` に置き、`target` の方を向いたカメラを作る
+    pub fn looking_at(
+        eye: &Point3<f32>,
+        target: &Point3<f32>,
+        up: &Vector3<f32>,
+        projection: impl Into<Projection>,
+    ) -> Self {
+        let mut camera = Self::new(
+            TransformComponent::with_translation(Translation3::from(eye.coords)),
+            projection.into(),
+        );
+        camera.look_at(target, up);
+        camera
+    }
+
+    /// 位置を変えずに `target` の方を向く
+    pub fn look_at(&mut self, target: &Point3<f32>, up: &Vector3<f32>) {
+        let dir = target - self.position();
+        self.transform.rotation = UnitQuaternion::face_towards(&dir, up);
+    }
+
+    /// ワールド座標系でのカメラの位置
+    pub fn position(&self) -> Point3<f32> {
+        Point3::from(self.transform.translation.vector)
+    }
+
+    pub fn set_position(&mut self, position: &Point3<f32>) {
+        self.transform.translation = Translation3::from(position.coords);
+    }
+
+    /// カメラが向いている方向 (ワールド座標系)
+    pub fn forward(&self) -> Unit<Vector3<f32>> {
+        self.transform.rotation * Vector3::z_axis()
+    }
+
+    /// カメラの右方向 (ワールド座標系)
+    pub fn right(&self) -> Unit<Vector3<f32>> {
+        self.transform.rotation * Vector3::x_axis()
+    }
+
+    /// カメラの上方向 (ワールド座標系)
+    pub fn up(&self) -> Unit<Vector3<f32>> {
+        self.transform.rotation * Vector3::y_axis()
+    }
+
+    /// ワールド座標系でのカメラの姿勢
+    pub const fn pose(&self) -> Isometry3<f32> {
+        self.transform.to_isometry3()
+    }
+
+    /// [`Camera::effects`] を適用した姿勢
+    pub fn effective_pose(&self) -> Isometry3<f32> {
+        self.effects.apply_to_pose(&self.pose())
+    }
+
+    /// [`Camera::effects`] を適用した投影の方法
+    pub fn effective_projection(&self) -> Projection {
+        self.effects.apply_to_projection(&self.projection)
+    }
+
+    /// ワールド座標系からクリップ座標系への変換行列。[`Camera::effects`] が適用される。
     pub fn get_matrix_world_to_render_coordinate(&self, viewport: &Viewport) -> Matrix4<f32> {
+        self.projection_matrix(viewport) * self.view_matrix()
+    }
+
+    /// ワールド座標系からビュー座標系への変換行列
+    ///
+    /// [`Camera::effects`] が適用される。
+    /// [`Projection::PixelPerfect`] の場合、ビュー座標系での平行移動が仮想解像度のピクセルの格子に揃えられる。
+    /// カメラが回転していても、ワールド座標系の原点は画面のピクセルの格子の上に映る。
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        let mut view = self.effective_pose().inverse();
+        if let Projection::PixelPerfect(projection) = &self.effective_projection() {
+            let unit = projection.pixels_per_unit;
+            let translation = &mut view.translation.vector;
+            translation.x = (translation.x * unit).round() / unit;
+            translation.y = (translation.y * unit).round() / unit;
+        }
+        view.to_homogeneous()
+    }
+
+    /// ビュー座標系からクリップ座標系への変換行列
+    ///
+    /// * `viewport`: 描画先全体の大きさ
+    pub fn projection_matrix(&self, viewport: &Viewport) -> Matrix4<f32> {
+        self.effective_projection()
+            .matrix(self.screen_rect(viewport).aspect_ratio())
+    }
+
+    /// 描画先 `viewport` のうち、このカメラの映像が表示される範囲
+    ///
+    /// [`Projection::PixelPerfect`] の場合は、[`Camera::viewport`] の中で拡大された映像の範囲になる。
+    pub fn screen_rect(&self, viewport: &Viewport) -> PixelRect {
+        let rect = self.viewport.to_pixels(viewport);
+        match &self.projection {
+            Projection::PixelPerfect(projection) => projection.output_rect(&rect),
+            _ => rect,
+        }
+    }
+
+    /// シェーダーから見た描画範囲の大きさ (ピクセル)
+    ///
+    /// [`Projection::PixelPerfect`] の場合は仮想解像度になる。
+    pub fn render_size(&self, viewport: &Viewport) -> (f32, f32) {
+        match &self.projection {
+            Projection::PixelPerfect(projection) => (
+                projection.width.get() as f32,
+                projection.height.get() as f32,
+            ),
+            _ => {
+                let rect = self.screen_rect(viewport);
+                (rect.width, rect.height)
+            }
+        }
+    }
+
+    /// カメラに映る範囲。[`Camera::effects`] が適用される。
+    ///
+    /// * `viewport`: 描画先全体の大きさ
+    pub fn frustum(&self, viewport: &Viewport) -> Frustum {
+        Frustum::from_matrix(&self.get_matrix_world_to_render_coordinate(viewport))
+    }
+
+    /// ワールド座標をスクリーン座標に変換する
+    ///
+    /// 戻り値の XY は [`Frame::mouse_position`](crate::scene::frame::Frame::mouse_position)
+    /// と同じ物理ピクセル単位 (左上原点、Y 軸下向き)、Z は深度 (0.0 から 1.0)。
+    /// カメラの後ろにある点の場合は `None` を返す。
+    pub fn world_to_screen(&self, world: &Point3<f32>, viewport: &Viewport) -> Option<Point3<f32>> {
+        let clip = self.get_matrix_world_to_render_coordinate(viewport) * world.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.xyz() / clip.w;
+        let (x, y) = self.screen_rect(viewport).ndc_to_screen(ndc.x, ndc.y);
+        Some(Point3::new(x, y, ndc.z))
+    }
+
+    /// スクリーン座標 (物理ピクセル) と深度 (0.0 から 1.0) をワールド座標に変換する
+    pub fn screen_to_world(
+        &self,
+        screen: PhysicalPosition<f64>,
+        depth: f32,
+        viewport: &Viewport,
+    ) -> Option<Point3<f32>> {
+        let inverse = self
+            .get_matrix_world_to_render_coordinate(viewport)
+            .try_inverse()?;
+        let (x, y) = self.screen_rect(viewport).screen_to_ndc(screen);
+        Point3::from_homogeneous(inverse * Vector4::new(x, y, depth, 1.0))
+    }
+
+    /// スクリーン座標 (物理ピクセル) を通る半直線をワールド座標系で求める
+    ///
+    /// 始点は近クリップ面上にある。
+    pub fn screen_point_to_ray(
+        &self,
+        screen: PhysicalPosition<f64>,
+        viewport: &Viewport,
+    ) -> Option<Ray> {
+        let near = self.screen_to_world(screen, 0.0, viewport)?;
+        let far = self.screen_to_world(screen, 1.0, viewport)?;
+        Some(Ray {
+            origin: near,
+            direction: Unit::try_new(far - near, f32::EPSILON)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// カメラの投影の方法
+pub enum Projection {
+    Orthographic(OrthographicProjection),
+    Perspective(PerspectiveProjection),
+    PixelPerfect(PixelPerfectProjection),
+}
+
+impl Projection {
+    /// ビュー座標系からクリップ座標系への変換行列
+    ///
+    /// * `aspect_ratio`: 描画範囲の幅 / 高さ
+    pub fn matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
         match self {
-            Self::Orthographic(camera) => camera.get_matrix_world_to_render_coordinate(viewport),
-            Self::Perspective(camera) => camera.get_matrix_world_to_render_coordinate(viewport),
+            Self::Orthographic(projection) => projection.matrix(aspect_ratio),
+            Self::Perspective(projection) => projection.matrix(aspect_ratio),
+            Self::PixelPerfect(projection) => projection.matrix(),
+        }
+    }
+
+    pub const fn z_near(&self) -> f32 {
+        match self {
+            Self::Orthographic(projection) => projection.z_near,
+            Self::Perspective(projection) => projection.z_near,
+            Self::PixelPerfect(projection) => projection.z_near,
+        }
+    }
+
+    pub const fn z_far(&self) -> f32 {
+        match self {
+            Self::Orthographic(projection) => projection.z_far,
+            Self::Perspective(projection) => projection.z_far,
+            Self::PixelPerfect(projection) => projection.z_far,
         }
     }
 }
 
-impl From<OrthographicCamera> for Camera {
-    fn from(value: OrthographicCamera) -> Self {
+impl From<OrthographicProjection> for Projection {
+    fn from(value: OrthographicProjection) -> Self {
         Self::Orthographic(value)
     }
 }
 
-impl From<PerspectiveCamera> for Camera {
-    fn from(value: PerspectiveCamera) -> Self {
+impl From<PerspectiveProjection> for Projection {
+    fn from(value: PerspectiveProjection) -> Self {
         Self::Perspective(value)
     }
 }
 
-#[derive(Debug, Default)]
-pub struct OrthographicCamera {
-    pub eye: Point3<f32>,
-    pub target: Point3<f32>,
-    pub up: Vector3<f32>,
-    // カメラの描画範囲の高さの半分の値
+impl From<PixelPerfectProjection> for Projection {
+    fn from(value: PixelPerfectProjection) -> Self {
+        Self::PixelPerfect(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 正射影
+pub struct OrthographicProjection {
+    /// カメラの描画範囲の高さの半分の値
     pub size: f32,
     pub z_near: f32,
     pub z_far: f32,
 }
 
-impl OrthographicCamera {
-    pub fn get_matrix_world_to_render_coordinate(&self, viewport: &Viewport) -> Matrix4<f32> {
-        let view = Matrix4::look_at_lh(&self.eye, &self.target, &self.up);
-
-        let aspect_ratio = viewport.width.get() as f32 / viewport.height.get() as f32;
+impl OrthographicProjection {
+    pub fn matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
         let half_height = self.size;
         let half_width = half_height * aspect_ratio;
         let left = -half_width;
@@ -62,70 +490,304 @@ impl OrthographicCamera {
         let bottom = -half_height;
         let top = half_height;
 
-        let proj = nalgebra_glm::ortho_lh_zo(left, right, bottom, top, self.z_near, self.z_far);
-
-        proj * view
+        nalgebra_glm::ortho_lh_zo(left, right, bottom, top, self.z_near, self.z_far)
     }
 }
 
-#[derive(Debug)]
-pub struct PerspectiveCamera {
-    pub transform: TransformComponent,
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 透視投影
+pub struct PerspectiveProjection {
+    /// 垂直方向の視野角 (ラジアン)
     pub fov_y_rad: f32,
     pub z_near: f32,
     pub z_far: f32,
 }
 
-impl PerspectiveCamera {
-    pub fn new(
-        eye: &Point3<f32>,
-        target: &Point3<f32>,
-        up: &Vector3<f32>,
-        fov_y_rad: f32,
-        z_near: f32,
-        z_far: f32,
-    ) -> Self {
-        let dir = target - eye;
-        let rotation = UnitQuaternion::look_at_lh(&dir, up);
-        println!("rotation: {}", rotation);
-
-        let translation = rotation * (-eye);
-        println!("translation: {}", translation);
-        let translation = Translation3::from(translation);
-        // これで得られる Transform はあくまでも view 行列を表しており、カメラの位置を表していない。
-        // TODO: カメラの位置を表す Transform を取得する。
-        let transform = TransformComponent::with_translation_and_rotation(translation, rotation);
-        Self {
-            transform,
-            fov_y_rad,
-            z_near,
-            z_far,
-        }
+impl PerspectiveProjection {
+    pub fn matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        nalgebra_glm::perspective_lh_zo(aspect_ratio, self.fov_y_rad, self.z_near, self.z_far)
     }
+}
 
-    pub const fn with_transform(
-        transform: TransformComponent,
-        fov_y_rad: f32,
-        z_near: f32,
-        z_far: f32,
-    ) -> Self {
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 仮想解像度を固定した正射影。ピクセルアート向け。
+///
+/// シーンは仮想解像度のテクスチャに描画され、描画範囲に収まる最大の整数倍に拡大して表示される。
+/// 余った部分は `letterbox_color` で塗られる。
+/// 描画範囲が仮想解像度より小さい場合は、整数倍でない倍率で縮小して表示される。
+pub struct PixelPerfectProjection {
+    /// 仮想解像度の幅
+    pub width: NonZero<u32>,
+    /// 仮想解像度の高さ
+    pub height: NonZero<u32>,
+    /// ワールド座標系の長さ 1 あたりの仮想ピクセル数
+    pub pixels_per_unit: f32,
+    pub z_near: f32,
+    pub z_far: f32,
+    /// 映像の周りの余白の色
+    pub letterbox_color: wgpu::Color,
+}
+
+impl PixelPerfectProjection {
+    pub const fn new(width: NonZero<u32>, height: NonZero<u32>, pixels_per_unit: f32) -> Self {
         Self {
-            transform,
-            fov_y_rad,
-            z_near,
-            z_far,
+            width,
+            height,
+            pixels_per_unit,
+            z_near: 0.1,
+            z_far: 100.0,
+            letterbox_color: wgpu::Color::BLACK,
         }
     }
 
-    pub fn get_matrix_world_to_render_coordinate(&self, viewport: &Viewport) -> Matrix4<f32> {
-        let view = self.transform.to_isometry3().to_homogeneous();
-        let proj = nalgebra_glm::perspective_fov_lh_zo(
-            self.fov_y_rad,
-            viewport.width.get() as f32,
-            viewport.height.get() as f32,
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let half_width = self.width.get() as f32 * 0.5 / self.pixels_per_unit;
+        let half_height = self.height.get() as f32 * 0.5 / self.pixels_per_unit;
+        nalgebra_glm::ortho_lh_zo(
+            -half_width,
+            half_width,
+            -half_height,
+            half_height,
             self.z_near,
             self.z_far,
+        )
+    }
+
+    /// 拡大の倍率。`rect` に収まる最大の整数。`rect` が仮想解像度より小さい場合は 1 未満になる。
+    pub fn scale(&self, rect: &PixelRect) -> f32 {
+        let scale =
+            (rect.width / self.width.get() as f32).min(rect.height / self.height.get() as f32);
+        if scale >= 1.0 { scale.floor() } else { scale }
+    }
+
+    /// `rect` の中央に拡大した映像を置いたときの範囲。左上はピクセルの境界に揃える。
+    pub fn output_rect(&self, rect: &PixelRect) -> PixelRect {
+        let scale = self.scale(rect);
+        let width = self.width.get() as f32 * scale;
+        let height = self.height.get() as f32 * scale;
+        PixelRect {
+            x: rect.x + ((rect.width - width) * 0.5).floor(),
+            y: rect.y + ((rect.height - height) * 0.5).floor(),
+            width,
+            height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport() -> Viewport {
+        Viewport {
+            width: NonZero::new(800).unwrap(),
+            height: NonZero::new(600).unwrap(),
+        }
+    }
+
+    fn approx_eq(a: &Point3<f32>, b: &Point3<f32>) {
+        assert!((a - b).norm() < 1e-3, "{a} != {b}");
+    }
+
+    fn orthographic() -> Camera {
+        Camera::looking_at(
+            &Point3::new(0.0, 0.0, -10.0),
+            &Point3::origin(),
+            &Vector3::y(),
+            OrthographicProjection {
+                size: 3.0,
+                z_near: 0.1,
+                z_far: 100.0,
+            },
+        )
+    }
+
+    fn perspective() -> Camera {
+        let mut camera = orthographic();
+        camera.projection = PerspectiveProjection {
+            fov_y_rad: std::f32::consts::FRAC_PI_2,
+            z_near: 0.1,
+            z_far: 100.0,
+        }
+        .into();
+        camera
+    }
+
+    #[test]
+    fn orthographic_screen_mapping() {
+        let camera = orthographic();
+        let viewport = viewport();
+        // 画面の中心はカメラの注視点
+        let center = camera
+            .world_to_screen(&Point3::origin(), &viewport)
+            .unwrap();
+        approx_eq(&Point3::new(400.0, 300.0, center.z), &center);
+        // 左上の角は (-4, 3)
+        let top_left = camera
+            .screen_to_world(PhysicalPosition::new(0.0, 0.0), center.z, &viewport)
+            .unwrap();
+        approx_eq(&top_left, &Point3::new(-4.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn round_trip() {
+        let viewport = viewport();
+        let world = Point3::new(1.5, -0.5, 2.0);
+        for camera in [orthographic(), perspective()] {
+            let screen = camera.world_to_screen(&world, &viewport).unwrap();
+            let back = camera
+                .screen_to_world(
+                    PhysicalPosition::new(f64::from(screen.x), f64::from(screen.y)),
+                    screen.z,
+                    &viewport,
+                )
+                .unwrap();
+            approx_eq(&back, &world);
+        }
+    }
+
+    #[test]
+    fn ray_from_screen_center() {
+        let viewport = viewport();
+        let center = PhysicalPosition::new(400.0, 300.0);
+        for camera in [orthographic(), perspective()] {
+            let ray = camera.screen_point_to_ray(center, &viewport).unwrap();
+            approx_eq(
+                &Point3::from(ray.direction.into_inner()),
+                &Point3::new(0.0, 0.0, 1.0),
+            );
+            approx_eq(&ray.at(-ray.origin.z), &Point3::origin());
+        }
+    }
+
+    #[test]
+    fn point_behind_perspective_camera() {
+        let camera = perspective();
+        assert!(
+            camera
+                .world_to_screen(&Point3::new(0.0, 0.0, -20.0), &viewport())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn view_matrix_matches_look_at() {
+        let eye = Point3::new(1.0, 2.0, -3.0);
+        let target = Point3::new(0.5, 0.0, 4.0);
+        let camera = Camera::looking_at(&eye, &target, &Vector3::y(), perspective().projection);
+        let expected = Matrix4::look_at_lh(&eye, &target, &Vector3::y());
+        assert!((camera.view_matrix() - expected).norm() < 1e-5);
+        approx_eq(&camera.position(), &eye);
+        let forward = (target - eye).normalize();
+        assert!((camera.forward().into_inner() - forward).norm() < 1e-5);
+    }
+
+    #[test]
+    fn switching_projection_keeps_pose() {
+        let mut camera = orthographic();
+        let position = camera.position();
+        let forward = camera.forward();
+        camera.projection = perspective().projection;
+        assert_eq!(camera.position(), position);
+        assert_eq!(camera.forward(), forward);
+    }
+
+    #[test]
+    fn split_screen_viewport() {
+        let viewport = viewport();
+        let mut camera = orthographic();
+        camera.viewport = ViewportRect::new(0.5, 0.0, 0.5, 1.0);
+        // 右半分の中心が注視点になる
+        let center = camera
+            .world_to_screen(&Point3::origin(), &viewport)
+            .unwrap();
+        approx_eq(&Point3::new(600.0, 300.0, center.z), &center);
+        // 縦長の範囲なので、上端の Y が 3.0 のとき右端の X は 2.0
+        let top_right = camera
+            .screen_to_world(PhysicalPosition::new(800.0, 0.0), center.z, &viewport)
+            .unwrap();
+        approx_eq(&top_right, &Point3::new(2.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn layer_mask() {
+        let mask = LayerMask::DEFAULT.with(3);
+        assert!(mask.contains(0));
+        assert!(mask.contains(3));
+        assert!(!mask.without(3).contains(3));
+        assert!(mask.intersects(LayerMask::layer(3)));
+        assert!(!mask.intersects(LayerMask::layer(4)));
+        assert!(!LayerMask::NONE.intersects(LayerMask::ALL));
+        // 範囲外のレイヤーは含まない
+        assert_eq!(LayerMask::layer(32), LayerMask::NONE);
+        assert_eq!(mask.with(40), mask);
+        assert!(!LayerMask::ALL.contains(32));
+    }
+
+    #[test]
+    fn pixel_perfect_letterbox() {
+        let projection = PixelPerfectProjection::new(
+            NonZero::new(320).unwrap(),
+            NonZero::new(180).unwrap(),
+            16.0,
+        );
+        let full = |width, height| PixelRect {
+            x: 0.0,
+            y: 0.0,
+            width,
+            height,
+        };
+        // 1920x1080 にはちょうど 6 倍で収まる
+        assert_eq!(
+            projection.output_rect(&full(1920.0, 1080.0)),
+            full(1920.0, 1080.0)
+        );
+        // 縦長の画面では上下に余白ができる
+        assert_eq!(
+            projection.output_rect(&full(1000.0, 1000.0)),
+            PixelRect {
+                x: 20.0,
+                y: 230.0,
+                width: 960.0,
+                height: 540.0,
+            }
+        );
+        // 仮想解像度より小さい場合は縮小する
+        assert_eq!(projection.scale(&full(160.0, 180.0)), 0.5);
+    }
+
+    #[test]
+    fn pixel_perfect_camera_snaps_to_pixels() {
+        let projection = PixelPerfectProjection::new(
+            NonZero::new(320).unwrap(),
+            NonZero::new(180).unwrap(),
+            16.0,
+        );
+        let mut camera = Camera::looking_at(
+            &Point3::new(0.01, 0.0, -10.0),
+            &Point3::new(0.01, 0.0, 0.0),
+            &Vector3::y(),
+            projection,
         );
-        proj * view
+        let snapped = camera.view_matrix();
+        camera.set_position(&Point3::new(0.0, 0.0, -10.0));
+        assert!((camera.view_matrix() - snapped).norm() < 1e-6);
+
+        // 回転したカメラでもビュー座標系で格子に揃う
+        let mut rolled = camera.clone();
+        rolled.transform.rotation *= UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.5);
+        rolled.set_position(&Point3::new(0.37, -0.81, -10.0));
+        let origin = rolled.view_matrix() * Point3::origin().to_homogeneous();
+        for pixels in [origin.x * 16.0, origin.y * 16.0] {
+            assert!((pixels - pixels.round()).abs() < 1e-3, "{pixels}");
+        }
+
+        let viewport = Viewport::new(NonZero::new(1000).unwrap(), NonZero::new(1000).unwrap());
+        assert_eq!(camera.render_size(&viewport), (320.0, 180.0));
+        // 画面の中心は拡大された映像の中心
+        let center = camera
+            .world_to_screen(&Point3::origin(), &viewport)
+            .unwrap();
+        approx_eq(&Point3::new(500.0, 500.0, center.z), &center);
     }
 }
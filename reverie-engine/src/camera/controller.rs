@@ -0,0 +1,592 @@
+//! [`Frame`] の入力でカメラを動かすコントローラー
+use std::collections::HashSet;
+
+use nalgebra::{Point2, Point3, UnitQuaternion, Vector2, Vector3};
+use reverie_util::math::{Deg, Rad, calc_front_right_up};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, MouseButton, MouseScrollDelta},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+use super::{Camera, Projection};
+use crate::scene::frame::Frame;
+
+/// 真上や真下を向くと向きが定まらなくなるため、ピッチはこの角度までに制限する
+const MAX_PITCH: Deg<f32> = Deg(89.0);
+
+/// [`MouseScrollDelta::PixelDelta`] を行数に換算するときの 1 行あたりのピクセル数
+const PIXELS_PER_LINE: f64 = 40.0;
+
+/// [`Frame`] の入力でカメラを動かす
+pub trait CameraController {
+    /// フレームごとに呼び、入力に従って `camera` を動かす
+    fn update(&mut self, camera: &mut Camera, frame: &Frame<'_>);
+}
+
+#[derive(Debug, Clone, Default)]
+/// [`Frame`] のイベントから、押されているキーやマウスの移動量を追跡する
+pub struct InputState {
+    keys: HashSet<KeyCode>,
+    buttons: HashSet<MouseButton>,
+    last_mouse_position: Option<PhysicalPosition<f64>>,
+    mouse_delta: Vector2<f32>,
+    scroll_lines: f32,
+}
+
+impl InputState {
+    /// フレームのイベントを反映する。フレームごとに 1 回呼ぶ。
+    pub fn update(&mut self, frame: &Frame<'_>) {
+        for event in frame.key_events {
+            if let PhysicalKey::Code(key) = event.physical_key {
+                match event.state {
+                    ElementState::Pressed => self.keys.insert(key),
+                    ElementState::Released => self.keys.remove(&key),
+                };
+            }
+        }
+        for (state, button, _) in frame.mouse_clicks {
+            match state {
+                ElementState::Pressed => self.buttons.insert(*button),
+                ElementState::Released => self.buttons.remove(button),
+            };
+        }
+
+        let position = frame.mouse_position;
+        let last = self
+            .last_mouse_position
+            .replace(position)
+            .unwrap_or(position);
+        self.mouse_delta = Vector2::new((position.x - last.x) as f32, (position.y - last.y) as f32);
+
+        self.scroll_lines = frame
+            .mouse_wheels
+            .iter()
+            .map(|(delta, ..)| match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                MouseScrollDelta::PixelDelta(p) => (p.y / PIXELS_PER_LINE) as f32,
+            })
+            .sum();
+    }
+
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// 前のフレームからのマウスの移動量 (物理ピクセル、Y 軸下向き)
+    pub const fn mouse_delta(&self) -> Vector2<f32> {
+        self.mouse_delta
+    }
+
+    /// このフレームのホイールの回転量 (行)。奥に回すと正になる。
+    pub const fn scroll_lines(&self) -> f32 {
+        self.scroll_lines
+    }
+
+    fn axis(&self, positive: KeyCode, negative: KeyCode) -> f32 {
+        f32::from(u8::from(self.is_key_pressed(positive)))
+            - f32::from(u8::from(self.is_key_pressed(negative)))
+    }
+}
+
+/// 時定数 `smoothing` 秒で目標に近づくときに、`delta` 秒で縮める差の割合
+fn smoothing_factor(smoothing: f32, delta: f32) -> f32 {
+    if smoothing <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-delta / smoothing).exp()
+    }
+}
+
+fn clamp_pitch(pitch: Rad<f32>) -> Rad<f32> {
+    let max = MAX_PITCH.to_rad().0;
+    Rad(pitch.0.clamp(-max, max))
+}
+
+/// ヨーとピッチから前方向、右方向、上方向を求める
+///
+/// [`calc_front_right_up`] は右手系の右方向を返すため、左手系のこのエンジンに合わせて反転する。
+fn front_right_up(yaw: Rad<f32>, pitch: Rad<f32>) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (front, right, up) = calc_front_right_up(yaw, pitch);
+    (front, -right, up)
+}
+
+/// カメラの前方向からヨーとピッチを求める
+fn yaw_pitch_of(camera: &Camera) -> (Rad<f32>, Rad<f32>) {
+    let front = camera.forward();
+    (
+        Rad(front.x.atan2(front.z)),
+        clamp_pitch(Rad(front.y.clamp(-1.0, 1.0).asin())),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`FlyController`] のキー割り当て
+pub struct FlyKeys {
+    pub forward: KeyCode,
+    pub backward: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub up: KeyCode,
+    pub down: KeyCode,
+    /// 押している間 [`FlyController::fast_multiplier`] 倍の速さで動く
+    pub fast: KeyCode,
+}
+
+impl Default for FlyKeys {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::KeyW,
+            backward: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            up: KeyCode::KeyE,
+            down: KeyCode::KeyQ,
+            fast: KeyCode::ShiftLeft,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// キーボードで移動し、マウスで向きを変える自由移動カメラ
+pub struct FlyController {
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+    /// 1 秒あたりの移動距離
+    pub speed: f32,
+    pub fast_multiplier: f32,
+    /// マウスの 1 ピクセルあたりの回転角 (ラジアン)
+    pub sensitivity: f32,
+    /// 押している間だけ向きを変えるマウスボタン。`None` の場合は常に向きを変える。
+    pub look_button: Option<MouseButton>,
+    pub keys: FlyKeys,
+    input: InputState,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            speed: 5.0,
+            fast_multiplier: 4.0,
+            sensitivity: 0.003,
+            look_button: Some(MouseButton::Right),
+            keys: FlyKeys::default(),
+            input: InputState::default(),
+        }
+    }
+}
+
+impl FlyController {
+    /// カメラの今の向きから始めるコントローラーを作る
+    pub fn from_camera(camera: &Camera) -> Self {
+        let (yaw, pitch) = yaw_pitch_of(camera);
+        Self {
+            yaw,
+            pitch,
+            ..Default::default()
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, frame: &Frame<'_>) {
+        self.input.update(frame);
+
+        if self
+            .look_button
+            .is_none_or(|button| self.input.is_button_pressed(button))
+        {
+            let delta = self.input.mouse_delta() * self.sensitivity;
+            self.yaw += Rad(delta.x);
+            self.pitch = clamp_pitch(self.pitch - Rad(delta.y));
+        }
+
+        let (front, right, up) = front_right_up(self.yaw, self.pitch);
+        let keys = self.keys;
+        let direction = front * self.input.axis(keys.forward, keys.backward)
+            + right * self.input.axis(keys.right, keys.left)
+            + Vector3::y() * self.input.axis(keys.up, keys.down);
+        if let Some(direction) = direction.try_normalize(f32::EPSILON) {
+            let mut speed = self.speed;
+            if self.input.is_key_pressed(keys.fast) {
+                speed *= self.fast_multiplier;
+            }
+            let position = camera.position() + direction * speed * frame.delta_time.as_secs_f32();
+            camera.set_position(&position);
+        }
+        camera.transform.rotation = UnitQuaternion::face_towards(&front, &up);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OrbitState {
+    target: Point3<f32>,
+    distance: f32,
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+}
+
+impl OrbitState {
+    fn approach(&mut self, goal: &Self, t: f32) {
+        self.target += (goal.target - self.target) * t;
+        self.distance += (goal.distance - self.distance) * t;
+        self.yaw += (goal.yaw - self.yaw) * t;
+        self.pitch += (goal.pitch - self.pitch) * t;
+    }
+}
+
+#[derive(Debug, Clone)]
+/// 注視点の周りを回るカメラ。ドラッグで回転と平行移動、ホイールでズームする。
+///
+/// `target`、`distance`、`yaw`、`pitch` は目標値で、カメラは `damping` に従って追いつく。
+pub struct OrbitController {
+    /// 注視点
+    pub target: Point3<f32>,
+    /// 注視点からカメラまでの距離
+    pub distance: f32,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub rotate_button: MouseButton,
+    pub pan_button: MouseButton,
+    /// マウスの 1 ピクセルあたりの回転角 (ラジアン)
+    pub rotate_sensitivity: f32,
+    /// マウスの 1 ピクセルあたりの平行移動量。注視点までの距離に比例する。
+    pub pan_sensitivity: f32,
+    /// ホイールの 1 行あたりに距離を縮める割合
+    pub zoom_sensitivity: f32,
+    /// 目標値に追いつくまでの時定数 (秒)。0 の場合はすぐに追いつく。
+    pub damping: f32,
+    current: Option<OrbitState>,
+    input: InputState,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            target: Point3::origin(),
+            distance: 5.0,
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            rotate_button: MouseButton::Left,
+            pan_button: MouseButton::Right,
+            rotate_sensitivity: 0.005,
+            pan_sensitivity: 0.002,
+            zoom_sensitivity: 0.1,
+            damping: 0.1,
+            current: None,
+            input: InputState::default(),
+        }
+    }
+}
+
+impl OrbitController {
+    /// カメラの今の位置と向きから、距離 `distance` 先の点を注視点とするコントローラーを作る
+    pub fn from_camera(camera: &Camera, distance: f32) -> Self {
+        let (yaw, pitch) = yaw_pitch_of(camera);
+        Self {
+            target: camera.position() + camera.forward().into_inner() * distance,
+            distance,
+            yaw,
+            pitch,
+            ..Default::default()
+        }
+    }
+
+    const fn goal(&self) -> OrbitState {
+        OrbitState {
+            target: self.target,
+            distance: self.distance,
+            yaw: self.yaw,
+            pitch: self.pitch,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, frame: &Frame<'_>) {
+        self.input.update(frame);
+        let delta = self.input.mouse_delta();
+
+        if self.input.is_button_pressed(self.rotate_button) {
+            self.yaw += Rad(delta.x * self.rotate_sensitivity);
+            self.pitch = clamp_pitch(self.pitch + Rad(delta.y * self.rotate_sensitivity));
+        } else if self.input.is_button_pressed(self.pan_button) {
+            let (_, right, up) = front_right_up(self.yaw, self.pitch);
+            let scale = self.pan_sensitivity * self.distance;
+            self.target += (up * delta.y - right * delta.x) * scale;
+        }
+        let zoom = (1.0 - self.zoom_sensitivity).powf(self.input.scroll_lines());
+        self.distance = (self.distance * zoom).clamp(self.min_distance, self.max_distance);
+
+        let goal = self.goal();
+        let current = self.current.get_or_insert(goal);
+        current.approach(
+            &goal,
+            smoothing_factor(self.damping, frame.delta_time.as_secs_f32()),
+        );
+
+        let (front, _, up) = front_right_up(current.yaw, current.pitch);
+        camera.set_position(&(current.target - front * current.distance));
+        camera.transform.rotation = UnitQuaternion::face_towards(&front, &up);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// XY 平面上の矩形の範囲
+pub struct Bounds2 {
+    pub min: Point2<f32>,
+    pub max: Point2<f32>,
+}
+
+#[derive(Debug, Clone)]
+/// XY 平面上の目標を追いかける 2D カメラ
+///
+/// カメラは +Z 方向を向いているものとし、位置の XY だけを動かす。
+/// ゲームは毎フレーム `target` に追いかける対象の位置を設定する。
+pub struct Follow2DController {
+    /// 追いかける対象の位置
+    pub target: Point2<f32>,
+    /// 画面の中心からこの範囲 (半分の大きさ) に対象がある間はカメラを動かさない
+    pub dead_zone: Vector2<f32>,
+    /// 対象の速度にこの秒数を掛けた分だけ先を映す
+    pub look_ahead: f32,
+    /// 目標位置に追いつくまでの時定数 (秒)。0 の場合はすぐに追いつく。
+    pub smoothing: f32,
+    /// 画面がこの範囲の外を映さないようにカメラの位置を制限する
+    pub bounds: Option<Bounds2>,
+    last_target: Option<Point2<f32>>,
+}
+
+impl Follow2DController {
+    pub const fn new(target: Point2<f32>) -> Self {
+        Self {
+            target,
+            dead_zone: Vector2::new(0.0, 0.0),
+            look_ahead: 0.0,
+            smoothing: 0.15,
+            bounds: None,
+            last_target: None,
+        }
+    }
+
+    /// 画面に映る範囲の半分の大きさ (ワールド座標系)
+    fn visible_half_extents(camera: &Camera, frame: &Frame<'_>) -> Vector2<f32> {
+        let aspect_ratio = camera.screen_rect(&frame.viewport).aspect_ratio();
+        let half_height = match &camera.projection {
+            Projection::Orthographic(projection) => projection.size,
+            Projection::Perspective(projection) => {
+                camera.position().z.abs() * (projection.fov_y_rad * 0.5).tan()
+            }
+            Projection::PixelPerfect(projection) => {
+                projection.height.get() as f32 * 0.5 / projection.pixels_per_unit
+            }
+        };
+        Vector2::new(half_height * aspect_ratio, half_height)
+    }
+}
+
+impl CameraController for Follow2DController {
+    fn update(&mut self, camera: &mut Camera, frame: &Frame<'_>) {
+        let delta = frame.delta_time.as_secs_f32();
+        let last_target = self.last_target.replace(self.target).unwrap_or(self.target);
+        let velocity = if delta > 0.0 {
+            (self.target - last_target) / delta
+        } else {
+            Vector2::zeros()
+        };
+
+        let position = camera.position();
+        let center = position.xy();
+        let offset = (self.target + velocity * self.look_ahead) - center;
+        // 対象が不感帯からはみ出した分だけ動かす
+        let outside = |offset: f32, dead_zone: f32| {
+            offset.signum() * (offset.abs() - dead_zone.max(0.0)).max(0.0)
+        };
+        let step = Vector2::new(
+            outside(offset.x, self.dead_zone.x),
+            outside(offset.y, self.dead_zone.y),
+        );
+        let mut next = center + step * smoothing_factor(self.smoothing, delta);
+
+        if let Some(bounds) = self.bounds {
+            let half = Self::visible_half_extents(camera, frame);
+            let clamp = |value: f32, min: f32, max: f32, half: f32| {
+                if max - min <= half * 2.0 {
+                    (min + max) * 0.5
+                } else {
+                    value.clamp(min + half, max - half)
+                }
+            };
+            next.x = clamp(next.x, bounds.min.x, bounds.max.x, half.x);
+            next.y = clamp(next.y, bounds.min.y, bounds.max.y, half.y);
+        }
+
+        camera.set_position(&Point3::new(next.x, next.y, position.z));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{
+        camera::{OrthographicProjection, PerspectiveProjection},
+        scene::frame::{InputBuffer, KeyInput},
+        stats::FrameStats,
+    };
+
+    const DT: Duration = Duration::from_millis(100);
+
+    fn camera(projection: impl Into<Projection>) -> Camera {
+        Camera::looking_at(
+            &Point3::new(0.0, 0.0, -10.0),
+            &Point3::origin(),
+            &Vector3::y(),
+            projection,
+        )
+    }
+
+    fn perspective() -> Camera {
+        camera(PerspectiveProjection {
+            fov_y_rad: std::f32::consts::FRAC_PI_2,
+            z_near: 0.1,
+            z_far: 100.0,
+        })
+    }
+
+    fn step(controller: &mut impl CameraController, camera: &mut Camera, input: &mut InputBuffer) {
+        let stats = FrameStats::default();
+        controller.update(camera, &input.frame(Instant::now(), DT, &stats));
+        input.clear();
+    }
+
+    fn approx_eq(a: &Point3<f32>, b: &Point3<f32>) {
+        assert!((a - b).norm() < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn fly_moves_along_view_direction() {
+        let mut camera = perspective();
+        let mut controller = FlyController::from_camera(&camera);
+        let mut input = InputBuffer::default();
+
+        input.key_events.push(KeyInput::from_key_code(
+            KeyCode::KeyW,
+            ElementState::Pressed,
+        ));
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(0.0, 0.0, -9.5));
+
+        // 押し続けている間は動き続ける
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(0.0, 0.0, -9.0));
+
+        input.key_events.push(KeyInput::from_key_code(
+            KeyCode::KeyW,
+            ElementState::Released,
+        ));
+        input.key_events.push(KeyInput::from_key_code(
+            KeyCode::KeyD,
+            ElementState::Pressed,
+        ));
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(0.5, 0.0, -9.0));
+        assert!((camera.right().into_inner() - Vector3::x()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn fly_looks_with_mouse_while_button_held() {
+        let mut camera = perspective();
+        let mut controller = FlyController::from_camera(&camera);
+        let mut input = InputBuffer::default();
+        step(&mut controller, &mut camera, &mut input);
+
+        input.mouse_position = PhysicalPosition::new(100.0, 0.0);
+        step(&mut controller, &mut camera, &mut input);
+        assert_eq!(controller.yaw, Rad(0.0));
+
+        input.mouse_clicks.push((
+            ElementState::Pressed,
+            MouseButton::Right,
+            input.mouse_position,
+        ));
+        input.mouse_position = PhysicalPosition::new(200.0, 0.0);
+        step(&mut controller, &mut camera, &mut input);
+        assert!(controller.yaw.0 > 0.0);
+        // 右を向く
+        assert!(camera.forward().x > 0.0);
+    }
+
+    #[test]
+    fn orbit_zoom_is_clamped_and_damped() {
+        let mut camera = perspective();
+        let mut controller = OrbitController::from_camera(&camera, 10.0);
+        controller.min_distance = 4.0;
+        controller.damping = 0.0;
+        let mut input = InputBuffer::default();
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(0.0, 0.0, -10.0));
+
+        input.mouse_wheels.push((
+            MouseScrollDelta::LineDelta(0.0, 100.0),
+            winit::event::TouchPhase::Moved,
+            input.mouse_position,
+        ));
+        controller.damping = 1.0;
+        step(&mut controller, &mut camera, &mut input);
+        assert_eq!(controller.distance, 4.0);
+        // 減衰があるので、まだ目標の距離に届いていない
+        let distance = (camera.position() - Point3::origin()).norm();
+        assert!(distance < 10.0 && distance > 4.0, "{distance}");
+
+        controller.damping = 0.0;
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(0.0, 0.0, -4.0));
+    }
+
+    #[test]
+    fn follow_respects_dead_zone_and_bounds() {
+        let mut camera = camera(OrthographicProjection {
+            size: 3.0,
+            z_near: 0.1,
+            z_far: 100.0,
+        });
+        let mut controller = Follow2DController::new(Point2::origin());
+        controller.smoothing = 0.0;
+        controller.dead_zone = Vector2::new(1.0, 1.0);
+        let mut input = InputBuffer::default();
+        // 描画範囲は 4:3 なので、画面に映る範囲の半分の大きさは (4, 3)
+        input.viewport = crate::camera::Viewport::new(
+            std::num::NonZeroU32::new(800).unwrap(),
+            std::num::NonZeroU32::new(600).unwrap(),
+        );
+
+        controller.target = Point2::new(0.5, 0.0);
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(0.0, 0.0, -10.0));
+
+        controller.target = Point2::new(3.0, 0.0);
+        step(&mut controller, &mut camera, &mut input);
+        approx_eq(&camera.position(), &Point3::new(2.0, 0.0, -10.0));
+
+        controller.bounds = Some(Bounds2 {
+            min: Point2::new(-10.0, -2.0),
+            max: Point2::new(5.0, 2.0),
+        });
+        controller.target = Point2::new(20.0, 0.0);
+        step(&mut controller, &mut camera, &mut input);
+        // 右端は 5 - 4、範囲の高さは画面より小さいので中央に置く
+        approx_eq(&camera.position(), &Point3::new(1.0, 0.0, -10.0));
+    }
+}
@@ -0,0 +1,369 @@
+//! カメラの揺れやズームなどの演出
+//!
+//! 演出はカメラの姿勢 ([`Camera::transform`](super::Camera::transform)) と投影の方法を書き換えず、
+//! 行列を計算するときにだけ適用される。コントローラーは演出の影響を受けない姿勢を動かせばよい。
+use std::time::Duration;
+
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector2, Vector3};
+use reverie_util::interpolation::{Interpolation, types::Time};
+
+use super::Projection;
+use crate::time::interpolation_time_span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 画面の揺れの設定
+///
+/// 揺れの強さはトラウマ (0.0 から 1.0) の 2 乗に比例する。トラウマは時間とともに線形に減る。
+pub struct ShakeSettings {
+    /// トラウマが 1.0 のときのカメラのローカル座標系での最大のずれ
+    pub max_offset: Vector2<f32>,
+    /// トラウマが 1.0 のときの最大の傾き (ラジアン)
+    pub max_roll_rad: f32,
+    /// 揺れの速さ (Hz)
+    pub frequency: f32,
+    /// 1 秒あたりに減るトラウマ
+    pub decay: f32,
+}
+
+impl ShakeSettings {
+    pub const fn new() -> Self {
+        Self {
+            max_offset: Vector2::new(0.3, 0.3),
+            max_roll_rad: 0.05,
+            frequency: 15.0,
+            decay: 1.0,
+        }
+    }
+}
+
+impl Default for ShakeSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// 一定の時間で値が変化する区間
+///
+/// 補間は区間の始まりからの時間で行い、始まりの時刻を後から動かせるようにする。
+struct Segment {
+    value: Interpolation<f32>,
+    start: Time,
+    span: Time,
+}
+
+impl Segment {
+    fn new(begin: f32, end: f32, now: Time, duration: Duration) -> Self {
+        let span = interpolation_time_span(duration).max(1);
+        Self {
+            value: Interpolation::new_cubic_ease_in_out(begin, end, 0, span),
+            start: now,
+            span: i64::from(span),
+        }
+    }
+
+    fn lerp(begin: f32, end: f32, now: Time, duration: Duration) -> Self {
+        let span = interpolation_time_span(duration).max(1);
+        Self {
+            value: Interpolation::new_lerp(begin, end, 0, span),
+            start: now,
+            span: i64::from(span),
+        }
+    }
+
+    fn value(&self, now: Time) -> f32 {
+        self.value.value(now - self.start)
+    }
+
+    const fn end(&self) -> Time {
+        self.start + self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+/// 別の姿勢からカメラの姿勢への遷移
+struct Blend {
+    from: Isometry3<f32>,
+    weight: Segment,
+}
+
+#[derive(Debug, Clone)]
+/// カメラに掛ける演出
+///
+/// [`Scene::update`](crate::scene::Scene::update) がゲームの時間で [`CameraEffects::update`] を呼ぶため、
+/// ゲームを一時停止すると演出も止まる。
+/// 最初の [`CameraEffects::update`] より前に始めた演出は、その時刻に始めたものとして扱う。
+pub struct CameraEffects {
+    pub shake: ShakeSettings,
+    trauma: Option<Segment>,
+    zoom: Vec<Segment>,
+    blend: Option<Blend>,
+    /// 最後の [`CameraEffects::update`] の時刻。まだ呼ばれていない場合は `None`。
+    now: Option<Time>,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraEffects {
+    pub const fn new() -> Self {
+        Self {
+            shake: ShakeSettings::new(),
+            trauma: None,
+            zoom: Vec::new(),
+            blend: None,
+            now: None,
+        }
+    }
+
+    /// 時刻を `now` (ミリ秒) に進め、終わった演出を取り除く
+    pub fn update(&mut self, now: Time) {
+        if self.now.is_none() {
+            // 時刻を知る前に始めた演出は 0 から始めているので、`now` から始めたことにする
+            let segments = self
+                .trauma
+                .iter_mut()
+                .chain(&mut self.zoom)
+                .chain(self.blend.as_mut().map(|blend| &mut blend.weight));
+            for segment in segments {
+                segment.start += now;
+            }
+        }
+        self.now = Some(now);
+        if self.trauma.as_ref().is_some_and(|s| s.end() <= now) {
+            self.trauma = None;
+        }
+        // 最後の区間は終わった後もその値を保つ
+        while self.zoom.len() > 1 && self.zoom[0].end() <= now {
+            self.zoom.remove(0);
+        }
+        if self
+            .zoom
+            .last()
+            .is_some_and(|s| s.end() <= now && s.value(now) == 1.0)
+        {
+            self.zoom.clear();
+        }
+        if self.blend.as_ref().is_some_and(|b| b.weight.end() <= now) {
+            self.blend = None;
+        }
+    }
+
+    /// 演出の時刻。[`CameraEffects::update`] の前は 0 とする。
+    fn now(&self) -> Time {
+        self.now.unwrap_or(0)
+    }
+
+    /// 現在のトラウマ (0.0 から 1.0)
+    pub fn trauma(&self) -> f32 {
+        self.trauma.as_ref().map_or(0.0, |s| s.value(self.now()))
+    }
+
+    /// トラウマを `amount` だけ増やして画面を揺らす。トラウマは 1.0 を超えない。
+    pub fn add_trauma(&mut self, amount: f32) {
+        let trauma = (self.trauma() + amount).clamp(0.0, 1.0);
+        let duration = Duration::from_secs_f32(trauma / self.shake.decay.max(f32::EPSILON));
+        self.trauma = Some(Segment::lerp(trauma, 0.0, self.now(), duration));
+    }
+
+    /// 現在のズームの倍率。1.0 より大きいと拡大される。
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+            .iter()
+            .find(|s| self.now() < s.end())
+            .or_else(|| self.zoom.last())
+            .map_or(1.0, |s| s.value(self.now()))
+    }
+
+    /// `duration` をかけてズームの倍率を `factor` に変え、その後も保つ
+    pub fn zoom_to(&mut self, factor: f32, duration: Duration) {
+        self.zoom = vec![Segment::new(self.zoom(), factor, self.now(), duration)];
+    }
+
+    /// `duration` の前半でズームの倍率を `factor` に変え、後半で元に戻す
+    pub fn zoom_punch(&mut self, factor: f32, duration: Duration) {
+        let current = self.zoom();
+        let first = Segment::new(current, factor, self.now(), duration / 2);
+        let second = Segment::new(factor, current, first.end(), duration / 2);
+        self.zoom = vec![first, second];
+    }
+
+    /// `duration` をかけて、姿勢 `from` からカメラの姿勢へなめらかに移る
+    ///
+    /// カメラを切り替えるときは、切り替える前の姿勢を `from` に渡してからカメラの姿勢を変える。
+    pub fn blend_from(&mut self, from: Isometry3<f32>, duration: Duration) {
+        self.blend = Some(Blend {
+            from,
+            weight: Segment::new(0.0, 1.0, self.now(), duration),
+        });
+    }
+
+    /// 遷移中かどうか
+    pub const fn is_blending(&self) -> bool {
+        self.blend.is_some()
+    }
+
+    /// 演出を掛けた姿勢
+    pub fn apply_to_pose(&self, pose: &Isometry3<f32>) -> Isometry3<f32> {
+        let mut pose = self.blend.as_ref().map_or(*pose, |blend| {
+            blend.from.lerp_slerp(pose, blend.weight.value(self.now()))
+        });
+        let trauma = self.trauma();
+        if trauma > 0.0 {
+            let intensity = trauma * trauma;
+            let t = self.now() as f32 / 1000.0 * self.shake.frequency;
+            let offset = Vector3::new(
+                self.shake.max_offset.x * intensity * noise(0, t),
+                self.shake.max_offset.y * intensity * noise(1, t),
+                0.0,
+            );
+            let roll = UnitQuaternion::from_axis_angle(
+                &Vector3::z_axis(),
+                self.shake.max_roll_rad * intensity * noise(2, t),
+            );
+            pose *= Isometry3::from_parts(Translation3::from(offset), roll);
+        }
+        pose
+    }
+
+    /// 演出を掛けた投影の方法
+    pub fn apply_to_projection(&self, projection: &Projection) -> Projection {
+        let zoom = self.zoom();
+        if zoom == 1.0 || zoom <= 0.0 {
+            return *projection;
+        }
+        let mut projection = *projection;
+        match &mut projection {
+            Projection::Orthographic(p) => p.size /= zoom,
+            Projection::Perspective(p) => {
+                p.fov_y_rad = 2.0 * ((p.fov_y_rad * 0.5).tan() / zoom).atan()
+            }
+            Projection::PixelPerfect(p) => p.pixels_per_unit *= zoom,
+        }
+        projection
+    }
+}
+
+/// 整数をハッシュして -1.0 から 1.0 の値にする
+fn hash(seed: u32, i: i64) -> f32 {
+    let mut x = (i as u32) ^ seed.wrapping_mul(0x9E37_79B9);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    (x as f32 / u32::MAX as f32).mul_add(2.0, -1.0)
+}
+
+/// なめらかに変化する -1.0 から 1.0 の値 (バリューノイズ)
+fn noise(seed: u32, t: f32) -> f32 {
+    let i = t.floor();
+    let f = t - i;
+    let s = f * f * 2.0f32.mul_add(-f, 3.0);
+    let a = hash(seed, i as i64);
+    let b = hash(seed, i as i64 + 1);
+    (b - a).mul_add(s, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::OrthographicProjection;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn trauma_decays_and_shake_stops() {
+        let mut effects = CameraEffects::new();
+        effects.update(0);
+        effects.shake.decay = 2.0;
+        effects.add_trauma(0.5);
+        effects.add_trauma(0.7);
+        assert_eq!(effects.trauma(), 1.0);
+
+        effects.update(250);
+        assert!((effects.trauma() - 0.5).abs() < 1e-3);
+        let shaken = effects.apply_to_pose(&Isometry3::identity());
+        assert_ne!(shaken, Isometry3::identity());
+
+        effects.update(500);
+        assert_eq!(effects.trauma(), 0.0);
+        assert_eq!(
+            effects.apply_to_pose(&Isometry3::identity()),
+            Isometry3::identity()
+        );
+    }
+
+    #[test]
+    fn zoom_punch_returns_to_previous_zoom() {
+        let mut effects = CameraEffects::new();
+        effects.update(0);
+        effects.zoom_to(2.0, ms(100));
+        effects.update(50);
+        assert!((effects.zoom() - 1.5).abs() < 1e-3);
+        effects.update(100);
+        assert_eq!(effects.zoom(), 2.0);
+
+        effects.zoom_punch(4.0, ms(200));
+        effects.update(200);
+        assert_eq!(effects.zoom(), 4.0);
+        effects.update(300);
+        assert_eq!(effects.zoom(), 2.0);
+
+        let projection = Projection::Orthographic(OrthographicProjection {
+            size: 3.0,
+            z_near: 0.1,
+            z_far: 100.0,
+        });
+        let Projection::Orthographic(zoomed) = effects.apply_to_projection(&projection) else {
+            unreachable!();
+        };
+        assert_eq!(zoomed.size, 1.5);
+    }
+
+    #[test]
+    fn blend_moves_between_poses() {
+        let mut effects = CameraEffects::new();
+        effects.update(0);
+        let from = Isometry3::translation(0.0, 0.0, 0.0);
+        let to = Isometry3::translation(10.0, 0.0, 0.0);
+        effects.blend_from(from, ms(100));
+        assert_eq!(effects.apply_to_pose(&to), from);
+
+        effects.update(50);
+        let middle = effects.apply_to_pose(&to);
+        assert!((middle.translation.x - 5.0).abs() < 1e-3);
+
+        effects.update(100);
+        assert!(!effects.is_blending());
+        assert_eq!(effects.apply_to_pose(&to), to);
+    }
+
+    #[test]
+    fn effects_started_before_first_update_begin_then() {
+        // ゲームの途中で作ったカメラは、最初の更新でゲームの時刻を知る
+        let mut effects = CameraEffects::new();
+        let from = Isometry3::translation(0.0, 0.0, 0.0);
+        let to = Isometry3::translation(10.0, 0.0, 0.0);
+        effects.blend_from(from, ms(100));
+        effects.zoom_to(2.0, ms(100));
+
+        effects.update(5000);
+        assert!(effects.is_blending());
+        assert_eq!(effects.apply_to_pose(&to), from);
+        assert_eq!(effects.zoom(), 1.0);
+
+        effects.update(5050);
+        assert!((effects.apply_to_pose(&to).translation.x - 5.0).abs() < 1e-3);
+        assert!((effects.zoom() - 1.5).abs() < 1e-3);
+
+        effects.update(5100);
+        assert!(!effects.is_blending());
+        assert_eq!(effects.zoom(), 2.0);
+    }
+}
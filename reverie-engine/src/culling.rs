@@ -0,0 +1,150 @@
+//! 視錐台カリングに関するモジュール
+use nalgebra::{Matrix4, Point3, Vector4};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 座標軸に平行な直方体 (Axis-Aligned Bounding Box)
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub const fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// すべての点を含む最小の直方体。点がない場合は `None` を返す。
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Point3<f32>>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(*first, *first), |aabb, p| Self {
+            min: aabb.min.inf(p),
+            max: aabb.max.sup(p),
+        }))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    /// 8 つの頂点
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// `matrix` で変換した直方体を含む直方体
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        let corners = self
+            .corners()
+            .map(|p| Point3::from_homogeneous(matrix * p.to_homogeneous()).unwrap_or(p));
+        Self::from_points(&corners).unwrap_or(*self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 視錐台。カメラに映る範囲を 6 つの平面で表す。
+///
+/// 平面 `(a, b, c, d)` は `a x + b y + c z + d >= 0` の側が内側になる。
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// ワールド座標系からクリップ座標系への変換行列から視錐台を求める
+    ///
+    /// クリップ座標系の深度は 0.0 から 1.0 とする。
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let row = |i: usize| matrix.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let norm = plane.xyz().norm();
+            if norm > 0.0 { plane / norm } else { plane }
+        });
+        Self { planes }
+    }
+
+    pub fn contains_point(&self, point: &Point3<f32>) -> bool {
+        let p = point.to_homogeneous();
+        self.planes.iter().all(|plane| plane.dot(&p) >= 0.0)
+    }
+
+    /// 直方体の一部でも視錐台の中にあるかどうか。
+    ///
+    /// 保守的な判定で、視錐台の角の近くにある直方体は外にあっても `true` になることがある。
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // 平面の法線の方向に最も進んだ頂点
+            let pick = |n: f32, min: f32, max: f32| if n >= 0.0 { max } else { min };
+            let p = Point3::new(
+                pick(plane.x, aabb.min.x, aabb.max.x),
+                pick(plane.y, aabb.min.y, aabb.max.y),
+                pick(plane.z, aabb.min.z, aabb.max.z),
+            );
+            plane.dot(&p.to_homogeneous()) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    fn frustum() -> Frustum {
+        let projection = nalgebra_glm::perspective_lh_zo(1.0, 90.0_f32.to_radians(), 0.1, 100.0);
+        let view = Matrix4::look_at_lh(
+            &Point3::origin(),
+            &Point3::new(0.0, 0.0, 1.0),
+            &Vector3::y(),
+        );
+        Frustum::from_matrix(&(projection * view))
+    }
+
+    fn cube(center: Point3<f32>) -> Aabb {
+        let half = Vector3::repeat(0.5);
+        Aabb::new(center - half, center + half)
+    }
+
+    #[test]
+    fn points_in_frustum() {
+        let frustum = frustum();
+        assert!(frustum.contains_point(&Point3::new(0.0, 0.0, 10.0)));
+        assert!(frustum.contains_point(&Point3::new(9.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(&Point3::new(11.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(&Point3::new(0.0, 0.0, -1.0)));
+        assert!(!frustum.contains_point(&Point3::new(0.0, 0.0, 200.0)));
+    }
+
+    #[test]
+    fn aabb_culling() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, 10.0))));
+        // 一部だけ中にある
+        assert!(frustum.intersects_aabb(&cube(Point3::new(10.3, 0.0, 10.0))));
+        assert!(!frustum.intersects_aabb(&cube(Point3::new(20.0, 0.0, 10.0))));
+        assert!(!frustum.intersects_aabb(&cube(Point3::new(0.0, 0.0, -5.0))));
+    }
+
+    #[test]
+    fn transformed_aabb() {
+        let aabb = cube(Point3::origin());
+        let rotated = aabb.transformed(
+            &(Matrix4::new_translation(&Vector3::new(1.0, 0.0, 0.0))
+                * Matrix4::from_euler_angles(0.0, 0.0, 45.0_f32.to_radians())),
+        );
+        let half = 0.5 * 2.0_f32.sqrt();
+        assert!((rotated.max - Point3::new(1.0 + half, half, 0.5)).norm() < 1e-5);
+        assert!((rotated.center() - Point3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+    }
+}
@@ -1,5 +1,6 @@
 //! Game トレイト
 use crate::{
+    pacing::EngineConfig,
     scene::{Scene, frame::Frame},
     window::App,
 };
@@ -19,13 +20,27 @@ pub trait Game {
     fn update<'a>(&mut self, frame: &'a Frame<'a>);
 }
 
+/// 1 フレーム分ゲームの状態を進める。
+///
+/// [`Scene::begin_frame`]、[`Game::update`]、[`Scene::update`] の順に呼ぶ。
+pub fn step_frame<G: Game>(game: &mut G, frame: &Frame<'_>) {
+    game.get_scene_mut_for_rendering().begin_frame(frame);
+    game.update(frame);
+    game.get_scene_mut_for_rendering().update(frame);
+}
+
+/// デフォルトの設定でエンジンを起動する
 pub fn start_engine<G: Game>(game: G) -> anyhow::Result<()> {
+    start_engine_with_config(game, EngineConfig::default())
+}
+
+/// 設定を指定してエンジンを起動する
+pub fn start_engine_with_config<G: Game>(game: G, config: EngineConfig) -> anyhow::Result<()> {
     use anyhow::Context;
 
     let event_loop = winit::event_loop::EventLoop::new().context("failed: create event loop")?;
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
-    event_loop
-        .run_app(&mut App::new(game))
-        .context("failed: run app")?;
+    let app = &mut App::new(game, config);
+    event_loop.set_control_flow(app.control_flow());
+    event_loop.run_app(app).context("failed: run app")?;
     Ok(())
 }
@@ -3,12 +3,19 @@
 #![deny(clippy::nursery)]
 
 pub mod camera;
+pub mod culling;
 mod game;
 pub mod model;
+pub mod pacing;
 pub mod render;
 pub mod scene;
+pub mod shader;
+pub mod stats;
+pub mod testing;
 pub mod texture;
+pub mod time;
 mod window;
 
 pub use game::Game;
 pub use game::start_engine;
+pub use game::start_engine_with_config;
@@ -1,26 +1,269 @@
-use crate::texture::TextureId;
+use nalgebra::Point3;
+
+use crate::{culling::Aabb, shader::ShaderKey, texture::TextureId};
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub uv: [f32; 2],
     pub normal: [f32; 3],
+    /// テクスチャの色に掛ける色 (RGBA)
+    pub color: [f32; 4],
 }
 
+/// メッシュ
+///
+/// `vertices` や `indices` を直接書き換えた場合は [`Mesh::update_bounds`] を呼ぶ。
+/// 呼ぶまでは GPU に送信し直されない。
 pub struct Mesh {
     pub name: String,
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub vertex_count: u32,
     pub index_count: u32,
+    /// モデル座標系での頂点を囲む直方体。頂点がない場合は `None`。
+    pub bounds: Option<Aabb>,
+    /// 頂点やインデックスを書き換えるたびに増える番号
+    revision: u64,
+}
+
+impl Mesh {
+    pub fn new(name: String, vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        let vertex_count = vertices.len() as u32;
+        let index_count = indices.len() as u32;
+        let mut mesh = Self {
+            name,
+            vertices,
+            indices,
+            vertex_count,
+            index_count,
+            bounds: None,
+            revision: 0,
+        };
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// 頂点とインデックスを置き換える
+    pub fn set_geometry(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>) {
+        self.vertex_count = vertices.len() as u32;
+        self.index_count = indices.len() as u32;
+        self.vertices = vertices;
+        self.indices = indices;
+        self.update_bounds();
+    }
+
+    /// 頂点やインデックスを書き換えた後に [`Mesh::bounds`] を計算し直し、GPU に送信し直すようにする
+    pub fn update_bounds(&mut self) {
+        let points: Vec<_> = self
+            .vertices
+            .iter()
+            .map(|v| Point3::from(v.position))
+            .collect();
+        self.bounds = Aabb::from_points(&points);
+        self.revision += 1;
+    }
+
+    /// 頂点やインデックスを書き換えるたびに増える番号
+    pub const fn revision(&self) -> u64 {
+        self.revision
+    }
 }
 
 slotmap::new_key_type! { pub struct MeshKey; }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+/// 描画した色と描画先の色の合成方法
+///
+/// [`BlendMode::Opaque`] は深度を書き込み、手前から奥の順に描画される。アルファが 0.5 未満のピクセルは捨てられる。
+/// それ以外は深度を書き込まず、不透明なものの後に、メッシュやスプライトの種類をまたいで奥から手前の順に描画される。
+pub enum BlendMode {
+    Opaque,
+    /// アルファで描画先の色と混ぜる
+    #[default]
+    Alpha,
+    /// 描画先の色に足す。光やパーティクルに使う。
+    Additive,
+    /// 描画先の色に掛ける。影に使う。透明な部分は白にする。
+    Multiply,
+    /// アルファを掛け済みの色を混ぜる
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub const ALL: [Self; 5] = [
+        Self::Opaque,
+        Self::Alpha,
+        Self::Additive,
+        Self::Multiply,
+        Self::Premultiplied,
+    ];
+
+    pub const fn is_opaque(self) -> bool {
+        matches!(self, Self::Opaque)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// マテリアルのシェーダーに渡すパラメータ
+///
+/// テクスチャ以外は [`Material::params`] の順にユニフォームの構造体のメンバーとして WGSL のレイアウトで並べる。
+pub enum MaterialParam {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    /// RGBA の色。WGSL では `vec4<f32>` になる。
+    Color([f32; 4]),
+    /// 追加のテクスチャ。アトラスのアロケーションの場合はアトラス全体を渡す。
+    Texture(TextureId),
+}
+
+impl MaterialParam {
+    /// WGSL のユニフォームでのアラインメントと大きさ。テクスチャの場合は `None` を返す。
+    pub const fn uniform_layout(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::Float(_) => Some((4, 4)),
+            Self::Vec2(_) => Some((8, 8)),
+            Self::Vec3(_) => Some((16, 12)),
+            Self::Vec4(_) | Self::Color(_) => Some((16, 16)),
+            Self::Texture(_) => None,
+        }
+    }
+
+    const fn components(&self) -> &[f32] {
+        match self {
+            Self::Float(value) => std::slice::from_ref(value),
+            Self::Vec2(value) => value,
+            Self::Vec3(value) => value,
+            Self::Vec4(value) | Self::Color(value) => value,
+            Self::Texture(_) => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// メッシュの描画方法
+///
+/// [`Material::shader`] を指定すると、組み込みのシェーダーの代わりに [`Shader`](crate::shader::Shader) で描画する。
+/// パイプラインとバインドグループは描画時に作られ、シェーダーとパラメータとテクスチャの組が変わるまで使い回される。
 pub struct Material {
     pub texture: TextureId,
+    pub blend: BlendMode,
+    /// 描画に使うシェーダー。`None` の場合は組み込みのシェーダーを使う。
+    pub shader: Option<ShaderKey>,
+    /// シェーダーに渡すパラメータ。名前はシェーダーの変数や構造体のメンバーの名前と合わせる。
+    pub params: Vec<(String, MaterialParam)>,
+    /// カリングする面。`None` の場合は両面を描画する。
+    pub cull_mode: Option<wgpu::Face>,
+    /// 深度テストを行うかどうか
+    pub depth_test: bool,
+    /// 深度を書き込むかどうか。`None` の場合は [`BlendMode`] に従う。
+    pub depth_write: Option<bool>,
+}
+
+impl Material {
+    /// 不透明なマテリアルを作る
+    pub const fn new(texture: TextureId) -> Self {
+        Self {
+            texture,
+            blend: BlendMode::Opaque,
+            shader: None,
+            params: Vec::new(),
+            cull_mode: Some(wgpu::Face::Back),
+            depth_test: true,
+            depth_write: None,
+        }
+    }
+
+    pub const fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub const fn with_shader(mut self, shader: ShaderKey) -> Self {
+        self.shader = Some(shader);
+        self
+    }
+
+    pub fn with_param(mut self, name: impl Into<String>, value: MaterialParam) -> Self {
+        self.set_param(name, value);
+        self
+    }
+
+    pub const fn with_cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub const fn with_depth(mut self, test: bool, write: Option<bool>) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self
+    }
+
+    /// パラメータを設定する。同じ名前のパラメータがない場合は末尾に追加する。
+    pub fn set_param(&mut self, name: impl Into<String>, value: MaterialParam) {
+        let name = name.into();
+        match self.params.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, param)) => *param = value,
+            None => self.params.push((name, value)),
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&MaterialParam> {
+        self.params
+            .iter()
+            .find_map(|(n, param)| (n == name).then_some(param))
+    }
+
+    /// 深度を書き込むかどうか
+    pub const fn writes_depth(&self) -> bool {
+        match self.depth_write {
+            Some(write) => write,
+            None => self.blend.is_opaque(),
+        }
+    }
+
+    /// テクスチャ以外のパラメータの名前、値、ユニフォームの構造体でのオフセット
+    pub fn uniform_members(&self) -> impl Iterator<Item = (&str, &MaterialParam, u32)> {
+        let mut end = 0_u32;
+        self.params.iter().filter_map(move |(name, param)| {
+            let (align, size) = param.uniform_layout()?;
+            let offset = end.next_multiple_of(align);
+            end = offset + size;
+            Some((name.as_str(), param, offset))
+        })
+    }
+
+    /// パラメータのユニフォームの大きさ。16 バイトに揃える。パラメータがない場合は 0 になる。
+    pub fn uniform_size(&self) -> u32 {
+        self.uniform_members()
+            .map(|(_, param, offset)| offset + param.uniform_layout().map_or(0, |(_, size)| size))
+            .max()
+            .unwrap_or(0)
+            .next_multiple_of(16)
+    }
+
+    /// パラメータのユニフォームの内容
+    pub fn uniform_data(&self) -> Vec<u8> {
+        let mut data = vec![0; self.uniform_size() as usize];
+        for (_, param, offset) in self.uniform_members() {
+            let bytes: &[u8] = bytemuck::cast_slice(param.components());
+            let offset = offset as usize;
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        data
+    }
+
+    /// テクスチャのパラメータの名前とテクスチャ
+    pub fn textures(&self) -> impl Iterator<Item = (&str, TextureId)> {
+        self.params.iter().filter_map(|(name, param)| match param {
+            MaterialParam::Texture(texture) => Some((name.as_str(), *texture)),
+            _ => None,
+        })
+    }
 }
 
 slotmap::new_key_type! { pub struct MaterialKey; }
@@ -33,11 +276,81 @@ impl std::fmt::Debug for Mesh {
             indices: _,
             vertex_count,
             index_count,
+            bounds,
+            revision,
         } = self;
         f.debug_struct("Mesh")
             .field("name", name)
             .field("vertex_count", vertex_count)
             .field("index_count", index_count)
+            .field("bounds", bounds)
+            .field("revision", revision)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::TextureIndex;
+
+    #[test]
+    fn mesh_revision_changes_with_geometry() {
+        let vertex = Vertex {
+            position: [1.0, 2.0, 3.0],
+            uv: [0.0, 0.0],
+            normal: [0.0, 0.0, -1.0],
+            color: [1.0; 4],
+        };
+        let mut mesh = Mesh::new("mesh".to_owned(), vec![vertex; 3], vec![0, 1, 2]);
+        let revision = mesh.revision();
+
+        mesh.vertices[0].position = [-1.0, 0.0, 0.0];
+        assert_eq!(mesh.revision(), revision);
+        mesh.update_bounds();
+        assert!(mesh.revision() > revision);
+        assert_eq!(mesh.bounds.unwrap().min, Point3::new(-1.0, 0.0, 0.0));
+
+        let revision = mesh.revision();
+        mesh.set_geometry(vec![vertex; 4], vec![0, 1, 2, 0, 2, 3]);
+        assert!(mesh.revision() > revision);
+        assert_eq!((mesh.vertex_count, mesh.index_count), (4, 6));
+    }
+
+    #[test]
+    fn material_params_use_wgsl_uniform_layout() {
+        let material = Material::new(TextureIndex::default().into())
+            .with_param("strength", MaterialParam::Float(2.0))
+            .with_param("offset", MaterialParam::Vec3([1.0, 2.0, 3.0]))
+            .with_param(
+                "mask",
+                MaterialParam::Texture(TextureIndex::default().into()),
+            )
+            .with_param("scroll", MaterialParam::Vec2([4.0, 5.0]))
+            .with_param("tint", MaterialParam::Color([0.5; 4]))
+            .with_param("strength", MaterialParam::Float(3.0));
+
+        let offsets: Vec<_> = material
+            .uniform_members()
+            .map(|(name, _, offset)| (name, offset))
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                ("strength", 0),
+                ("offset", 16),
+                ("scroll", 32),
+                ("tint", 48)
+            ]
+        );
+        assert_eq!(material.uniform_size(), 64);
+
+        let data: Vec<f32> = bytemuck::cast_slice(&material.uniform_data()).to_vec();
+        assert_eq!(data[0], 3.0);
+        assert_eq!(&data[4..7], &[1.0, 2.0, 3.0]);
+        assert_eq!(&data[8..10], &[4.0, 5.0]);
+        assert_eq!(&data[12..16], &[0.5; 4]);
+        assert_eq!(material.textures().count(), 1);
+        assert!(material.writes_depth());
+    }
+}
@@ -0,0 +1,183 @@
+//! フレームを更新するタイミングに関するモジュール
+use std::{
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use winit::event_loop::ControlFlow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// フレームを更新するタイミング
+pub enum UpdateMode {
+    /// 可能な限り速くフレームを更新する。CPU のコアを 1 つ使い続ける。
+    #[default]
+    Continuous,
+    /// 指定した FPS を上限としてフレームを更新する。フレームの間はスレッドを休ませる。
+    Limited { target_fps: NonZeroU32 },
+    /// 入力があったとき、またはゲームが
+    /// [`Frame::request_redraw`](crate::scene::frame::Frame::request_redraw) を呼んだときだけフレームを更新する。
+    ///
+    /// 静的なメニュー画面やツール、エディタ向け。
+    Reactive,
+}
+
+impl UpdateMode {
+    /// FPS の上限を指定する。`target_fps` が 0 の場合は [`UpdateMode::Continuous`] になる。
+    pub fn limited(target_fps: u32) -> Self {
+        NonZeroU32::new(target_fps)
+            .map_or(Self::Continuous, |target_fps| Self::Limited { target_fps })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// エンジンの設定
+pub struct EngineConfig {
+    pub update_mode: UpdateMode,
+    /// [`Shader::load`](crate::shader::Shader::load) で読み込んだシェーダーのファイルの変更を調べる間隔。
+    /// `None` の場合は読み込み直さない。デバッグビルドでは 0.5 秒になる。
+    pub shader_hot_reload: Option<Duration>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            update_mode: UpdateMode::default(),
+            shader_hot_reload: cfg!(debug_assertions).then_some(Duration::from_millis(500)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// [`UpdateMode`] に従って次のフレームを更新するかどうかを決める
+pub(crate) struct FramePacer {
+    mode: UpdateMode,
+    next_frame: Instant,
+    redraw_pending: bool,
+}
+
+impl FramePacer {
+    pub const fn new(mode: UpdateMode, now: Instant) -> Self {
+        Self {
+            mode,
+            next_frame: now,
+            // 最初のフレームは必ず描画する
+            redraw_pending: true,
+        }
+    }
+
+    const fn frame_period(&self) -> Option<Duration> {
+        match self.mode {
+            UpdateMode::Limited { target_fps } => Some(Duration::from_nanos(
+                1_000_000_000 / target_fps.get() as u64,
+            )),
+            UpdateMode::Continuous | UpdateMode::Reactive => None,
+        }
+    }
+
+    /// 現在時刻にフレームを更新するべきかどうか
+    pub fn should_redraw(&self, now: Instant) -> bool {
+        match self.mode {
+            UpdateMode::Continuous => true,
+            UpdateMode::Limited { .. } => now >= self.next_frame,
+            UpdateMode::Reactive => self.redraw_pending,
+        }
+    }
+
+    /// 次のイベントまでイベントループをどう待たせるか
+    pub const fn control_flow(&self) -> ControlFlow {
+        match self.mode {
+            UpdateMode::Continuous => ControlFlow::Poll,
+            UpdateMode::Limited { .. } => ControlFlow::WaitUntil(self.next_frame),
+            UpdateMode::Reactive if self.redraw_pending => ControlFlow::Poll,
+            UpdateMode::Reactive => ControlFlow::Wait,
+        }
+    }
+
+    /// 入力があったことを通知する
+    pub const fn on_input(&mut self) {
+        self.redraw_pending = true;
+    }
+
+    /// 次のフレームの描画を要求する
+    pub const fn request_redraw(&mut self) {
+        self.redraw_pending = true;
+    }
+
+    /// フレームを更新したことを通知する
+    pub fn on_frame(&mut self, now: Instant) {
+        self.redraw_pending = false;
+        if let Some(period) = self.frame_period() {
+            self.next_frame += period;
+            // 大きく遅れた場合は遅れを取り戻そうとせず、現在時刻から数え直す
+            if self.next_frame < now {
+                self.next_frame = now + period;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuous_always_redraws() {
+        let now = Instant::now();
+        let mut pacer = FramePacer::new(UpdateMode::Continuous, now);
+        pacer.on_frame(now);
+        assert!(pacer.should_redraw(now));
+        assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+    }
+
+    #[test]
+    fn limited_waits_until_next_frame() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(UpdateMode::limited(50), start);
+        assert!(pacer.should_redraw(start));
+
+        pacer.on_frame(start);
+        let next = start + Duration::from_millis(20);
+        assert_eq!(pacer.control_flow(), ControlFlow::WaitUntil(next));
+        assert!(!pacer.should_redraw(start + Duration::from_millis(19)));
+        assert!(pacer.should_redraw(next));
+
+        // 少し遅れても周期は元のスケジュールに揃う
+        pacer.on_frame(next + Duration::from_millis(3));
+        assert_eq!(
+            pacer.control_flow(),
+            ControlFlow::WaitUntil(start + Duration::from_millis(40))
+        );
+
+        // 大きく遅れた場合は現在時刻から数え直す
+        let late = start + Duration::from_millis(500);
+        pacer.on_frame(late);
+        assert_eq!(
+            pacer.control_flow(),
+            ControlFlow::WaitUntil(late + Duration::from_millis(20))
+        );
+    }
+
+    #[test]
+    fn limited_zero_is_continuous() {
+        assert_eq!(UpdateMode::limited(0), UpdateMode::Continuous);
+    }
+
+    #[test]
+    fn reactive_redraws_only_on_request() {
+        let now = Instant::now();
+        let mut pacer = FramePacer::new(UpdateMode::Reactive, now);
+        assert!(pacer.should_redraw(now));
+
+        pacer.on_frame(now);
+        assert!(!pacer.should_redraw(now));
+        assert_eq!(pacer.control_flow(), ControlFlow::Wait);
+
+        pacer.on_input();
+        assert!(pacer.should_redraw(now));
+        pacer.on_frame(now);
+
+        pacer.request_redraw();
+        assert!(pacer.should_redraw(now));
+        assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+    }
+}
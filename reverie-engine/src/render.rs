@@ -2,33 +2,65 @@
 use std::num::NonZeroU32;
 
 use anyhow::Context;
+use batch::SpriteBatcher;
+use blit::{BlitPipeline, OffscreenTarget};
+use clear::{ClearPipeline, ClearRequest};
+use instanced::InstancedSpriteRenderer;
+use mesh::MeshRenderer;
+use overlay::OverlayRenderPipeline;
+use slotmap::SecondaryMap;
 use sprite::SpriteRenderPipeline;
-use wgpu::{self as w, util::DeviceExt};
+use uniform::{CameraBinding, CameraUniform};
+use wgpu as w;
 
 use crate::{
-    camera::{Camera, Viewport},
-    scene::Scene,
+    camera::{Camera, CameraKey, CameraTarget, ClearMode, PixelRect, Projection, Viewport},
+    culling::Frustum,
+    scene::{DrawKind, Scene},
+    stats::{FrameStats, RenderCounterCells, RenderCounters},
+    texture::TextureIndex,
 };
 
 use texture::WgpuTexture;
 
+pub(crate) mod batch;
+pub(crate) mod blend;
+pub(crate) mod blit;
 pub(crate) mod buffer;
+pub(crate) mod clear;
+pub(crate) mod instanced;
+pub(crate) mod mesh;
+pub(crate) mod overlay;
+pub(crate) mod reflect;
 pub(crate) mod sprite;
 pub(crate) mod texture;
 pub(crate) mod uniform;
 pub(crate) mod vertex;
+pub(crate) mod wgsl;
 
 /// レンダリングを行うためのリソースをまとめた構造体
 pub struct RenderingResource<'window> {
-    pub transform_uniform_buffer: w::Buffer,
+    /// カメラごとのユニフォームバッファ。描画する順番に並ぶ。
+    pub camera_bindings: Vec<CameraBinding>,
     pub texture_sampler: w::Sampler,
     pub sprite_pipeline: SpriteRenderPipeline,
+    pub overlay_pipeline: OverlayRenderPipeline,
+    pub clear_pipeline: ClearPipeline,
+    pub sprite_batcher: SpriteBatcher,
+    pub instanced_sprites: InstancedSpriteRenderer,
+    pub mesh_renderer: MeshRenderer,
+    pub blit_pipeline: BlitPipeline,
+    /// ピクセルパーフェクトのカメラが仮想解像度で描画するテクスチャ
+    pub pixel_targets: SecondaryMap<CameraKey, OffscreenTarget>,
+    /// カメラごとの [`DrawList::transparent`](crate::scene::DrawList::transparent)
+    pub(crate) transparent_order: Vec<Vec<DrawKind>>,
     pub surface: w::Surface<'window>,
     pub surface_config: w::SurfaceConfiguration,
     pub device: w::Device,
     pub queue: w::Queue,
     pub viewport: Viewport,
     pub depth_texture: WgpuTexture,
+    pub(crate) counters: RenderCounterCells,
 }
 
 impl<'window> RenderingResource<'window> {
@@ -37,12 +69,10 @@ impl<'window> RenderingResource<'window> {
     /// * `surface_target`: 描画対象の surface
     /// * `width`: surface の幅
     /// * `height`: surface の高さ
-    /// * `camera`: カメラ
     pub async fn setup<S>(
         surface_target: S,
         width: NonZeroU32,
         height: NonZeroU32,
-        camera: &Camera,
     ) -> anyhow::Result<Self>
     where
         S: Into<w::SurfaceTarget<'window>> + Send,
@@ -64,50 +94,324 @@ impl<'window> RenderingResource<'window> {
         );
 
         let viewport = Viewport { width, height };
-        let transform_uniform_buffer = setup_uniform_buffer(&device, camera, &viewport)?;
 
         let sampler = setup_sampler(&device)?;
         tracing::trace!(?sampler, "setup_sampler");
 
-        let sprite_pipeline =
-            SpriteRenderPipeline::new(&device, surface_format, &transform_uniform_buffer);
+        let sprite_pipeline = SpriteRenderPipeline::new(&device, surface_format)?;
         tracing::trace!(?sprite_pipeline, "setup_render_pipeline");
 
+        let overlay_pipeline = OverlayRenderPipeline::new(&device, surface_format)?;
+        tracing::trace!(?overlay_pipeline, "setup_overlay_pipeline");
+
+        let clear_pipeline = ClearPipeline::new(&device, surface_format)?;
+        tracing::trace!(?clear_pipeline, "setup_clear_pipeline");
+
+        let sprite_batcher = SpriteBatcher::new(&device)?;
+
+        let instanced_sprites = InstancedSpriteRenderer::new(
+            &device,
+            surface_format,
+            &sprite_pipeline.texture_bind_group_layout,
+            &sprite_pipeline.camera_bind_group_layout,
+        )?;
+        tracing::trace!(?instanced_sprites, "setup_instanced_sprites");
+
+        let mesh_renderer = MeshRenderer::new(
+            &device,
+            surface_format,
+            &sprite_pipeline.texture_bind_group_layout,
+            &sprite_pipeline.camera_bind_group_layout,
+            &sampler,
+        )?;
+        tracing::trace!(?mesh_renderer, "setup_mesh_renderer");
+
+        let blit_pipeline = BlitPipeline::new(
+            &device,
+            surface_format,
+            &sprite_pipeline.texture_bind_group_layout,
+        )?;
+        tracing::trace!(?blit_pipeline, "setup_blit_pipeline");
+
         let depth_texture =
             WgpuTexture::create_depth_texture(&device, width, height, Some("depth_texture"));
 
         Ok(Self {
-            transform_uniform_buffer,
+            camera_bindings: Vec::new(),
             texture_sampler: sampler,
             sprite_pipeline,
+            overlay_pipeline,
+            clear_pipeline,
+            sprite_batcher,
+            instanced_sprites,
+            mesh_renderer,
+            blit_pipeline,
+            pixel_targets: SecondaryMap::new(),
             surface,
             surface_config,
             device,
             queue,
             viewport,
             depth_texture,
+            counters: RenderCounterCells::default(),
+            transparent_order: Vec::new(),
         })
     }
 
-    pub fn resize(&mut self, width: NonZeroU32, height: NonZeroU32, camera: &Camera) {
+    pub fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) {
         self.surface_config.width = width.get();
         self.surface_config.height = height.get();
         self.surface.configure(&self.device, &self.surface_config);
 
         self.viewport.width = width;
         self.viewport.height = height;
-        let matrix = camera.get_matrix_world_to_render_coordinate(&self.viewport);
-        self.queue.write_buffer(
-            &self.transform_uniform_buffer,
-            0,
-            bytemuck::cast_slice(matrix.as_slice()),
-        );
 
         self.depth_texture =
             WgpuTexture::create_depth_texture(&self.device, width, height, Some("depth_texture"));
     }
 
-    pub fn render(&self, scene: &mut Scene) {
+    /// シーンを描画し、このフレームのカウンタを返す
+    ///
+    /// * `stats`: パフォーマンスオーバーレイに表示する計測値
+    pub fn render(&mut self, scene: &mut Scene, stats: &FrameStats) -> RenderCounters {
+        scene.textures.prepare_render_targets(
+            &self.device,
+            self.surface_config.format,
+            &self.sprite_pipeline.texture_bind_group_layout,
+            &self.texture_sampler,
+            sprite::BINDING_TEXTURE.binding,
+            sprite::BINDING_SAMPLER.binding,
+        );
+        let passes = self.prepare_cameras(scene);
+        self.prepare_draws(scene, &passes);
+        if scene.show_performance_overlay {
+            self.overlay_pipeline.prepare(
+                &self.device,
+                &self.queue,
+                &self.counters,
+                &self.viewport,
+                stats,
+            );
+        }
+        let this = &*self;
+        this.render_scene(scene, &passes);
+        this.counters.take()
+    }
+
+    /// 描画先の大きさ。描画先のテクスチャがない場合は `None` を返す。
+    fn target_viewport(&self, scene: &Scene, target: CameraTarget) -> Option<Viewport> {
+        match target {
+            CameraTarget::Surface => Some(self.viewport),
+            CameraTarget::Texture(index) => scene
+                .textures
+                .render_target_size(index)
+                .map(|(width, height)| Viewport { width, height }),
+        }
+    }
+
+    /// カメラごとのユニフォームと消去を GPU に送信し、描画する順番に並べる
+    fn prepare_cameras(&mut self, scene: &Scene) -> Vec<CameraPass> {
+        let time = scene.time.game.elapsed().as_secs_f32();
+        let mut clears = Vec::new();
+        let mut passes: Vec<CameraPass> = Vec::new();
+        for key in scene.cameras_in_render_order() {
+            let camera = &scene.cameras[key];
+            let Some(viewport) = self.target_viewport(scene, camera.target) else {
+                tracing::warn!(?key, target = ?camera.target, "camera target is not a render target");
+                continue;
+            };
+            let i = passes.len();
+            if self.camera_bindings.len() <= i {
+                self.camera_bindings.push(CameraBinding::new(
+                    &self.device,
+                    &self.sprite_pipeline.camera_bind_group_layout,
+                    sprite::BINDING_CAMERA.binding,
+                ));
+            }
+            self.camera_bindings[i].uniform.write(
+                &self.queue,
+                &self.counters,
+                &CameraUniform::new(camera, &viewport, time),
+            );
+
+            let pixel = match &camera.projection {
+                Projection::PixelPerfect(projection) => {
+                    self.prepare_pixel_target(key, projection.width, projection.height);
+                    Some(PixelPass {
+                        output: camera.screen_rect(&viewport),
+                        load: PassLoad::new(
+                            camera.clear,
+                            match camera.clear {
+                                ClearMode::Color(color) => color,
+                                _ => scene.skybox,
+                            },
+                        ),
+                    })
+                }
+                _ => None,
+            };
+
+            // 描画先全体を覆う最初のカメラの消去はレンダーパスの開始時に行う
+            let first_of_target = passes
+                .last()
+                .is_none_or(|pass| pass.target != camera.target);
+            let clear = if first_of_target && camera.viewport.is_full() {
+                None
+            } else {
+                match camera.clear {
+                    ClearMode::Skybox | ClearMode::Color(_) => {
+                        Some(ClearRequest::Color(Self::outer_clear_color(scene, camera)))
+                    }
+                    ClearMode::DepthOnly => Some(ClearRequest::DepthOnly),
+                    ClearMode::None => None,
+                }
+            };
+            let clear = clear.map(|request| {
+                clears.push(request);
+                clears.len() - 1
+            });
+            passes.push(CameraPass {
+                camera: key,
+                frustum: camera.frustum(&viewport),
+                target: camera.target,
+                rect: camera.viewport.to_pixels(&viewport),
+                clear,
+                pixel,
+            });
+        }
+        self.clear_pipeline
+            .prepare(&self.device, &self.queue, &self.counters, clears);
+        self.pixel_targets
+            .retain(|key, _| passes.iter().any(|pass| pass.camera == key));
+        passes
+    }
+
+    /// カメラごとに見えるものを描画する順番に並べて GPU に送信する
+    fn prepare_draws(&mut self, scene: &Scene, passes: &[CameraPass]) {
+        let mut meshes = Vec::with_capacity(passes.len());
+        let mut sprites = Vec::with_capacity(passes.len());
+        let mut instances = Vec::with_capacity(passes.len());
+        self.transparent_order.clear();
+        for pass in passes {
+            let list = scene.draw_list(pass.camera, &pass.frustum, &self.counters);
+            meshes.push(list.meshes);
+            sprites.push(
+                list.sprites
+                    .into_iter()
+                    .map(|group| {
+                        group
+                            .into_iter()
+                            .filter_map(|(key, world)| scene.sprite_quad(key, &world))
+                            .collect()
+                    })
+                    .collect(),
+            );
+            instances.push(list.instances);
+            self.transparent_order.push(list.transparent);
+        }
+        self.mesh_renderer
+            .prepare(&self.device, &self.queue, &self.counters, scene, meshes);
+        self.sprite_batcher
+            .prepare(&self.device, &self.queue, &self.counters, sprites);
+        self.instanced_sprites
+            .prepare(&self.device, &self.queue, &self.counters, scene, instances);
+    }
+
+    /// ピクセルパーフェクトのカメラの描画先を用意する。仮想解像度が変わった場合は作り直す。
+    fn prepare_pixel_target(&mut self, key: CameraKey, width: NonZeroU32, height: NonZeroU32) {
+        let up_to_date = self
+            .pixel_targets
+            .get(key)
+            .is_some_and(|target| target.size() == (width.get(), height.get()));
+        if up_to_date {
+            return;
+        }
+        let target = OffscreenTarget::new(
+            &self.device,
+            self.surface_config.format,
+            width,
+            height,
+            &self.sprite_pipeline.texture_bind_group_layout,
+            &self.texture_sampler,
+        );
+        self.pixel_targets.insert(key, target);
+    }
+
+    /// カメラの描画範囲を消去する色。ピクセルパーフェクトのカメラでは余白の色になる。
+    const fn outer_clear_color(scene: &Scene, camera: &Camera) -> w::Color {
+        match (&camera.projection, camera.clear) {
+            (Projection::PixelPerfect(projection), _) => projection.letterbox_color,
+            (_, ClearMode::Color(color)) => color,
+            _ => scene.skybox,
+        }
+    }
+
+    /// レンダーパスの開始時に描画先全体をどう消去するか。
+    /// 描画先全体を覆う最初のカメラの [`ClearMode`] に従い、そのようなカメラがなければ空の色で消去する。
+    ///
+    /// * `passes`: 同じ描画先に描画するカメラ
+    fn initial_load(scene: &Scene, passes: &[(usize, &CameraPass)]) -> PassLoad {
+        passes
+            .first()
+            .map(|(_, pass)| &scene.cameras[pass.camera])
+            .filter(|camera| camera.viewport.is_full())
+            .map_or_else(
+                || PassLoad::new(ClearMode::Skybox, scene.skybox),
+                |camera| PassLoad::new(camera.clear, Self::outer_clear_color(scene, camera)),
+            )
+    }
+
+    fn render_scene(&self, scene: &Scene, passes: &[CameraPass]) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Main CommandEncoder"),
+            });
+
+        // ピクセルパーフェクトのカメラは、まず仮想解像度のテクスチャに描画する
+        for (i, pass) in passes.iter().enumerate() {
+            let (Some(pixel), Some(target)) = (&pass.pixel, self.pixel_targets.get(pass.camera))
+            else {
+                continue;
+            };
+            let mut rp = Self::begin_render_pass(
+                &mut encoder,
+                "Pixel Perfect Pass",
+                &target.color.view,
+                &target.depth.view,
+                pixel.load,
+            );
+            scene.render(&mut rp, self, i);
+        }
+
+        // テクスチャに描画するカメラは先に並んでいる
+        let mut texture_targets: Vec<TextureIndex> = Vec::new();
+        for pass in passes {
+            if let CameraTarget::Texture(index) = pass.target
+                && !texture_targets.contains(&index)
+            {
+                texture_targets.push(index);
+            }
+        }
+        for index in texture_targets {
+            let Some((color, depth)) = scene.textures.render_target_views(index) else {
+                continue;
+            };
+            let target_passes: Vec<_> = passes
+                .iter()
+                .enumerate()
+                .filter(|(_, pass)| pass.target == CameraTarget::Texture(index))
+                .collect();
+            let mut rp = Self::begin_render_pass(
+                &mut encoder,
+                "Render Target Pass",
+                color,
+                depth,
+                Self::initial_load(scene, &target_passes),
+            );
+            self.render_cameras(&mut rp, scene, &target_passes);
+        }
+
         match self.surface.get_current_texture() {
             wgpu::CurrentSurfaceTexture::Success(surface_texture)
             | wgpu::CurrentSurfaceTexture::Suboptimal(surface_texture) => {
@@ -117,37 +421,31 @@ impl<'window> RenderingResource<'window> {
                     .texture
                     .create_view(&wgpu::TextureViewDescriptor::default());
 
-                let mut encoder =
-                    self.device
-                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                            label: Some("Main CommandEncoder"),
-                        });
                 {
-                    let mut rp = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("SpriteComponent Render Pass"),
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &output,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(scene.skybox),
-                                store: wgpu::StoreOp::Store,
-                            },
-                            depth_slice: None,
-                        })],
-                        depth_stencil_attachment: Some(w::RenderPassDepthStencilAttachment {
-                            view: &self.depth_texture.view,
-                            depth_ops: Some(w::Operations {
-                                load: w::LoadOp::Clear(1.0),
-                                store: w::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        }),
-                        timestamp_writes: None,
-                        occlusion_query_set: None,
-                        multiview_mask: None,
-                    });
-
-                    scene.render(&mut rp, self);
+                    let surface_passes: Vec<_> = passes
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, pass)| pass.target == CameraTarget::Surface)
+                        .collect();
+                    let mut rp = Self::begin_render_pass(
+                        &mut encoder,
+                        "SpriteComponent Render Pass",
+                        &output,
+                        &self.depth_texture.view,
+                        Self::initial_load(scene, &surface_passes),
+                    );
+                    self.render_cameras(&mut rp, scene, &surface_passes);
+                    if scene.show_performance_overlay {
+                        rp.set_viewport(
+                            0.0,
+                            0.0,
+                            self.viewport.width.get() as f32,
+                            self.viewport.height.get() as f32,
+                            0.0,
+                            1.0,
+                        );
+                        self.overlay_pipeline.render(&mut rp, &self.counters);
+                    }
                 }
                 self.queue.submit(Some(encoder.finish()));
 
@@ -155,11 +453,131 @@ impl<'window> RenderingResource<'window> {
             }
             _ => {
                 tracing::warn!("no surface texture");
+                self.queue.submit(Some(encoder.finish()));
+            }
+        }
+    }
+
+    fn begin_render_pass<'e>(
+        encoder: &'e mut w::CommandEncoder,
+        label: &str,
+        color: &w::TextureView,
+        depth: &w::TextureView,
+        load: PassLoad,
+    ) -> w::RenderPass<'e> {
+        encoder.begin_render_pass(&w::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(w::RenderPassColorAttachment {
+                view: color,
+                resolve_target: None,
+                ops: w::Operations {
+                    load: load.color,
+                    store: w::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(w::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(w::Operations {
+                    load: load.depth,
+                    store: w::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        })
+    }
+
+    /// 同じ描画先に描画するカメラを順に描画する
+    ///
+    /// * `passes`: [`CameraPass`] とその添字。添字は [`RenderingResource::camera_bindings`] の添字と一致する。
+    fn render_cameras(
+        &self,
+        rp: &mut w::RenderPass<'_>,
+        scene: &Scene,
+        passes: &[(usize, &CameraPass)],
+    ) {
+        for &(i, pass) in passes {
+            let PixelRect {
+                x,
+                y,
+                width,
+                height,
+            } = pass.rect;
+            rp.set_viewport(x, y, width, height, 0.0, 1.0);
+            if let Some(index) = pass.clear {
+                self.clear_pipeline.render(rp, &self.counters, index);
+            }
+            if let Some(pixel) = &pass.pixel {
+                let Some(target) = self.pixel_targets.get(pass.camera) else {
+                    continue;
+                };
+                let PixelRect {
+                    x,
+                    y,
+                    width,
+                    height,
+                } = pixel.output;
+                rp.set_viewport(x, y, width, height, 0.0, 1.0);
+                self.blit_pipeline.render(rp, &self.counters, target);
+            } else {
+                scene.render(rp, self, i);
             }
         }
     }
 }
 
+#[derive(Debug)]
+/// 1 つのカメラの描画
+struct CameraPass {
+    camera: CameraKey,
+    frustum: Frustum,
+    target: CameraTarget,
+    rect: PixelRect,
+    /// [`ClearPipeline::prepare`] に渡した消去の添字
+    clear: Option<usize>,
+    /// ピクセルパーフェクトのカメラの場合の情報
+    pixel: Option<PixelPass>,
+}
+
+#[derive(Debug)]
+/// ピクセルパーフェクトのカメラの描画
+struct PixelPass {
+    /// 拡大した映像を表示する範囲
+    output: PixelRect,
+    /// 仮想解像度のテクスチャの消去
+    load: PassLoad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// レンダーパスの開始時に色と深度を消去するか、前の内容を読み込むか
+struct PassLoad {
+    color: w::LoadOp<w::Color>,
+    depth: w::LoadOp<f32>,
+}
+
+impl PassLoad {
+    /// `clear` に従う。色を消去する場合は `color` を使う。
+    const fn new(clear: ClearMode, color: w::Color) -> Self {
+        match clear {
+            ClearMode::Skybox | ClearMode::Color(_) => Self {
+                color: w::LoadOp::Clear(color),
+                depth: w::LoadOp::Clear(1.0),
+            },
+            ClearMode::DepthOnly => Self {
+                color: w::LoadOp::Load,
+                depth: w::LoadOp::Clear(1.0),
+            },
+            ClearMode::None => Self {
+                color: w::LoadOp::Load,
+                depth: w::LoadOp::Load,
+            },
+        }
+    }
+}
+
 #[tracing::instrument(level = "trace", skip(surface_target))]
 async fn setup_instance_surface_adapter_device_queue<'window, S>(
     surface_target: S,
@@ -240,19 +658,6 @@ where
     ))
 }
 
-fn setup_uniform_buffer(
-    device: &w::Device,
-    camera: &Camera,
-    viewport: &Viewport,
-) -> anyhow::Result<w::Buffer> {
-    let initial_matrix = camera.get_matrix_world_to_render_coordinate(viewport);
-    Ok(device.create_buffer_init(&w::util::BufferInitDescriptor {
-        label: Some("Pixel to Render Coordinate Matrix Buffer"),
-        contents: bytemuck::cast_slice(initial_matrix.as_slice()),
-        usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
-    }))
-}
-
 fn setup_sampler(device: &w::Device) -> anyhow::Result<w::Sampler> {
     Ok(device.create_sampler(&w::SamplerDescriptor {
         label: Some("Main Texture Sampler"),
@@ -282,16 +687,93 @@ impl BindingId {
 mod shader_test {
     use rstest::rstest;
 
+    use super::wgsl;
+
     #[rstest]
-    #[case::sprite(include_str!("./render/sprite.wgsl"))]
-    fn shader_compiles(#[case] source: &str) {
-        let module = naga::front::wgsl::parse_str(source).expect("WGSL parse error");
+    #[case::sprite("sprite.wgsl", include_str!("./render/sprite.wgsl"), &[])]
+    #[case::instanced_sprite("sprite.wgsl", include_str!("./render/sprite.wgsl"), &["INSTANCED"])]
+    #[case::overlay("overlay.wgsl", include_str!("./render/overlay.wgsl"), &[])]
+    #[case::clear("clear.wgsl", include_str!("./render/clear.wgsl"), &[])]
+    #[case::blit("blit.wgsl", include_str!("./render/blit.wgsl"), &[])]
+    #[case::mesh("mesh.wgsl", include_str!("./render/mesh.wgsl"), &[])]
+    #[case::error("error.wgsl", include_str!("./render/error.wgsl"), &[])]
+    fn shader_compiles(#[case] name: &str, #[case] source: &str, #[case] defines: &[&str]) {
+        let source = wgsl::preprocess(name, source, defines);
+        let module = naga::front::wgsl::parse_str(&source).expect("WGSL parse error");
         let mut validator = naga::valid::Validator::new(
             naga::valid::ValidationFlags::all(),
             naga::valid::Capabilities::all(),
         );
         if let Err(e) = validator.validate(&module) {
-            panic!("WGSL validation error: {}", e.emit_to_string(source));
+            panic!("WGSL validation error: {}", e.emit_to_string(&source));
         }
     }
+
+    /// WGSL の構造体 `name` の大きさ
+    fn wgsl_struct_size(source: &str, name: &str) -> u64 {
+        let source = wgsl::preprocess("test.wgsl", source, &[]);
+        let module = naga::front::wgsl::parse_str(&source).expect("WGSL parse error");
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(module.to_ctx()).unwrap();
+        let (handle, _) = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("struct {name} not found"));
+        u64::from(layouter[handle].size)
+    }
+
+    #[test]
+    fn camera_uniform_layout_matches_wgsl() {
+        use encase::ShaderType;
+
+        assert_eq!(
+            wgsl_struct_size(include_str!("./render/include/camera.wgsl"), "Camera"),
+            super::uniform::CameraUniform::min_size().get()
+        );
+    }
+
+    #[test]
+    fn object_uniform_layout_matches_wgsl() {
+        use encase::ShaderType;
+
+        assert_eq!(
+            wgsl_struct_size(include_str!("./render/include/object.wgsl"), "Object"),
+            super::uniform::ObjectUniform::min_size().get()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu as w;
+
+    use super::PassLoad;
+    use crate::camera::ClearMode;
+
+    #[test]
+    fn pass_load_keeps_contents_unless_color_is_cleared() {
+        let color = w::Color::RED;
+        assert_eq!(
+            PassLoad::new(ClearMode::Color(color), color),
+            PassLoad {
+                color: w::LoadOp::Clear(color),
+                depth: w::LoadOp::Clear(1.0),
+            }
+        );
+        assert_eq!(
+            PassLoad::new(ClearMode::DepthOnly, color),
+            PassLoad {
+                color: w::LoadOp::Load,
+                depth: w::LoadOp::Clear(1.0),
+            }
+        );
+        assert_eq!(
+            PassLoad::new(ClearMode::None, color),
+            PassLoad {
+                color: w::LoadOp::Load,
+                depth: w::LoadOp::Load,
+            }
+        );
+    }
 }
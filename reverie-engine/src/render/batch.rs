@@ -0,0 +1,251 @@
+use std::ops::Range;
+
+use wgpu as w;
+
+use crate::{
+    model::{BlendMode, Vertex},
+    scene::SpriteQuad,
+    stats::RenderCounterCells,
+    texture::{TextureIndex, TextureRegistry},
+};
+
+use super::{
+    blend::{BlendPipelines, RenderPhase},
+    buffer::VertexIndexBuffer,
+    sprite,
+};
+
+/// 1 つのスプライトの四角形のインデックス。頂点は左上、右上、左下、右下の順。
+const QUAD_INDICES: [u32; 6] = [0, 3, 1, 0, 2, 3];
+
+/// バッファの最初の容量 (スプライトの数)
+const INITIAL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 同じテクスチャと合成方法を使うスプライトをまとめた 1 回の描画
+struct SpriteBatch {
+    texture: TextureIndex,
+    blend: BlendMode,
+    indices: Range<u32>,
+}
+
+#[derive(Debug, Default)]
+/// スプライトを並べた頂点とインデックス、描画の単位
+struct SpriteBatches {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    batches: Vec<SpriteBatch>,
+    /// カメラごと、[`RenderPhase::group`] ごとの `batches` の範囲
+    passes: Vec<Vec<Range<usize>>>,
+}
+
+impl SpriteBatches {
+    /// カメラと区切りごとに、描画する順番を保ったまま同じテクスチャと合成方法が続く部分を 1 つの描画にまとめる
+    fn build(passes: Vec<Vec<Vec<SpriteQuad>>>) -> Self {
+        let mut this = Self::default();
+        for groups in passes {
+            let mut ranges = Vec::with_capacity(groups.len());
+            for sprites in groups {
+                let first_batch = this.batches.len();
+                for (texture, blend, quad) in sprites {
+                    let base = this.vertices.len() as u32;
+                    this.vertices.extend_from_slice(&quad);
+                    let start = this.indices.len() as u32;
+                    this.indices.extend(QUAD_INDICES.map(|i| base + i));
+                    let end = this.indices.len() as u32;
+                    match this.batches[first_batch..].last_mut() {
+                        Some(batch) if batch.texture == texture && batch.blend == blend => {
+                            batch.indices.end = end
+                        }
+                        _ => this.batches.push(SpriteBatch {
+                            texture,
+                            blend,
+                            indices: start..end,
+                        }),
+                    }
+                }
+                ranges.push(first_batch..this.batches.len());
+            }
+            this.passes.push(ranges);
+        }
+        this
+    }
+}
+
+#[derive(Debug)]
+/// 見えるスプライトを 1 つの頂点バッファに集め、テクスチャごとにまとめて描画する
+///
+/// すべてのカメラのスプライトを 1 つのバッファに並べ、フレームの始めに一度だけ送信する。
+/// 内容が前のフレームと同じ場合は送信しない。
+pub struct SpriteBatcher {
+    buffer: VertexIndexBuffer<Vertex, u32>,
+    batches: Vec<SpriteBatch>,
+    /// カメラごと、[`RenderPhase::group`] ごとの `batches` の範囲
+    passes: Vec<Vec<Range<usize>>>,
+}
+
+impl SpriteBatcher {
+    pub fn new(device: &w::Device) -> anyhow::Result<Self> {
+        Ok(Self {
+            buffer: VertexIndexBuffer::new(
+                device,
+                INITIAL_CAPACITY * 4,
+                INITIAL_CAPACITY * QUAD_INDICES.len(),
+                Some("sprite batch"),
+            )?,
+            batches: Vec::new(),
+            passes: Vec::new(),
+        })
+    }
+
+    /// このフレームで描画するスプライトを GPU に送信する
+    ///
+    /// * `passes`: カメラごとの [`DrawList::sprites`](crate::scene::DrawList::sprites) のテクスチャと頂点。
+    ///   添字が [`SpriteBatcher::render`] の `pass` になる。
+    pub fn prepare(
+        &mut self,
+        device: &w::Device,
+        queue: &w::Queue,
+        counters: &RenderCounterCells,
+        passes: Vec<Vec<Vec<SpriteQuad>>>,
+    ) {
+        let next = SpriteBatches::build(passes);
+        let mut update = self.buffer.start_update(device, queue, counters);
+        let (vertices, indices) = update.arrays_mut();
+        let vertices_changed = *vertices != next.vertices;
+        let indices_changed = *indices != next.indices;
+        *vertices = next.vertices;
+        *indices = next.indices;
+        let (vertex_len, index_len) = (vertices.len(), indices.len());
+        if vertices_changed {
+            update.set_vertex_update(0..vertex_len);
+        }
+        if indices_changed {
+            update.set_index_update(0..index_len);
+        }
+        drop(update);
+        self.batches = next.batches;
+        self.passes = next.passes;
+    }
+
+    /// [`SpriteBatcher::prepare`] で送信した `pass` 番目のカメラのスプライトのうち、`phase` の区切りのものを描画する
+    ///
+    /// * `pipelines`: [`sprite::SpriteRenderPipeline::pipelines`]
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        rp: &mut w::RenderPass<'_>,
+        textures: &TextureRegistry,
+        counters: &RenderCounterCells,
+        pipelines: &BlendPipelines,
+        camera_bind_group: &w::BindGroup,
+        pass: usize,
+        phase: RenderPhase,
+    ) {
+        let Some(range) = self
+            .passes
+            .get(pass)
+            .and_then(|groups| groups.get(phase.group()))
+        else {
+            return;
+        };
+        let batches = &self.batches[range.clone()];
+        if batches.is_empty() {
+            return;
+        }
+        rp.set_bind_group(sprite::GROUP_CAMERA, camera_bind_group, &[]);
+        self.buffer.bind(rp);
+        for batch in batches {
+            let bind_group = match textures.get_bind_group(batch.texture.into()) {
+                Ok(bind_group) => bind_group,
+                Err(err) => {
+                    tracing::warn!(?err, texture = ?batch.texture, "skipped sprite batch");
+                    continue;
+                }
+            };
+            rp.set_pipeline(pipelines.get(batch.blend));
+            rp.set_bind_group(sprite::GROUP_TEXTURE, bind_group, &[]);
+            rp.draw_indexed(batch.indices.clone(), 0, 0..1);
+            counters.add_sprites_drawn(batch.indices.len() as u64 / QUAD_INDICES.len() as u64);
+            counters.add_draw_calls(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slotmap::SlotMap;
+
+    use super::*;
+
+    fn quad(x: f32) -> [Vertex; 4] {
+        [Vertex {
+            position: [x, 0.0, 0.0],
+            uv: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            color: [1.0; 4],
+        }; 4]
+    }
+
+    #[test]
+    fn adjacent_sprites_are_grouped_by_texture() {
+        let mut keys = SlotMap::<TextureIndex, ()>::with_key();
+        let a = keys.insert(());
+        let b = keys.insert(());
+
+        let batches = SpriteBatches::build(vec![
+            vec![vec![
+                (a, BlendMode::Alpha, quad(0.0)),
+                (a, BlendMode::Alpha, quad(1.0)),
+                (b, BlendMode::Alpha, quad(2.0)),
+            ]],
+            vec![vec![(b, BlendMode::Alpha, quad(3.0))]],
+        ]);
+        assert_eq!(batches.vertices.len(), 16);
+        assert_eq!(batches.passes, vec![vec![0..2], vec![2..3]]);
+        assert_eq!(
+            batches.batches,
+            vec![
+                SpriteBatch {
+                    texture: a,
+                    blend: BlendMode::Alpha,
+                    indices: 0..12,
+                },
+                SpriteBatch {
+                    texture: b,
+                    blend: BlendMode::Alpha,
+                    indices: 12..18,
+                },
+                SpriteBatch {
+                    texture: b,
+                    blend: BlendMode::Alpha,
+                    indices: 18..24,
+                },
+            ]
+        );
+        // 2 つ目のカメラのスプライトは頂点の後ろの方を指す
+        assert_eq!(batches.indices[18..24], [12, 15, 13, 12, 14, 15]);
+
+        // 描画する順番を変えないため、離れたスプライトはまとめない
+        let batches = SpriteBatches::build(vec![vec![vec![
+            (a, BlendMode::Alpha, quad(0.0)),
+            (b, BlendMode::Alpha, quad(1.0)),
+            (a, BlendMode::Alpha, quad(2.0)),
+        ]]]);
+        assert_eq!(
+            batches
+                .batches
+                .iter()
+                .map(|b| b.texture)
+                .collect::<Vec<_>>(),
+            vec![a, b, a]
+        );
+
+        // 区切りをまたいではまとめない
+        let batches = SpriteBatches::build(vec![vec![
+            vec![(a, BlendMode::Alpha, quad(0.0))],
+            vec![(a, BlendMode::Alpha, quad(1.0))],
+        ]]);
+        assert_eq!(batches.passes, vec![vec![0..1, 1..2]]);
+    }
+}
@@ -0,0 +1,120 @@
+use wgpu as w;
+
+use crate::model::BlendMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 1 つのカメラの描画の段階。不透明なものをすべて描画してから半透明のものを描画する。
+///
+/// 半透明のものは種類をまたいで奥から手前に並べ、同じ種類が続く区切りごとに描画する。
+/// [`DrawList`](crate::scene::DrawList) を参照。
+pub enum RenderPhase {
+    Opaque,
+    /// 種類ごとの `n` 番目の半透明の区切り
+    Transparent(usize),
+}
+
+impl RenderPhase {
+    /// [`DrawList`](crate::scene::DrawList) の種類ごとの区切りの添字
+    pub const fn group(self) -> usize {
+        match self {
+            Self::Opaque => 0,
+            Self::Transparent(n) => n + 1,
+        }
+    }
+}
+
+/// 合成方法ごとのフラグメントシェーダーのエントリポイント
+///
+/// 不透明なものは `fs_opaque` で描画し、アルファの小さいピクセルを捨てる。
+pub const fn fragment_entry_point(blend: BlendMode) -> &'static str {
+    if blend.is_opaque() {
+        "fs_opaque"
+    } else {
+        "fs_main"
+    }
+}
+
+/// 合成方法ごとの色の書き込み方
+pub fn color_target(format: w::TextureFormat, blend: BlendMode) -> w::ColorTargetState {
+    let over = |src_factor, dst_factor| w::BlendState {
+        color: w::BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: w::BlendOperation::Add,
+        },
+        alpha: w::BlendComponent::OVER,
+    };
+    let state = match blend {
+        BlendMode::Opaque => None,
+        BlendMode::Alpha => Some(over(
+            w::BlendFactor::SrcAlpha,
+            w::BlendFactor::OneMinusSrcAlpha,
+        )),
+        BlendMode::Additive => Some(over(w::BlendFactor::SrcAlpha, w::BlendFactor::One)),
+        BlendMode::Multiply => Some(over(w::BlendFactor::Dst, w::BlendFactor::Zero)),
+        BlendMode::Premultiplied => Some(w::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+    };
+    w::ColorTargetState {
+        format,
+        blend: state,
+        write_mask: w::ColorWrites::ALL,
+    }
+}
+
+/// 合成方法ごとの深度の扱い。半透明のものは深度を書き込まない。
+pub const fn depth_stencil(blend: BlendMode) -> w::DepthStencilState {
+    let opaque = blend.is_opaque();
+    w::DepthStencilState {
+        format: w::TextureFormat::Depth32Float,
+        depth_write_enabled: Some(opaque),
+        depth_compare: Some(if opaque {
+            w::CompareFunction::Less
+        } else {
+            w::CompareFunction::LessEqual
+        }),
+        stencil: w::StencilState {
+            front: w::StencilFaceState::IGNORE,
+            back: w::StencilFaceState::IGNORE,
+            read_mask: 0,
+            write_mask: 0,
+        },
+        bias: w::DepthBiasState {
+            constant: 0,
+            slope_scale: 0.0,
+            clamp: 0.0,
+        },
+    }
+}
+
+#[derive(Debug)]
+/// 合成方法ごとのパイプライン
+pub struct BlendPipelines([w::RenderPipeline; BlendMode::ALL.len()]);
+
+impl BlendPipelines {
+    /// [`BlendMode::ALL`] のそれぞれについて `create` でパイプラインを作る
+    pub fn new(create: impl FnMut(BlendMode) -> w::RenderPipeline) -> Self {
+        Self(BlendMode::ALL.map(create))
+    }
+
+    pub const fn get(&self, blend: BlendMode) -> &w::RenderPipeline {
+        &self.0[blend as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_modes_are_split_into_phases() {
+        for (i, blend) in BlendMode::ALL.into_iter().enumerate() {
+            assert_eq!(blend as usize, i);
+            assert_eq!(
+                depth_stencil(blend).depth_write_enabled,
+                Some(blend.is_opaque())
+            );
+        }
+        assert_eq!(RenderPhase::Opaque.group(), 0);
+        assert_eq!(RenderPhase::Transparent(0).group(), 1);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{borrow::Cow, num::NonZeroU32};
+
+use wgpu as w;
+
+use crate::stats::RenderCounterCells;
+
+use super::{
+    reflect,
+    sprite::{BINDING_SAMPLER, BINDING_TEXTURE},
+    texture::WgpuTexture,
+    wgsl,
+};
+
+#[derive(Debug)]
+/// テクスチャをビューポート全体に拡大して描くパイプライン
+///
+/// ピクセルパーフェクトのカメラが仮想解像度で描画した映像を、描画先に転送するために使う。
+pub struct BlitPipeline {
+    pipeline: w::RenderPipeline,
+}
+
+impl BlitPipeline {
+    /// * `texture_bind_group_layout`: テクスチャとサンプラーを持つバインドグループのレイアウト
+    pub fn new(
+        device: &w::Device,
+        surface_format: w::TextureFormat,
+        texture_bind_group_layout: &w::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let source = wgsl::preprocess("blit.wgsl", include_str!("./blit.wgsl"), &[]);
+        // 頂点は頂点番号から作るので、頂点バッファを使わない
+        reflect::check_vertex_inputs(&reflect::parse("blit.wgsl", &source)?, "vs_main", &[])?;
+        let shader = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some("blit.wgsl"),
+            source: w::ShaderSource::Wgsl(Cow::Owned(source)),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("blit render pipeline layout"),
+            bind_group_layouts: &[Some(texture_bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_render_pipeline(&w::RenderPipelineDescriptor {
+            label: Some("blit render pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: w::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(w::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(w::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(w::BlendState::ALPHA_BLENDING),
+                    write_mask: w::ColorWrites::ALL,
+                })],
+            }),
+            primitive: w::PrimitiveState::default(),
+            depth_stencil: Some(w::DepthStencilState {
+                format: w::TextureFormat::Depth32Float,
+                depth_write_enabled: Some(false),
+                depth_compare: Some(w::CompareFunction::Always),
+                stencil: w::StencilState::default(),
+                bias: w::DepthBiasState::default(),
+            }),
+            multisample: w::MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+
+        Ok(Self { pipeline })
+    }
+
+    /// `target` の映像を描く。ビューポートは呼び出し側で設定する。
+    pub fn render(
+        &self,
+        rp: &mut w::RenderPass<'_>,
+        counters: &RenderCounterCells,
+        target: &OffscreenTarget,
+    ) {
+        rp.set_pipeline(&self.pipeline);
+        rp.set_bind_group(0, &target.bind_group, &[]);
+        rp.draw(0..3, 0..1);
+        counters.add_draw_calls(1);
+    }
+}
+
+#[derive(Debug)]
+/// カメラが一旦描画するための、描画先とは別のテクスチャ
+pub struct OffscreenTarget {
+    pub color: WgpuTexture,
+    pub depth: WgpuTexture,
+    pub bind_group: w::BindGroup,
+}
+
+impl OffscreenTarget {
+    /// * `layout`: [`SpriteRenderPipeline`](super::sprite::SpriteRenderPipeline) のテクスチャのバインドグループのレイアウト
+    pub fn new(
+        device: &w::Device,
+        format: w::TextureFormat,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        layout: &w::BindGroupLayout,
+        sampler: &w::Sampler,
+    ) -> Self {
+        let color = WgpuTexture::create_render_target(
+            device,
+            width,
+            height,
+            format,
+            Some("offscreen target"),
+        );
+        let depth =
+            WgpuTexture::create_depth_texture(device, width, height, Some("offscreen depth"));
+        let bind_group = color.create_bind_group(
+            device,
+            Some("offscreen target bind group"),
+            layout,
+            sampler,
+            BINDING_TEXTURE.binding,
+            BINDING_SAMPLER.binding,
+        );
+        Self {
+            color,
+            depth,
+            bind_group,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.color.width(), self.color.height())
+    }
+}
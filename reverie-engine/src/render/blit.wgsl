@@ -0,0 +1,29 @@
+#include "bindings.wgsl"
+
+struct VertexOutput {
+  @location(0) uv: vec2<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@group(GROUP_TEXTURE)
+@binding(BINDING_TEXTURE)
+var tex: texture_2d<f32>;
+
+@group(GROUP_TEXTURE)
+@binding(BINDING_SAMPLER)
+var samp: sampler;
+
+// 頂点バッファを使わずにビューポート全体を覆う三角形を描く
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+  var out: VertexOutput;
+  let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+  out.uv = uv;
+  out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return textureSample(tex, samp, in.uv);
+}
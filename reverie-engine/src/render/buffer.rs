@@ -2,109 +2,138 @@ use std::ops::Range;
 
 use wgpu as w;
 
+use crate::stats::RenderCounterCells;
+
 use super::vertex::VertexLayout;
 
+/// インデックスバッファに格納できる整数型
+pub trait Index: bytemuck::Pod {
+    const FORMAT: w::IndexFormat;
+}
+
+impl Index for u16 {
+    const FORMAT: w::IndexFormat = w::IndexFormat::Uint16;
+}
+
+impl Index for u32 {
+    const FORMAT: w::IndexFormat = w::IndexFormat::Uint32;
+}
+
 #[derive(Debug)]
 /// 頂点バッファとインデックスバッファをまとめた構造体
-pub struct VertexIndexBuffer<V> {
+///
+/// CPU 側の配列が GPU のバッファより大きくなった場合は、更新時にバッファを作り直す。
+pub struct VertexIndexBuffer<V, I = u16> {
+    label: Option<String>,
     pub(crate) vertex_buffer: w::Buffer,
     vertex_array: Vec<V>,
     pub(crate) index_buffer: w::Buffer,
-    index_array: Vec<u16>,
+    index_array: Vec<I>,
     pub(crate) index_buffer_range: Range<u32>,
 }
 
-impl<V: VertexLayout + bytemuck::Pod> VertexIndexBuffer<V> {
+impl<V: VertexLayout + bytemuck::Pod, I: Index> VertexIndexBuffer<V, I> {
+    /// * `vertices`, `indices`: 最初に確保する頂点とインデックスの数
     pub fn new(
         device: &w::Device,
-        max_vertices: usize,
-        max_indices: usize,
+        vertices: usize,
+        indices: usize,
         label: Option<&str>,
     ) -> anyhow::Result<Self> {
-        let name_v = label.map(|label| format!("{label} [vertex part]"));
-        let name_i = label.map(|label| format!("{label} [index part]"));
-
-        let vertex_buffer = device.create_buffer(&w::BufferDescriptor {
-            label: name_v.as_deref(),
-            usage: w::BufferUsages::VERTEX | w::BufferUsages::COPY_DST,
-            size: (max_vertices * size_of::<V>()) as u64,
-            mapped_at_creation: false,
-        });
-        let index_buffer = device.create_buffer(&w::BufferDescriptor {
-            label: name_i.as_deref(),
-            usage: w::BufferUsages::INDEX | w::BufferUsages::COPY_DST,
-            size: (max_indices * size_of::<u16>()) as u64,
-            mapped_at_creation: false,
-        });
-
+        let label = label.map(str::to_string);
         Ok(Self {
-            vertex_buffer,
-            vertex_array: Vec::with_capacity(max_vertices),
-            index_buffer,
-            index_array: Vec::with_capacity(max_indices),
+            vertex_buffer: create_vertex_buffer::<V>(device, label.as_deref(), vertices),
+            vertex_array: Vec::with_capacity(vertices),
+            index_buffer: create_index_buffer::<I>(device, label.as_deref(), indices),
+            index_array: Vec::with_capacity(indices),
             index_buffer_range: 0..0,
+            label,
         })
     }
 
     pub const fn start_update<'a>(
         &'a mut self,
+        device: &'a w::Device,
         queue: &'a w::Queue,
-    ) -> VertexIndexBufferUpdater<'a, V> {
+        counters: &'a RenderCounterCells,
+    ) -> VertexIndexBufferUpdater<'a, V, I> {
         VertexIndexBufferUpdater {
             buffer: self,
+            device,
             queue,
+            counters,
             vertex_update: 0..0,
             index_update: 0..0,
         }
     }
 
+    /// 頂点バッファとインデックスバッファを設定する
+    pub fn bind(&self, rp: &mut w::RenderPass<'_>) {
+        rp.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rp.set_index_buffer(self.index_buffer.slice(..), I::FORMAT);
+    }
+
+    /// 配列が収まらない場合はバッファを作り直す。作り直した場合は配列全体を送信する必要がある。
+    fn reserve(
+        &mut self,
+        device: &w::Device,
+        vertex_update: &mut Range<usize>,
+        index_update: &mut Range<usize>,
+    ) {
+        let label = self.label.as_deref();
+        if grow::<V>(
+            &mut self.vertex_buffer,
+            self.vertex_array.len(),
+            |capacity| create_vertex_buffer::<V>(device, label, capacity),
+        ) {
+            *vertex_update = 0..self.vertex_array.len();
+        }
+        if grow::<I>(&mut self.index_buffer, self.index_array.len(), |capacity| {
+            create_index_buffer::<I>(device, label, capacity)
+        }) {
+            *index_update = 0..self.index_array.len();
+        }
+    }
+
+    /// 指定した範囲を GPU に送信し、送信したバイト数を返す
     fn send_to_gpu(
         &self,
         queue: &w::Queue,
         vertex_update: Range<usize>,
         index_update: Range<usize>,
-    ) {
-        if !vertex_update.is_empty() {
-            queue.write_buffer(
-                &self.vertex_buffer,
-                vertex_update.start as u64,
-                bytemuck::cast_slice(&self.vertex_array[vertex_update]),
-            );
-        }
-        if !index_update.is_empty() {
-            queue.write_buffer(
-                &self.index_buffer,
-                index_update.start as u64,
-                bytemuck::cast_slice::<u16, u8>(&self.index_array[index_update]),
-            );
-        }
+    ) -> u64 {
+        write_range(
+            queue,
+            &self.vertex_buffer,
+            &self.vertex_array,
+            vertex_update,
+        ) + write_range(queue, &self.index_buffer, &self.index_array, index_update)
     }
 }
 
 #[derive(Debug)]
 /// [`VertexIndexBuffer`] の更新を行うための構造体。Drop されると GPU にデータを送信する
-pub struct VertexIndexBufferUpdater<'a, V: VertexLayout + bytemuck::Pod> {
-    buffer: &'a mut VertexIndexBuffer<V>,
+pub struct VertexIndexBufferUpdater<'a, V: VertexLayout + bytemuck::Pod, I: Index = u16> {
+    buffer: &'a mut VertexIndexBuffer<V, I>,
+    device: &'a w::Device,
     queue: &'a w::Queue,
+    counters: &'a RenderCounterCells,
     vertex_update: Range<usize>,
     index_update: Range<usize>,
 }
 
-impl<V: VertexLayout + bytemuck::Pod> VertexIndexBufferUpdater<'_, V> {
-    pub const fn vertex_mut(&mut self) -> &mut Vec<V> {
-        &mut self.buffer.vertex_array
+impl<V: VertexLayout + bytemuck::Pod, I: Index> VertexIndexBufferUpdater<'_, V, I> {
+    /// 頂点とインデックスを同時に編集する
+    pub const fn arrays_mut(&mut self) -> (&mut Vec<V>, &mut Vec<I>) {
+        (&mut self.buffer.vertex_array, &mut self.buffer.index_array)
     }
 
-    pub const fn index_mut(&mut self) -> &mut Vec<u16> {
-        &mut self.buffer.index_array
-    }
-
-    /// 更新した頂点バッファの範囲を設定する
+    /// 更新した頂点バッファの範囲 (要素の添字) を設定する
     pub const fn set_vertex_update(&mut self, range: Range<usize>) {
         self.vertex_update = range;
     }
 
-    /// 更新したインデックスバッファの範囲を設定する
+    /// 更新したインデックスバッファの範囲 (要素の添字) を設定する
     pub const fn set_index_update(&mut self, range: Range<usize>) {
         self.index_update = range;
     }
@@ -115,12 +144,112 @@ impl<V: VertexLayout + bytemuck::Pod> VertexIndexBufferUpdater<'_, V> {
     }
 }
 
-impl<V: VertexLayout + bytemuck::Pod> std::ops::Drop for VertexIndexBufferUpdater<'_, V> {
+impl<V: VertexLayout + bytemuck::Pod, I: Index> std::ops::Drop
+    for VertexIndexBufferUpdater<'_, V, I>
+{
     fn drop(&mut self) {
-        self.buffer.send_to_gpu(
-            self.queue,
-            self.vertex_update.clone(),
-            self.index_update.clone(),
-        )
+        let mut vertex_update = self.vertex_update.clone();
+        let mut index_update = self.index_update.clone();
+        self.buffer
+            .reserve(self.device, &mut vertex_update, &mut index_update);
+        let bytes = self
+            .buffer
+            .send_to_gpu(self.queue, vertex_update, index_update);
+        self.counters.add_uploaded_bytes(bytes);
+    }
+}
+
+/// 要素の範囲 `elements` を、[`w::COPY_BUFFER_ALIGNMENT`] に揃えたバイトの範囲にする
+///
+/// * `size`: 要素 1 つのバイト数
+fn aligned_byte_range(elements: &Range<usize>, size: usize) -> Range<u64> {
+    let start = (elements.start * size) as u64;
+    let end = (elements.end * size) as u64;
+    let start = start - start % w::COPY_BUFFER_ALIGNMENT;
+    start..w::util::align_to(end, w::COPY_BUFFER_ALIGNMENT)
+}
+
+/// `buffer` に `len` 個の要素が収まらない場合は、`create` で 2 のべき乗の容量のバッファに作り直す
+///
+/// 作り直した場合は `true` を返す。新しいバッファは空なので、呼び出し側で配列全体を送信する。
+pub fn grow<T>(
+    buffer: &mut w::Buffer,
+    len: usize,
+    create: impl FnOnce(usize) -> w::Buffer,
+) -> bool {
+    if (len * size_of::<T>()) as u64 <= buffer.size() {
+        return false;
+    }
+    *buffer = create(len.next_power_of_two());
+    true
+}
+
+/// `array` の `update` の範囲を `buffer` に書き込み、送信したバイト数を返す
+///
+/// 範囲の端が [`w::COPY_BUFFER_ALIGNMENT`] に揃わない場合は、前後を含めて送信し、配列の末尾を超える部分は 0 で埋める。
+pub fn write_range<T: bytemuck::Pod>(
+    queue: &w::Queue,
+    buffer: &w::Buffer,
+    array: &[T],
+    update: Range<usize>,
+) -> u64 {
+    let update = update.start.min(array.len())..update.end.min(array.len());
+    if update.is_empty() {
+        return 0;
+    }
+    let bytes: &[u8] = bytemuck::cast_slice(array);
+    let range = aligned_byte_range(&update, size_of::<T>());
+    let (start, end) = (range.start as usize, range.end as usize);
+    if end <= bytes.len() {
+        queue.write_buffer(buffer, range.start, &bytes[start..end]);
+    } else {
+        let mut data = bytes[start..].to_vec();
+        data.resize(end - start, 0);
+        queue.write_buffer(buffer, range.start, &data);
+    }
+    range.end - range.start
+}
+
+/// `len` 個の要素を格納できるバッファの大きさ。[`w::COPY_BUFFER_ALIGNMENT`] に揃える。
+pub fn buffer_size<T>(len: usize) -> u64 {
+    w::util::align_to(
+        (len.max(1) * size_of::<T>()) as u64,
+        w::COPY_BUFFER_ALIGNMENT,
+    )
+}
+
+fn create_vertex_buffer<V>(device: &w::Device, label: Option<&str>, len: usize) -> w::Buffer {
+    let label = label.map(|label| format!("{label} [vertex part]"));
+    device.create_buffer(&w::BufferDescriptor {
+        label: label.as_deref(),
+        usage: w::BufferUsages::VERTEX | w::BufferUsages::COPY_DST,
+        size: buffer_size::<V>(len),
+        mapped_at_creation: false,
+    })
+}
+
+fn create_index_buffer<I>(device: &w::Device, label: Option<&str>, len: usize) -> w::Buffer {
+    let label = label.map(|label| format!("{label} [index part]"));
+    device.create_buffer(&w::BufferDescriptor {
+        label: label.as_deref(),
+        usage: w::BufferUsages::INDEX | w::BufferUsages::COPY_DST,
+        size: buffer_size::<I>(len),
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_ranges_are_aligned() {
+        // 頂点は要素の添字ではなくバイトでオフセットを求める
+        assert_eq!(aligned_byte_range(&(2..5), 32), 64..160);
+        // u16 のインデックスは 4 バイトに揃える
+        assert_eq!(aligned_byte_range(&(1..4), 2), 0..8);
+        assert_eq!(aligned_byte_range(&(2..6), 2), 4..12);
+        assert_eq!(buffer_size::<u16>(3), 8);
+        assert_eq!(buffer_size::<u32>(0), 4);
     }
 }
@@ -0,0 +1,149 @@
+use std::borrow::Cow;
+
+use wgpu as w;
+
+use crate::{render::vertex::VertexLayout, stats::RenderCounterCells};
+
+use super::{overlay::OverlayVertex, reflect};
+
+/// ビューポート全体を覆う三角形
+const TRIANGLE: [[f32; 2]; 3] = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// [`ClearPipeline`] で行う消去
+pub enum ClearRequest {
+    /// 色と深度を消去する
+    Color(w::Color),
+    /// 深度だけを消去する
+    DepthOnly,
+}
+
+#[derive(Debug)]
+/// 描画先の一部だけを消去するためのパイプライン
+///
+/// [`w::LoadOp::Clear`] は描画先全体を消去してしまうため、ビューポートを覆う三角形を描いて消去する。
+pub struct ClearPipeline {
+    color: w::RenderPipeline,
+    depth_only: w::RenderPipeline,
+    buffer: w::Buffer,
+    capacity: usize,
+    requests: Vec<ClearRequest>,
+}
+
+impl ClearPipeline {
+    pub fn new(device: &w::Device, surface_format: w::TextureFormat) -> anyhow::Result<Self> {
+        let source = include_str!("./clear.wgsl");
+        let module = reflect::parse("clear.wgsl", source)?;
+        reflect::check_vertex_inputs(&module, "vs_main", &[OverlayVertex::DESC])?;
+        let shader = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some("clear.wgsl"),
+            source: w::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("clear render pipeline layout"),
+            bind_group_layouts: &[],
+            immediate_size: 0,
+        });
+
+        let create_pipeline = |label, write_mask| {
+            device.create_render_pipeline(&w::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: w::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[Some(OverlayVertex::DESC)],
+                },
+                fragment: Some(w::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(w::ColorTargetState {
+                        format: surface_format,
+                        blend: None,
+                        write_mask,
+                    })],
+                }),
+                primitive: w::PrimitiveState::default(),
+                depth_stencil: Some(w::DepthStencilState {
+                    format: w::TextureFormat::Depth32Float,
+                    depth_write_enabled: Some(true),
+                    depth_compare: Some(w::CompareFunction::Always),
+                    stencil: w::StencilState::default(),
+                    bias: w::DepthBiasState::default(),
+                }),
+                multisample: w::MultisampleState::default(),
+                cache: None,
+                multiview_mask: None,
+            })
+        };
+        let color = create_pipeline("clear color render pipeline", w::ColorWrites::ALL);
+        let depth_only = create_pipeline("clear depth render pipeline", w::ColorWrites::empty());
+
+        let capacity = 4;
+        Ok(Self {
+            color,
+            depth_only,
+            buffer: create_buffer(device, capacity),
+            capacity,
+            requests: Vec::new(),
+        })
+    }
+
+    /// このフレームで行う消去を GPU に送信する。`requests` の添字が [`ClearPipeline::render`] の `index` になる。
+    pub fn prepare(
+        &mut self,
+        device: &w::Device,
+        queue: &w::Queue,
+        counters: &RenderCounterCells,
+        requests: Vec<ClearRequest>,
+    ) {
+        if requests.len() > self.capacity {
+            self.capacity = requests.len().next_power_of_two();
+            self.buffer = create_buffer(device, self.capacity);
+        }
+        let vertices: Vec<_> = requests
+            .iter()
+            .flat_map(|request| {
+                let color = match request {
+                    ClearRequest::Color(c) => [c.r as f32, c.g as f32, c.b as f32, c.a as f32],
+                    ClearRequest::DepthOnly => [0.0; 4],
+                };
+                TRIANGLE.map(|position| OverlayVertex { position, color })
+            })
+            .collect();
+        if !vertices.is_empty() {
+            let data = bytemuck::cast_slice(&vertices);
+            queue.write_buffer(&self.buffer, 0, data);
+            counters.add_uploaded_bytes(data.len() as u64);
+        }
+        self.requests = requests;
+    }
+
+    /// [`ClearPipeline::prepare`] で送信した `index` 番目の消去を行う。ビューポートは呼び出し側で設定する。
+    pub fn render(&self, rp: &mut w::RenderPass<'_>, counters: &RenderCounterCells, index: usize) {
+        let Some(request) = self.requests.get(index) else {
+            return;
+        };
+        let pipeline = match request {
+            ClearRequest::Color(_) => &self.color,
+            ClearRequest::DepthOnly => &self.depth_only,
+        };
+        let start = (index * TRIANGLE.len()) as u32;
+        rp.set_pipeline(pipeline);
+        rp.set_vertex_buffer(0, self.buffer.slice(..));
+        rp.draw(start..start + TRIANGLE.len() as u32, 0..1);
+        counters.add_draw_calls(1);
+    }
+}
+
+fn create_buffer(device: &w::Device, capacity: usize) -> w::Buffer {
+    device.create_buffer(&w::BufferDescriptor {
+        label: Some("clear vertex buffer"),
+        size: (capacity * TRIANGLE.len() * size_of::<OverlayVertex>()) as w::BufferAddress,
+        usage: w::BufferUsages::VERTEX | w::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
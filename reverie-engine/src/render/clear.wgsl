@@ -0,0 +1,26 @@
+const LOC_POSITION: u32 = 0;
+const LOC_COLOR: u32 = 1;
+
+struct VertexInput {
+  @location(LOC_POSITION) position: vec2<f32>,
+  @location(LOC_COLOR) color: vec4<f32>
+}
+
+struct VertexOutput {
+  @location(0) color: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+  var out: VertexOutput;
+  out.color = in.color;
+  // 遠クリップ面に置いて深度を 1.0 で上書きする
+  out.position = vec4<f32>(in.position, 1.0, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return in.color;
+}
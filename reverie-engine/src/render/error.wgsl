@@ -0,0 +1,14 @@
+// シェーダーを使えないマテリアルのメッシュをマゼンタで描画する
+#include "vertex.wgsl"
+#include "camera.wgsl"
+#include "object.wgsl"
+
+@vertex
+fn vs_main(in: VertexInput) -> @builtin(position) vec4<f32> {
+  return camera.view_projection * object.model * vec4<f32>(in.position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+  return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+}
@@ -0,0 +1,30 @@
+#include "bindings.wgsl"
+
+// Rust の CameraUniform と同じ
+struct Camera {
+  view: mat4x4<f32>,
+  projection: mat4x4<f32>,
+  view_projection: mat4x4<f32>,
+  inverse_view: mat4x4<f32>,
+  inverse_projection: mat4x4<f32>,
+  inverse_view_projection: mat4x4<f32>,
+  eye: vec3<f32>,
+  time: f32,
+  viewport_size: vec2<f32>,
+  pixel_snap: f32
+}
+
+@group(GROUP_CAMERA)
+@binding(BINDING_CAMERA)
+var<uniform> camera: Camera;
+
+// ピクセルパーフェクトのカメラでは、頂点を仮想解像度のピクセルの境界に揃える
+fn snap_to_pixel(position: vec4<f32>) -> vec4<f32> {
+  if camera.pixel_snap < 0.5 {
+    return position;
+  }
+  let ndc = position.xy / position.w;
+  let pixel = round((ndc * 0.5 + 0.5) * camera.viewport_size);
+  let snapped = pixel / camera.viewport_size * 2.0 - 1.0;
+  return vec4<f32>(snapped * position.w, position.zw);
+}
@@ -0,0 +1,11 @@
+#include "bindings.wgsl"
+
+// Rust の ObjectUniform と同じ
+struct Object {
+  model: mat4x4<f32>,
+  uv_rect: vec4<f32>
+}
+
+@group(GROUP_OBJECT)
+@binding(BINDING_OBJECT)
+var<uniform> object: Object;
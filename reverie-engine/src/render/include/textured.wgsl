@@ -0,0 +1,31 @@
+#include "bindings.wgsl"
+
+// テクスチャに頂点の色を掛けて描画するフラグメントシェーダー
+struct VertexOutput {
+  @location(0) uv: vec2<f32>,
+  @location(1) color: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@group(GROUP_TEXTURE)
+@binding(BINDING_TEXTURE)
+var tex: texture_2d<f32>;
+
+@group(GROUP_TEXTURE)
+@binding(BINDING_SAMPLER)
+var samp: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return textureSample(tex, samp, in.uv) * in.color;
+}
+
+// 不透明なものを描画する。アルファの小さいピクセルは捨てる。
+@fragment
+fn fs_opaque(in: VertexOutput) -> @location(0) vec4<f32> {
+  let color = textureSample(tex, samp, in.uv) * in.color;
+  if color.a < 0.5 {
+    discard;
+  }
+  return vec4<f32>(color.rgb, 1.0);
+}
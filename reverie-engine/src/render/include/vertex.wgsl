@@ -0,0 +1,9 @@
+#include "bindings.wgsl"
+
+// Rust の Vertex と同じ
+struct VertexInput {
+  @location(LOC_VERTEX) position: vec3<f32>,
+  @location(LOC_UV) uv: vec2<f32>,
+  @location(LOC_NORMAL) normal: vec3<f32>,
+  @location(LOC_COLOR) color: vec4<f32>
+}
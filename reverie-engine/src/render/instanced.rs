@@ -0,0 +1,298 @@
+use std::{borrow::Cow, ops::Range};
+
+use slotmap::SecondaryMap;
+use wgpu::{self as w, util::DeviceExt};
+
+use crate::{
+    model::{BlendMode, Vertex},
+    render::vertex::VertexLayout,
+    scene::{GameObjectKey, Scene, SpriteInstance},
+    stats::RenderCounterCells,
+    texture::{TextureIndex, TextureRegistry},
+};
+
+use super::{
+    blend::{self, BlendPipelines, RenderPhase},
+    buffer, reflect, sprite, wgsl,
+};
+
+pub static LOC_MODEL_0: u32 = 4;
+pub static LOC_MODEL_1: u32 = 5;
+pub static LOC_MODEL_2: u32 = 6;
+pub static LOC_MODEL_3: u32 = 7;
+pub static LOC_UV_RECT: u32 = 8;
+pub static LOC_TINT: u32 = 9;
+pub static LOC_LAYER: u32 = 10;
+
+impl VertexLayout for SpriteInstance {
+    const DESC: wgpu::VertexBufferLayout<'static> = w::VertexBufferLayout {
+        array_stride: size_of::<Self>() as w::BufferAddress,
+        step_mode: w::VertexStepMode::Instance,
+        attributes: &w::vertex_attr_array![
+            LOC_MODEL_0 => Float32x4,
+            LOC_MODEL_1 => Float32x4,
+            LOC_MODEL_2 => Float32x4,
+            LOC_MODEL_3 => Float32x4,
+            LOC_UV_RECT => Float32x4,
+            LOC_TINT => Float32x4,
+            LOC_LAYER => Float32,
+        ],
+    };
+}
+
+// セットごとにバッファの途中へ書き込むため、インスタンスの大きさはコピーの単位の倍数にする
+const _: () =
+    assert!((size_of::<SpriteInstance>() as u64).is_multiple_of(w::COPY_BUFFER_ALIGNMENT));
+
+/// 原点を中心とする 1x1 の四角形。左上、右上、左下、右下の順。
+const QUAD: [Vertex; 4] = [
+    quad_vertex(-0.5, 0.5, 0.0, 0.0),
+    quad_vertex(0.5, 0.5, 1.0, 0.0),
+    quad_vertex(-0.5, -0.5, 0.0, 1.0),
+    quad_vertex(0.5, -0.5, 1.0, 1.0),
+];
+
+const QUAD_INDICES: [u16; 6] = [0, 3, 1, 0, 2, 3];
+
+/// インスタンスバッファの最初の容量
+const INITIAL_CAPACITY: usize = 1024;
+
+const fn quad_vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+    Vertex {
+        position: [x, y, 0.0],
+        uv: [u, v],
+        normal: [0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// GPU に送信した 1 つの [`SpriteInstances`](crate::scene::SpriteInstances)
+struct UploadedSet {
+    key: GameObjectKey,
+    /// 送信したときの [`SpriteInstances::revision`](crate::scene::SpriteInstances::revision)
+    revision: u64,
+    /// インスタンスバッファでの範囲
+    instances: Range<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 1 つの [`SpriteInstances`](crate::scene::SpriteInstances) の描画
+struct InstanceDraw {
+    texture: TextureIndex,
+    blend: BlendMode,
+    instances: Range<u32>,
+}
+
+#[derive(Debug)]
+/// [`SpriteInstances`](crate::scene::SpriteInstances) をインスタンシングで描画するパイプライン
+///
+/// 四角形の頂点は固定で、インスタンスごとの変換行列などを別のバッファで渡す。
+/// インスタンスはワールド座標系で指定されるため、すべてのカメラで 1 つのバッファを共有する。
+/// [`SpriteInstances::revision`](crate::scene::SpriteInstances::revision) が変わったセットだけを送信し直す。
+pub struct InstancedSpriteRenderer {
+    pipelines: BlendPipelines,
+    quad_vertex_buffer: w::Buffer,
+    quad_index_buffer: w::Buffer,
+    instance_buffer: w::Buffer,
+    /// インスタンスバッファに並べたセット
+    uploaded: Vec<UploadedSet>,
+    draws: Vec<InstanceDraw>,
+    /// カメラごと、[`RenderPhase::group`] ごとに描画する `draws` の添字
+    passes: Vec<Vec<Vec<usize>>>,
+}
+
+impl InstancedSpriteRenderer {
+    /// * `texture_bind_group_layout`, `camera_bind_group_layout`: [`sprite::SpriteRenderPipeline`] と同じレイアウト
+    pub fn new(
+        device: &w::Device,
+        surface_format: w::TextureFormat,
+        texture_bind_group_layout: &w::BindGroupLayout,
+        camera_bind_group_layout: &w::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let source = wgsl::preprocess("sprite.wgsl", include_str!("./sprite.wgsl"), &["INSTANCED"]);
+        let module = reflect::parse("sprite.wgsl (INSTANCED)", &source)?;
+        reflect::check_vertex_inputs(&module, "vs_main", &[Vertex::DESC, SpriteInstance::DESC])?;
+        let shader = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some("sprite.wgsl (INSTANCED)"),
+            source: w::ShaderSource::Wgsl(Cow::Owned(source)),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("instanced sprite render pipeline layout"),
+            bind_group_layouts: &[
+                Some(texture_bind_group_layout),
+                Some(camera_bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+
+        let pipelines = BlendPipelines::new(|blend| {
+            device.create_render_pipeline(&w::RenderPipelineDescriptor {
+                label: Some(&format!("instanced sprite render pipeline ({blend:?})")),
+                layout: Some(&pipeline_layout),
+                vertex: w::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[Some(Vertex::DESC), Some(SpriteInstance::DESC)],
+                },
+                fragment: Some(w::FragmentState {
+                    module: &shader,
+                    entry_point: Some(blend::fragment_entry_point(blend)),
+                    compilation_options: Default::default(),
+                    targets: &[Some(blend::color_target(surface_format, blend))],
+                }),
+                primitive: w::PrimitiveState {
+                    topology: w::PrimitiveTopology::TriangleList,
+                    front_face: w::FrontFace::Ccw,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(blend::depth_stencil(blend)),
+                multisample: w::MultisampleState::default(),
+                cache: None,
+                multiview_mask: None,
+            })
+        });
+
+        let quad_vertex_buffer = device.create_buffer_init(&w::util::BufferInitDescriptor {
+            label: Some("instanced sprite quad vertex buffer"),
+            contents: bytemuck::cast_slice(&QUAD),
+            usage: w::BufferUsages::VERTEX,
+        });
+        let quad_index_buffer = device.create_buffer_init(&w::util::BufferInitDescriptor {
+            label: Some("instanced sprite quad index buffer"),
+            contents: bytemuck::cast_slice(&QUAD_INDICES),
+            usage: w::BufferUsages::INDEX,
+        });
+
+        Ok(Self {
+            pipelines,
+            quad_vertex_buffer,
+            quad_index_buffer,
+            instance_buffer: create_instance_buffer(device, INITIAL_CAPACITY),
+            uploaded: Vec::new(),
+            draws: Vec::new(),
+            passes: Vec::new(),
+        })
+    }
+
+    /// このフレームで描画するインスタンスを GPU に送信する
+    ///
+    /// * `passes`: カメラごとの [`DrawList::instances`](crate::scene::DrawList::instances)。
+    ///   添字が [`InstancedSpriteRenderer::render`] の `pass` になる。
+    pub fn prepare(
+        &mut self,
+        device: &w::Device,
+        queue: &w::Queue,
+        counters: &RenderCounterCells,
+        scene: &Scene,
+        passes: Vec<Vec<Vec<GameObjectKey>>>,
+    ) {
+        let mut uploaded = Vec::with_capacity(self.uploaded.len());
+        let mut len = 0;
+        let mut draw_indices = SecondaryMap::new();
+        self.draws.clear();
+        for (key, set) in &scene.sprite_instances {
+            if set.instances().is_empty() {
+                continue;
+            }
+            draw_indices.insert(key, self.draws.len());
+            let start = len;
+            len += set.instances().len() as u32;
+            uploaded.push(UploadedSet {
+                key,
+                revision: set.revision(),
+                instances: start..len,
+            });
+            self.draws.push(InstanceDraw {
+                texture: set.texture,
+                blend: set.blend,
+                instances: start..len,
+            });
+        }
+        self.passes = passes
+            .into_iter()
+            .map(|groups| {
+                groups
+                    .into_iter()
+                    .map(|keys| {
+                        keys.into_iter()
+                            .filter_map(|key| draw_indices.get(key).copied())
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let resized =
+            buffer::grow::<SpriteInstance>(&mut self.instance_buffer, len as usize, |capacity| {
+                create_instance_buffer(device, capacity)
+            });
+        // 位置と内容が前のフレームと同じセットは送信しない
+        for (i, set) in uploaded.iter().enumerate() {
+            if !resized && self.uploaded.get(i) == Some(set) {
+                continue;
+            }
+            let instances = scene.sprite_instances[set.key].instances();
+            let offset = u64::from(set.instances.start) * size_of::<SpriteInstance>() as u64;
+            queue.write_buffer(
+                &self.instance_buffer,
+                offset,
+                bytemuck::cast_slice(instances),
+            );
+            counters.add_uploaded_bytes(size_of_val(instances) as u64);
+        }
+        self.uploaded = uploaded;
+    }
+
+    /// [`InstancedSpriteRenderer::prepare`] で送信した `pass` 番目のカメラのインスタンスのうち、`phase` の区切りのものを描画する
+    pub fn render(
+        &self,
+        rp: &mut w::RenderPass<'_>,
+        textures: &TextureRegistry,
+        counters: &RenderCounterCells,
+        camera_bind_group: &w::BindGroup,
+        pass: usize,
+        phase: RenderPhase,
+    ) {
+        let Some(draws) = self
+            .passes
+            .get(pass)
+            .and_then(|groups| groups.get(phase.group()))
+        else {
+            return;
+        };
+        if draws.is_empty() {
+            return;
+        }
+        rp.set_bind_group(sprite::GROUP_CAMERA, camera_bind_group, &[]);
+        rp.set_vertex_buffer(0, self.quad_vertex_buffer.slice(..));
+        rp.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        rp.set_index_buffer(self.quad_index_buffer.slice(..), w::IndexFormat::Uint16);
+        for draw in draws.iter().map(|&i| &self.draws[i]) {
+            let bind_group = match textures.get_bind_group(draw.texture.into()) {
+                Ok(bind_group) => bind_group,
+                Err(err) => {
+                    tracing::warn!(?err, texture = ?draw.texture, "skipped sprite instances");
+                    continue;
+                }
+            };
+            rp.set_pipeline(self.pipelines.get(draw.blend));
+            rp.set_bind_group(sprite::GROUP_TEXTURE, bind_group, &[]);
+            rp.draw_indexed(0..QUAD_INDICES.len() as u32, 0, draw.instances.clone());
+            counters.add_sprites_drawn(draw.instances.len() as u64);
+            counters.add_draw_calls(1);
+        }
+    }
+}
+
+fn create_instance_buffer(device: &w::Device, capacity: usize) -> w::Buffer {
+    device.create_buffer(&w::BufferDescriptor {
+        label: Some("sprite instance buffer"),
+        size: buffer::buffer_size::<SpriteInstance>(capacity),
+        usage: w::BufferUsages::VERTEX | w::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
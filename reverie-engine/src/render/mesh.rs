@@ -0,0 +1,767 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use encase::ShaderType;
+use slotmap::SecondaryMap;
+use wgpu::{self as w, util::DeviceExt};
+
+use crate::{
+    model::{BlendMode, Material, MaterialKey, Mesh, MeshKey, Vertex},
+    render::vertex::VertexLayout,
+    scene::{Scene, VisibleMesh},
+    shader::{Shader, ShaderKey},
+    stats::RenderCounterCells,
+    texture::{TextureId, TextureIndex},
+};
+
+use super::{
+    BindingId,
+    blend::{self, RenderPhase},
+    reflect, sprite,
+    uniform::ObjectUniform,
+};
+
+pub static GROUP_OBJECT: u32 = 2;
+pub static BINDING_OBJECT: BindingId = BindingId::new(GROUP_OBJECT, 0);
+pub static GROUP_MATERIAL: u32 = 3;
+pub static BINDING_MATERIAL_PARAMS: BindingId = BindingId::new(GROUP_MATERIAL, 0);
+pub static BINDING_MATERIAL_SAMPLER: BindingId = BindingId::new(GROUP_MATERIAL, 1);
+
+/// オブジェクトのユニフォームバッファの最初の容量 (描画の数)
+const INITIAL_CAPACITY: u64 = 64;
+
+#[derive(Debug)]
+/// GPU に送信したメッシュ
+///
+/// 送信したときの [`Mesh::revision`] を覚えておき、[`Mesh`] が書き換えられたときだけ送信し直す。
+/// 頂点を CPU 側に持たないため、[`VertexIndexBuffer`](super::buffer::VertexIndexBuffer) は使わず、書き換えられたらバッファごと作り直す。
+struct GpuMesh {
+    vertex_buffer: w::Buffer,
+    index_buffer: w::Buffer,
+    index_count: u32,
+    revision: u64,
+}
+
+impl GpuMesh {
+    fn new(device: &w::Device, mesh: &Mesh) -> Self {
+        let vertex_buffer = device.create_buffer_init(&w::util::BufferInitDescriptor {
+            label: Some(&format!("{} vertex buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&mesh.vertices),
+            usage: w::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&w::util::BufferInitDescriptor {
+            label: Some(&format!("{} index buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&mesh.indices),
+            usage: w::BufferUsages::INDEX,
+        });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: mesh.indices.len() as u32,
+            revision: mesh.revision(),
+        }
+    }
+
+    const fn is_up_to_date(&self, mesh: &Mesh) -> bool {
+        self.revision == mesh.revision()
+    }
+
+    fn size(&self) -> u64 {
+        self.vertex_buffer.size() + self.index_buffer.size()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// マテリアルのバインドグループのレイアウト
+struct MaterialLayout {
+    /// テクスチャ以外のパラメータがあるかどうか
+    uniform: bool,
+    /// テクスチャのパラメータの数
+    textures: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// パイプラインで使うシェーダー
+enum PipelineShader {
+    /// 組み込みのシェーダー
+    Default,
+    /// シェーダーを使えないマテリアルをマゼンタで描画するシェーダー
+    Error,
+    /// [`Scene::shaders`] のシェーダーと、その [`Shader::generation`]
+    Custom(ShaderKey, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// パイプラインを使い回すためのキー
+struct PipelineKey {
+    shader: PipelineShader,
+    blend: BlendMode,
+    cull_mode: Option<w::Face>,
+    depth_test: bool,
+    depth_write: bool,
+    layout: MaterialLayout,
+}
+
+#[derive(Debug, PartialEq)]
+/// マテリアルのバインドグループを作り直す必要があるかどうかを判定するための値
+struct MaterialSignature {
+    pipeline: PipelineKey,
+    /// パラメータの名前とユニフォームでのアラインメントと大きさ
+    params: Vec<(String, Option<(u32, u32)>)>,
+    /// テクスチャのパラメータと、その [`TextureRegistry::generation`](crate::texture::TextureRegistry::generation)
+    textures: Vec<(TextureId, u64)>,
+}
+
+impl MaterialSignature {
+    fn new(material: &Material, scene: &Scene) -> Self {
+        let shader = material.shader.map_or(PipelineShader::Default, |key| {
+            let generation = scene.shaders.map.get(key).map_or(0, Shader::generation);
+            PipelineShader::Custom(key, generation)
+        });
+        Self {
+            pipeline: PipelineKey {
+                shader,
+                blend: material.blend,
+                cull_mode: material.cull_mode,
+                depth_test: material.depth_test,
+                depth_write: material.writes_depth(),
+                layout: MaterialLayout {
+                    uniform: material.uniform_size() > 0,
+                    textures: material.textures().count(),
+                },
+            },
+            params: material
+                .params
+                .iter()
+                .map(|(name, param)| (name.clone(), param.uniform_layout()))
+                .collect(),
+            textures: material
+                .textures()
+                .map(|(_, texture)| (texture, scene.textures.generation(texture)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// GPU に送信したマテリアル
+struct GpuMaterial {
+    signature: MaterialSignature,
+    /// テクスチャが GPU にない場合は `None`。`None` の間は毎フレーム作り直しを試みる。
+    bound: Option<BoundMaterial>,
+}
+
+#[derive(Debug)]
+struct BoundMaterial {
+    /// シェーダーとパラメータが合わない場合は [`PipelineShader::Error`] になる
+    pipeline: PipelineKey,
+    /// パラメータのユニフォームバッファ。テクスチャ以外のパラメータがない場合は `None`。
+    buffer: Option<w::Buffer>,
+    bind_group: w::BindGroup,
+    /// 最後に GPU に送信したパラメータ
+    uniform: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 1 つのメッシュの描画
+struct MeshDraw {
+    mesh: MeshKey,
+    material: MaterialKey,
+    pipeline: PipelineKey,
+    texture: TextureIndex,
+    /// オブジェクトのユニフォームバッファでのオフセット
+    offset: u32,
+}
+
+#[derive(Debug)]
+/// マテリアルごとのパイプラインのキャッシュ
+struct PipelineCache {
+    /// [`Material::shader`] が `None` のときのシェーダー
+    default_shader: Shader,
+    /// シェーダーを使えないときのシェーダー
+    error_shader: Shader,
+    surface_format: w::TextureFormat,
+    texture_bind_group_layout: w::BindGroupLayout,
+    camera_bind_group_layout: w::BindGroupLayout,
+    object_bind_group_layout: w::BindGroupLayout,
+    /// マテリアル以外のバインドグループのレイアウトのエントリ。シェーダーがこれに合うかどうかを調べる。
+    bind_group_entries: [Vec<w::BindGroupLayoutEntry>; 3],
+    material_bind_group_layouts: HashMap<MaterialLayout, w::BindGroupLayout>,
+    /// シェーダーで作成できなかったパイプラインはエラーのシェーダーで作る。それにも失敗した場合は `None` にして、作り直さない。
+    pipelines: HashMap<PipelineKey, Option<w::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    fn material_bind_group_layout(
+        &mut self,
+        device: &w::Device,
+        layout: MaterialLayout,
+    ) -> &w::BindGroupLayout {
+        self.material_bind_group_layouts
+            .entry(layout)
+            .or_insert_with(|| create_material_bind_group_layout(device, layout))
+    }
+
+    fn shader<'a>(
+        &'a self,
+        scene: &'a Scene,
+        shader: PipelineShader,
+    ) -> anyhow::Result<&'a Shader> {
+        match shader {
+            PipelineShader::Default => Ok(&self.default_shader),
+            PipelineShader::Error => Ok(&self.error_shader),
+            PipelineShader::Custom(key, _) => scene
+                .shaders
+                .map
+                .get(key)
+                .ok_or_else(|| anyhow::anyhow!("no such shader: {key:?}")),
+        }
+    }
+
+    /// `key` のパイプラインがなければ作る
+    fn ensure(&mut self, device: &w::Device, scene: &Scene, key: &PipelineKey) {
+        if self.pipelines.contains_key(key) {
+            return;
+        }
+        let material_bind_group_layout =
+            self.material_bind_group_layout(device, key.layout).clone();
+        let pipeline = self
+            .shader(scene, key.shader)
+            .ok()
+            .and_then(|shader| self.create(device, shader, key, &material_bind_group_layout))
+            .or_else(|| self.create(device, &self.error_shader, key, &material_bind_group_layout));
+        self.pipelines.insert(*key, pipeline);
+    }
+
+    /// 読み込み直されたシェーダーと削除されたシェーダーのパイプラインを取り除く
+    fn retain_current_shaders(&mut self, scene: &Scene) {
+        self.pipelines.retain(|key, _| match key.shader {
+            PipelineShader::Custom(shader, generation) => scene
+                .shaders
+                .map
+                .get(shader)
+                .is_some_and(|shader| shader.generation() == generation),
+            PipelineShader::Default | PipelineShader::Error => true,
+        });
+    }
+
+    fn get(&self, key: &PipelineKey) -> Option<&w::RenderPipeline> {
+        self.pipelines.get(key)?.as_ref()
+    }
+
+    /// パイプラインを作る。シェーダーがパイプラインと合わない場合は警告を出して `None` を返す。
+    ///
+    /// シェーダーは [`Shader::from_wgsl`] で naga の検証を済ませ、ここで頂点属性とバインドグループを照らし合わせるので、
+    /// 描画スレッドを止めて wgpu の検証の結果を待つことはしない。
+    fn create(
+        &self,
+        device: &w::Device,
+        shader: &Shader,
+        key: &PipelineKey,
+        material_bind_group_layout: &w::BindGroupLayout,
+    ) -> Option<w::RenderPipeline> {
+        if let Err(err) = self.check(shader) {
+            tracing::warn!(
+                err = format!("{err:#}"),
+                shader = shader.name(),
+                "failed: mesh shader does not match pipeline"
+            );
+            return None;
+        }
+        let pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("mesh render pipeline layout"),
+            bind_group_layouts: &[
+                Some(&self.texture_bind_group_layout),
+                Some(&self.camera_bind_group_layout),
+                Some(&self.object_bind_group_layout),
+                Some(material_bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+
+        let mut fragment_entry_point = blend::fragment_entry_point(key.blend);
+        if shader.entry_point(fragment_entry_point).is_none() {
+            fragment_entry_point = "fs_main";
+        }
+        let mut depth_stencil = blend::depth_stencil(key.blend);
+        depth_stencil.depth_write_enabled = Some(key.depth_write);
+        if !key.depth_test {
+            depth_stencil.depth_compare = Some(w::CompareFunction::Always);
+        }
+
+        let module = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some(shader.name()),
+            source: w::ShaderSource::Wgsl(Cow::Borrowed(shader.wgsl())),
+        });
+        let pipeline = device.create_render_pipeline(&w::RenderPipelineDescriptor {
+            label: Some(&format!(
+                "mesh render pipeline ({} {:?})",
+                shader.name(),
+                key.blend
+            )),
+            layout: Some(&pipeline_layout),
+            vertex: w::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[Some(Vertex::DESC)],
+            },
+            fragment: Some(w::FragmentState {
+                module: &module,
+                entry_point: Some(fragment_entry_point),
+                compilation_options: Default::default(),
+                targets: &[Some(blend::color_target(self.surface_format, key.blend))],
+            }),
+            primitive: w::PrimitiveState {
+                topology: w::PrimitiveTopology::TriangleList,
+                front_face: w::FrontFace::Ccw,
+                cull_mode: key.cull_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil),
+            multisample: w::MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+        Some(pipeline)
+    }
+
+    /// シェーダーの頂点属性とマテリアル以外のバインドグループが Rust 側と合うかどうかを調べる
+    fn check(&self, shader: &Shader) -> anyhow::Result<()> {
+        reflect::check_vertex_inputs(shader.module(), "vs_main", &[Vertex::DESC])?;
+        for (group, entries) in (0..).zip(&self.bind_group_entries) {
+            reflect::check_bind_group(shader.module(), group, entries)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// [`ModelComponent`](crate::scene::ModelComponent) のメッシュを描画するパイプライン
+///
+/// メッシュの頂点とインデックスは [`Scene::meshes`] に合わせて GPU に置いておく。
+/// オブジェクトの変換行列は 1 つのユニフォームバッファに並べ、動的オフセットで描画ごとに切り替える。
+/// パイプラインはマテリアルのシェーダーと描画の設定の組ごとに、バインドグループはマテリアルごとに作って使い回す。
+pub struct MeshRenderer {
+    pipelines: PipelineCache,
+    /// テクスチャのパラメータに使うサンプラー
+    sampler: w::Sampler,
+    materials: SecondaryMap<MaterialKey, GpuMaterial>,
+    object_buffer: w::Buffer,
+    object_bind_group: w::BindGroup,
+    /// 最後に GPU に送信したオブジェクトのユニフォームバッファの内容
+    objects: Vec<u8>,
+    meshes: SecondaryMap<MeshKey, GpuMesh>,
+    /// カメラごと、[`RenderPhase::group`] ごとの描画
+    passes: Vec<Vec<Vec<MeshDraw>>>,
+}
+
+impl MeshRenderer {
+    /// * `texture_bind_group_layout`, `camera_bind_group_layout`: [`sprite::SpriteRenderPipeline`] と同じレイアウト
+    /// * `sampler`: マテリアルのテクスチャのパラメータに使うサンプラー
+    pub fn new(
+        device: &w::Device,
+        surface_format: w::TextureFormat,
+        texture_bind_group_layout: &w::BindGroupLayout,
+        camera_bind_group_layout: &w::BindGroupLayout,
+        sampler: &w::Sampler,
+    ) -> anyhow::Result<Self> {
+        let default_shader = Shader::from_wgsl("mesh.wgsl", include_str!("./mesh.wgsl"))?;
+        let error_shader = Shader::from_wgsl("error.wgsl", include_str!("./error.wgsl"))?;
+
+        // バインドグループのレイアウトは mesh.wgsl から求める
+        let module = default_shader.module();
+        let mut object_entries = reflect::bind_group_layout_entries(module, GROUP_OBJECT)?;
+        reflect::check_uniform_size(
+            &object_entries,
+            BINDING_OBJECT.binding,
+            ObjectUniform::min_size(),
+        )?;
+        for entry in &mut object_entries {
+            if let w::BindingType::Buffer {
+                has_dynamic_offset, ..
+            } = &mut entry.ty
+            {
+                *has_dynamic_offset = true;
+            }
+        }
+        let bind_group_entries = [
+            reflect::bind_group_layout_entries(module, sprite::GROUP_TEXTURE)?,
+            reflect::bind_group_layout_entries(module, sprite::GROUP_CAMERA)?,
+            object_entries,
+        ];
+        let object_bind_group_layout =
+            device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+                label: Some("object bind group layout"),
+                entries: &bind_group_entries[2],
+            });
+
+        let object_buffer = create_object_buffer(device, INITIAL_CAPACITY * object_stride(device));
+        let object_bind_group =
+            create_object_bind_group(device, &object_bind_group_layout, &object_buffer);
+
+        Ok(Self {
+            pipelines: PipelineCache {
+                default_shader,
+                error_shader,
+                surface_format,
+                texture_bind_group_layout: texture_bind_group_layout.clone(),
+                camera_bind_group_layout: camera_bind_group_layout.clone(),
+                object_bind_group_layout,
+                bind_group_entries,
+                material_bind_group_layouts: HashMap::new(),
+                pipelines: HashMap::new(),
+            },
+            sampler: sampler.clone(),
+            materials: SecondaryMap::new(),
+            object_buffer,
+            object_bind_group,
+            objects: Vec::new(),
+            meshes: SecondaryMap::new(),
+            passes: Vec::new(),
+        })
+    }
+
+    /// [`Scene::meshes`] と [`Scene::materials`] を GPU に送信し、このフレームで描画するオブジェクトの情報を送信する
+    ///
+    /// * `passes`: カメラごとの [`DrawList::meshes`](crate::scene::DrawList::meshes)。
+    ///   添字が [`MeshRenderer::render`] の `pass` になる。
+    pub fn prepare(
+        &mut self,
+        device: &w::Device,
+        queue: &w::Queue,
+        counters: &RenderCounterCells,
+        scene: &Scene,
+        passes: Vec<Vec<Vec<VisibleMesh>>>,
+    ) {
+        self.sync_meshes(device, counters, scene);
+        self.sync_materials(device, queue, counters, scene);
+
+        let stride = object_stride(device);
+        let mut objects =
+            encase::DynamicUniformBuffer::new_with_alignment(Vec::<u8>::new(), stride);
+        self.passes = passes
+            .into_iter()
+            .map(|groups| {
+                groups
+                    .into_iter()
+                    .map(|visible| self.mesh_draws(scene, &mut objects, visible))
+                    .collect()
+            })
+            .collect();
+
+        let objects = objects.into_inner();
+        let resized = objects.len() as u64 > self.object_buffer.size();
+        if resized {
+            let capacity = (objects.len() as u64 / stride).next_power_of_two() * stride;
+            self.object_buffer = create_object_buffer(device, capacity);
+            self.object_bind_group = create_object_bind_group(
+                device,
+                &self.pipelines.object_bind_group_layout,
+                &self.object_buffer,
+            );
+        }
+        if resized || objects != self.objects {
+            queue.write_buffer(&self.object_buffer, 0, &objects);
+            counters.add_uploaded_bytes(objects.len() as u64);
+        }
+        self.objects = objects;
+    }
+
+    /// 描画できるメッシュのオブジェクトのユニフォームを `objects` に書き込み、描画の情報を返す
+    fn mesh_draws(
+        &self,
+        scene: &Scene,
+        objects: &mut encase::DynamicUniformBuffer<Vec<u8>>,
+        visible: Vec<VisibleMesh>,
+    ) -> Vec<MeshDraw> {
+        visible
+            .into_iter()
+            .filter(|(_, mesh, _, _)| self.meshes.contains_key(*mesh))
+            .filter_map(|(key, mesh, material_key, world)| {
+                let Some(material) = scene.materials.map.get(material_key) else {
+                    tracing::warn!(?key, material = ?material_key, "skipped mesh: no such material");
+                    return None;
+                };
+                // バインドグループを作れなかったマテリアルは sync_materials で警告済み
+                let pipeline = self.materials.get(material_key)?.bound.as_ref()?.pipeline;
+                self.pipelines.get(&pipeline)?;
+                let uv_rect = match scene.textures.get_uv(material.texture) {
+                    Ok(uv_rect) => uv_rect,
+                    Err(err) => {
+                        tracing::warn!(?err, ?key, "skipped mesh");
+                        return None;
+                    }
+                };
+                let offset = objects
+                    .write(&ObjectUniform::new(&world, uv_rect))
+                    .expect("failed: encode object uniform");
+                Some(MeshDraw {
+                    mesh,
+                    material: material_key,
+                    pipeline,
+                    texture: *material.texture.get_texture_index(),
+                    offset: offset as u32,
+                })
+            })
+            .collect()
+    }
+
+    /// マテリアルのバインドグループとパイプラインを用意し、書き換えられたパラメータを送信する
+    fn sync_materials(
+        &mut self,
+        device: &w::Device,
+        queue: &w::Queue,
+        counters: &RenderCounterCells,
+        scene: &Scene,
+    ) {
+        let materials = &scene.materials.map;
+        self.materials.retain(|key, _| materials.contains_key(key));
+        self.pipelines.retain_current_shaders(scene);
+        for (key, material) in materials {
+            let signature = MaterialSignature::new(material, scene);
+            let gpu = self.materials.get(key);
+            let changed = gpu.is_none_or(|gpu| gpu.signature != signature);
+            if changed || gpu.is_some_and(|gpu| gpu.bound.is_none()) {
+                // 作れなかったバインドグループは毎フレーム作り直しを試みるが、警告は変わったときだけ出す
+                let bound = self
+                    .bind_material(device, scene, key, material, signature.pipeline)
+                    .inspect_err(|err| {
+                        if changed {
+                            tracing::warn!(?err, material = ?key, "failed: bind material");
+                        }
+                    })
+                    .ok();
+                self.materials.insert(key, GpuMaterial { signature, bound });
+            }
+            let Some(bound) = self.materials[key].bound.as_mut() else {
+                continue;
+            };
+            let uniform = material.uniform_data();
+            if uniform != bound.uniform {
+                if let Some(buffer) = &bound.buffer {
+                    queue.write_buffer(buffer, 0, &uniform);
+                    counters.add_uploaded_bytes(uniform.len() as u64);
+                }
+                bound.uniform = uniform;
+            }
+        }
+    }
+
+    /// マテリアルのパラメータをシェーダーと照らし合わせ、パイプラインとバインドグループを作る
+    ///
+    /// シェーダーとパラメータが合わない場合は、エラーのシェーダーで描画する。
+    fn bind_material(
+        &mut self,
+        device: &w::Device,
+        scene: &Scene,
+        key: MaterialKey,
+        material: &Material,
+        mut pipeline: PipelineKey,
+    ) -> anyhow::Result<BoundMaterial> {
+        let views = material
+            .textures()
+            .map(|(_, texture)| scene.textures.get_view(texture))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if let Err(err) = self
+            .pipelines
+            .shader(scene, pipeline.shader)
+            .and_then(|shader| shader.check_material(material))
+        {
+            tracing::warn!(material = ?key, "failed: check material: {err:#}");
+            pipeline.shader = PipelineShader::Error;
+        }
+        self.pipelines.ensure(device, scene, &pipeline);
+
+        let buffer = (pipeline.layout.uniform).then(|| {
+            device.create_buffer(&w::BufferDescriptor {
+                label: Some("material uniform buffer"),
+                size: u64::from(material.uniform_size()),
+                usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        let mut entries = Vec::new();
+        if let Some(buffer) = &buffer {
+            entries.push(w::BindGroupEntry {
+                binding: BINDING_MATERIAL_PARAMS.binding,
+                resource: buffer.as_entire_binding(),
+            });
+        }
+        if !views.is_empty() {
+            entries.push(w::BindGroupEntry {
+                binding: BINDING_MATERIAL_SAMPLER.binding,
+                resource: w::BindingResource::Sampler(&self.sampler),
+            });
+        }
+        for (i, view) in views.into_iter().enumerate() {
+            entries.push(w::BindGroupEntry {
+                binding: material_texture_binding(i),
+                resource: w::BindingResource::TextureView(view),
+            });
+        }
+        let bind_group = device.create_bind_group(&w::BindGroupDescriptor {
+            label: Some("material bind group"),
+            layout: self
+                .pipelines
+                .material_bind_group_layout(device, pipeline.layout),
+            entries: &entries,
+        });
+        Ok(BoundMaterial {
+            pipeline,
+            buffer,
+            bind_group,
+            uniform: Vec::new(),
+        })
+    }
+
+    /// 削除されたメッシュを GPU から取り除き、追加されたメッシュと書き換えられたメッシュを送信する
+    fn sync_meshes(&mut self, device: &w::Device, counters: &RenderCounterCells, scene: &Scene) {
+        let meshes = &scene.meshes.map;
+        self.meshes
+            .retain(|key, _| meshes.get(key).is_some_and(|mesh| !mesh.indices.is_empty()));
+        for (key, mesh) in meshes {
+            if mesh.indices.is_empty()
+                || self
+                    .meshes
+                    .get(key)
+                    .is_some_and(|gpu| gpu.is_up_to_date(mesh))
+            {
+                continue;
+            }
+            let gpu = GpuMesh::new(device, mesh);
+            counters.add_uploaded_bytes(gpu.size());
+            self.meshes.insert(key, gpu);
+        }
+    }
+
+    /// [`MeshRenderer::prepare`] で送信した `pass` 番目のカメラのメッシュのうち、`phase` の区切りのものを描画する
+    pub fn render(
+        &self,
+        rp: &mut w::RenderPass<'_>,
+        scene: &Scene,
+        counters: &RenderCounterCells,
+        camera_bind_group: &w::BindGroup,
+        pass: usize,
+        phase: RenderPhase,
+    ) {
+        let Some(draws) = self
+            .passes
+            .get(pass)
+            .and_then(|groups| groups.get(phase.group()))
+        else {
+            return;
+        };
+        if draws.is_empty() {
+            return;
+        }
+        rp.set_bind_group(sprite::GROUP_CAMERA, camera_bind_group, &[]);
+        for draw in draws {
+            let bind_group = match scene.textures.get_bind_group(draw.texture.into()) {
+                Ok(bind_group) => bind_group,
+                Err(err) => {
+                    tracing::warn!(?err, texture = ?draw.texture, "skipped mesh");
+                    continue;
+                }
+            };
+            let (Some(pipeline), Some(material)) = (
+                self.pipelines.get(&draw.pipeline),
+                self.materials
+                    .get(draw.material)
+                    .and_then(|gpu| gpu.bound.as_ref()),
+            ) else {
+                continue;
+            };
+            let gpu = &self.meshes[draw.mesh];
+            rp.set_pipeline(pipeline);
+            rp.set_bind_group(sprite::GROUP_TEXTURE, bind_group, &[]);
+            rp.set_bind_group(GROUP_OBJECT, &self.object_bind_group, &[draw.offset]);
+            rp.set_bind_group(GROUP_MATERIAL, &material.bind_group, &[]);
+            rp.set_vertex_buffer(0, gpu.vertex_buffer.slice(..));
+            rp.set_index_buffer(gpu.index_buffer.slice(..), w::IndexFormat::Uint32);
+            rp.draw_indexed(0..gpu.index_count, 0, 0..1);
+            counters.add_meshes_drawn(1);
+            counters.add_draw_calls(1);
+        }
+    }
+}
+
+/// `index` 番目のテクスチャのパラメータのバインディング
+fn material_texture_binding(index: usize) -> u32 {
+    BINDING_MATERIAL_SAMPLER.binding + 1 + index as u32
+}
+
+fn create_material_bind_group_layout(
+    device: &w::Device,
+    layout: MaterialLayout,
+) -> w::BindGroupLayout {
+    let mut entries = Vec::new();
+    if layout.uniform {
+        entries.push(w::BindGroupLayoutEntry {
+            binding: BINDING_MATERIAL_PARAMS.binding,
+            visibility: w::ShaderStages::VERTEX_FRAGMENT,
+            ty: w::BindingType::Buffer {
+                ty: w::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+    }
+    if layout.textures > 0 {
+        entries.push(w::BindGroupLayoutEntry {
+            binding: BINDING_MATERIAL_SAMPLER.binding,
+            visibility: w::ShaderStages::VERTEX_FRAGMENT,
+            ty: w::BindingType::Sampler(w::SamplerBindingType::Filtering),
+            count: None,
+        });
+    }
+    entries.extend((0..layout.textures).map(|i| w::BindGroupLayoutEntry {
+        binding: material_texture_binding(i),
+        visibility: w::ShaderStages::VERTEX_FRAGMENT,
+        ty: w::BindingType::Texture {
+            sample_type: w::TextureSampleType::Float { filterable: true },
+            view_dimension: w::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }));
+    device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+        label: Some("material bind group layout"),
+        entries: &entries,
+    })
+}
+
+/// オブジェクトのユニフォームバッファで 1 つの描画が占める大きさ
+fn object_stride(device: &w::Device) -> u64 {
+    ObjectUniform::min_size().get().next_multiple_of(u64::from(
+        device.limits().min_uniform_buffer_offset_alignment,
+    ))
+}
+
+fn create_object_buffer(device: &w::Device, size: u64) -> w::Buffer {
+    device.create_buffer(&w::BufferDescriptor {
+        label: Some("object uniform buffer"),
+        size,
+        usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_object_bind_group(
+    device: &w::Device,
+    layout: &w::BindGroupLayout,
+    buffer: &w::Buffer,
+) -> w::BindGroup {
+    device.create_bind_group(&w::BindGroupDescriptor {
+        label: Some("object bind group"),
+        layout,
+        entries: &[w::BindGroupEntry {
+            binding: BINDING_OBJECT.binding,
+            resource: w::BindingResource::Buffer(w::BufferBinding {
+                buffer,
+                offset: 0,
+                size: Some(ObjectUniform::min_size()),
+            }),
+        }],
+    })
+}
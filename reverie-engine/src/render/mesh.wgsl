@@ -0,0 +1,13 @@
+#include "vertex.wgsl"
+#include "camera.wgsl"
+#include "object.wgsl"
+#include "textured.wgsl"
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+  var out: VertexOutput;
+  out.uv = mix(object.uv_rect.xy, object.uv_rect.zw, in.uv);
+  out.color = in.color;
+  out.position = camera.view_projection * object.model * vec4<f32>(in.position, 1.0);
+  return out;
+}
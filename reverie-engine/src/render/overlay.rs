@@ -0,0 +1,348 @@
+//! パフォーマンスオーバーレイの描画
+use std::{borrow::Cow, time::Duration};
+
+use wgpu as w;
+
+use crate::{
+    camera::Viewport,
+    stats::{FrameStats, RenderCounterCells, TimeSamples},
+};
+
+use super::{buffer::VertexIndexBuffer, reflect, vertex::VertexLayout};
+
+pub static LOC_POSITION: u32 = 0;
+pub static LOC_COLOR: u32 = 1;
+
+/// オーバーレイが一度に描画できる四角形の数。インデックスが `u16` に収まるようにする。
+const MAX_QUADS: usize = 4096;
+/// バッファの最初の容量 (四角形の数)
+const INITIAL_QUADS: usize = 512;
+/// フォントの 1 ドットの大きさ (物理ピクセル)
+const DOT: f32 = 2.0;
+const LINE_HEIGHT: f32 = 7.0 * DOT;
+const MARGIN: f32 = 8.0;
+const GRAPH_HEIGHT: f32 = 64.0;
+/// グラフの上端が表す時間 (ミリ秒)
+const GRAPH_MAX_MS: f32 = 50.0;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const GOOD: [f32; 4] = [0.2, 0.9, 0.3, 1.0];
+const WARN: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+const BAD: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+const GUIDE: [f32; 4] = [1.0, 1.0, 1.0, 0.3];
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+/// オーバーレイの頂点。位置は正規化デバイス座標で表す。
+pub struct OverlayVertex {
+    pub position: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl VertexLayout for OverlayVertex {
+    const DESC: wgpu::VertexBufferLayout<'static> = w::VertexBufferLayout {
+        array_stride: size_of::<Self>() as w::BufferAddress,
+        step_mode: w::VertexStepMode::Vertex,
+        attributes: &w::vertex_attr_array![
+            LOC_POSITION => Float32x2,
+            LOC_COLOR => Float32x4,
+        ],
+    };
+}
+
+#[derive(Debug)]
+/// フレーム時間のグラフと計測値を画面左上に描画するパイプライン
+pub struct OverlayRenderPipeline {
+    pub pipeline: w::RenderPipeline,
+    buffer: VertexIndexBuffer<OverlayVertex>,
+}
+
+impl OverlayRenderPipeline {
+    pub fn new(device: &w::Device, surface_format: w::TextureFormat) -> anyhow::Result<Self> {
+        let source = include_str!("./overlay.wgsl");
+        let module = reflect::parse("overlay.wgsl", source)?;
+        reflect::check_vertex_inputs(&module, "vs_main", &[OverlayVertex::DESC])?;
+        let shader = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some("overlay.wgsl"),
+            source: w::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("overlay render pipeline layout"),
+            bind_group_layouts: &[],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_render_pipeline(&w::RenderPipelineDescriptor {
+            label: Some("overlay render pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: w::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[Some(OverlayVertex::DESC)],
+            },
+            fragment: Some(w::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(w::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(w::BlendState::ALPHA_BLENDING),
+                    write_mask: w::ColorWrites::ALL,
+                })],
+            }),
+            primitive: w::PrimitiveState {
+                topology: w::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: w::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: w::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(w::DepthStencilState {
+                format: w::TextureFormat::Depth32Float,
+                depth_write_enabled: Some(false),
+                depth_compare: Some(w::CompareFunction::Always),
+                stencil: w::StencilState::default(),
+                bias: w::DepthBiasState::default(),
+            }),
+            multisample: w::MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+
+        let buffer = VertexIndexBuffer::new(
+            device,
+            INITIAL_QUADS * 4,
+            INITIAL_QUADS * 6,
+            Some("overlay buffer"),
+        )
+        .expect("failed: create overlay buffer");
+
+        Ok(Self { pipeline, buffer })
+    }
+
+    /// 計測値から頂点を作り、GPU に送信する
+    pub fn prepare(
+        &mut self,
+        device: &w::Device,
+        queue: &w::Queue,
+        counters: &RenderCounterCells,
+        viewport: &Viewport,
+        stats: &FrameStats,
+    ) {
+        let mut update = self.buffer.start_update(device, queue, counters);
+        let (vertices, indices) = update.arrays_mut();
+        vertices.clear();
+        indices.clear();
+        let mut mesh = OverlayMesh {
+            vertices,
+            indices,
+            width: viewport.width.get() as f32,
+            height: viewport.height.get() as f32,
+        };
+        build_overlay(&mut mesh, stats);
+        let (vertex_len, index_len) = (mesh.vertices.len(), mesh.indices.len());
+        update.set_vertex_update(0..vertex_len);
+        update.set_index_update(0..index_len);
+        update.set_render_range(0..index_len as u32);
+    }
+
+    pub fn render(&self, rp: &mut w::RenderPass<'_>, counters: &RenderCounterCells) {
+        if self.buffer.index_buffer_range.is_empty() {
+            return;
+        }
+        rp.set_pipeline(&self.pipeline);
+        self.buffer.bind(rp);
+        rp.draw_indexed(self.buffer.index_buffer_range.clone(), 0, 0..1);
+        counters.add_draw_calls(1);
+    }
+}
+
+/// 物理ピクセル単位で四角形を積むためのヘルパー
+struct OverlayMesh<'a> {
+    vertices: &'a mut Vec<OverlayVertex>,
+    indices: &'a mut Vec<u16>,
+    width: f32,
+    height: f32,
+}
+
+impl OverlayMesh<'_> {
+    /// 左上が `(x, y)` の四角形を追加する。容量を超える場合は何もしない。
+    fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: [f32; 4]) {
+        if self.vertices.len() + 4 > MAX_QUADS * 4 {
+            return;
+        }
+        let to_ndc = |px: f32, py: f32| {
+            [
+                (px / self.width).mul_add(2.0, -1.0),
+                (py / self.height).mul_add(-2.0, 1.0),
+            ]
+        };
+        let base = self.vertices.len() as u16;
+        for (px, py) in [(x, y), (x + w, y), (x, y + h), (x + w, y + h)] {
+            self.vertices.push(OverlayVertex {
+                position: to_ndc(px, py),
+                color,
+            });
+        }
+        self.indices
+            .extend_from_slice(&[base, base + 3, base + 1, base, base + 2, base + 3]);
+    }
+
+    /// 3x5 のビットマップフォントで文字列を描画する
+    fn text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        let mut cursor = x;
+        for c in text.chars() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                // 横に連続するドットは 1 つの四角形にまとめる
+                let mut col = 0;
+                while col < 3 {
+                    if bits & (0b100 >> col) == 0 {
+                        col += 1;
+                        continue;
+                    }
+                    let start = col;
+                    while col < 3 && bits & (0b100 >> col) != 0 {
+                        col += 1;
+                    }
+                    self.rect(
+                        (start as f32).mul_add(DOT, cursor),
+                        (row as f32).mul_add(DOT, y),
+                        (col - start) as f32 * DOT,
+                        DOT,
+                        color,
+                    );
+                }
+            }
+            cursor += 4.0 * DOT;
+        }
+    }
+}
+
+fn build_overlay(mesh: &mut OverlayMesh<'_>, stats: &FrameStats) {
+    let fps = stats
+        .fps()
+        .map_or_else(|| "-".to_string(), |fps| format!("{fps:.1}"));
+    let counters = stats.counters;
+    let lines = [
+        format!("FPS {fps}"),
+        format!("FRAME {}", summary(&stats.frame_time)),
+        format!("UPDATE {}", summary(&stats.update_time)),
+        format!("RENDER {}", summary(&stats.render_time)),
+        format!(
+            "SPRITES {} MESHES {} DRAWS {} UPLOAD {} B",
+            counters.sprites_drawn,
+            counters.meshes_drawn,
+            counters.draw_calls,
+            counters.uploaded_bytes
+        ),
+        format!(
+            "CULLED SPRITES {} MESHES {}",
+            counters.sprites_culled, counters.meshes_culled
+        ),
+    ];
+
+    let text_width = lines
+        .iter()
+        .map(|line| line.chars().count() as f32 * 4.0 * DOT)
+        .fold(0.0, f32::max);
+    let graph_width = stats.frame_time.len().max(1) as f32;
+    let panel_width = MARGIN.mul_add(2.0, text_width.max(graph_width));
+    let panel_height = MARGIN.mul_add(3.0, (lines.len() as f32).mul_add(LINE_HEIGHT, GRAPH_HEIGHT));
+    mesh.rect(0.0, 0.0, panel_width, panel_height, BACKGROUND);
+
+    let mut y = MARGIN;
+    for line in &lines {
+        mesh.text(MARGIN, y, line, TEXT);
+        y += LINE_HEIGHT;
+    }
+
+    // フレーム時間のグラフ。1 サンプルを 1 ピクセル幅の棒で表す。
+    let graph_top = y + MARGIN;
+    let graph_bottom = graph_top + GRAPH_HEIGHT;
+    for target_ms in [1000.0 / 60.0, 1000.0 / 30.0] {
+        let guide_y = (target_ms / GRAPH_MAX_MS).mul_add(-GRAPH_HEIGHT, graph_bottom);
+        mesh.rect(MARGIN, guide_y, graph_width, 1.0, GUIDE);
+    }
+    for (i, sample) in stats.frame_time.iter().enumerate() {
+        let sample_ms = sample.as_secs_f32() * 1000.0;
+        let h = (sample_ms / GRAPH_MAX_MS).min(1.0) * GRAPH_HEIGHT;
+        let color = if sample_ms <= 1000.0 / 60.0 + 1.0 {
+            GOOD
+        } else if sample_ms <= 1000.0 / 30.0 + 1.0 {
+            WARN
+        } else {
+            BAD
+        };
+        mesh.rect(MARGIN + i as f32, graph_bottom - h, 1.0, h, color);
+    }
+}
+
+/// 平均・最小・最大・99 パーセンタイルをミリ秒で表した文字列
+fn summary(samples: &TimeSamples) -> String {
+    format!(
+        "{} MS MIN {} MAX {} P99 {}",
+        ms(samples.average()),
+        ms(samples.min()),
+        ms(samples.max()),
+        ms(samples.p99()),
+    )
+}
+
+fn ms(duration: Option<Duration>) -> String {
+    duration.map_or_else(
+        || "-".to_string(),
+        |d| format!("{:.2}", d.as_secs_f64() * 1000.0),
+    )
+}
+
+/// 3x5 のビットマップフォント。各行の下位 3 ビットが左から右のドットを表す。
+const fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0; 5],
+    }
+}
@@ -0,0 +1,390 @@
+//! naga で解析したシェーダーからバインドグループのレイアウトと頂点属性を求める
+use std::num::NonZeroU64;
+
+use anyhow::Context;
+use wgpu as w;
+
+/// 前処理した WGSL を naga で解析する
+pub fn parse(name: &str, source: &str) -> anyhow::Result<naga::Module> {
+    naga::front::wgsl::parse_str(source)
+        .map_err(|err| anyhow::anyhow!("{}", err.emit_to_string_with_path(source, name)))
+}
+
+/// シェーダーの `group` のバインディングに合うレイアウトのエントリ。バインディングの番号順に並ぶ。
+///
+/// 動的オフセットはシェーダーから分からないため、必要な場合は呼び出し側で設定する。
+pub fn bind_group_layout_entries(
+    module: &naga::Module,
+    group: u32,
+) -> anyhow::Result<Vec<w::BindGroupLayoutEntry>> {
+    let mut layouter = naga::proc::Layouter::default();
+    layouter
+        .update(module.to_ctx())
+        .context("failed: compute type layouts")?;
+    let mut entries = module
+        .global_variables
+        .iter()
+        .filter_map(|(_, var)| {
+            let binding = var.binding.as_ref()?;
+            (binding.group == group).then_some((var, binding.binding))
+        })
+        .map(|(var, binding)| {
+            let ty = binding_type(module, &layouter, var).with_context(|| {
+                format!(
+                    "@group({group}) @binding({binding}) var {}",
+                    var.name.as_deref().unwrap_or("_")
+                )
+            })?;
+            Ok(w::BindGroupLayoutEntry {
+                binding,
+                visibility: w::ShaderStages::VERTEX_FRAGMENT,
+                ty,
+                count: None,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.binding);
+    Ok(entries)
+}
+
+/// シェーダーの `group` のバインディングが、すべて `entries` に同じ番号と型で含まれるかどうかを調べる
+pub fn check_bind_group(
+    module: &naga::Module,
+    group: u32,
+    entries: &[w::BindGroupLayoutEntry],
+) -> anyhow::Result<()> {
+    for expected in bind_group_layout_entries(module, group)? {
+        let binding = expected.binding;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.binding == binding)
+            .with_context(|| format!("@group({group}) @binding({binding}) is not provided"))?;
+        anyhow::ensure!(
+            binding_compatible(&expected.ty, &entry.ty),
+            "@group({group}) @binding({binding}) is {:?} in shader but {:?} in layout",
+            expected.ty,
+            entry.ty
+        );
+    }
+    Ok(())
+}
+
+/// `entries` の `binding` が大きさ `size` のユニフォームバッファかどうかを調べる
+pub fn check_uniform_size(
+    entries: &[w::BindGroupLayoutEntry],
+    binding: u32,
+    size: NonZeroU64,
+) -> anyhow::Result<()> {
+    let entry = entries
+        .iter()
+        .find(|entry| entry.binding == binding)
+        .with_context(|| format!("shader has no @binding({binding})"))?;
+    let w::BindingType::Buffer {
+        ty: w::BufferBindingType::Uniform,
+        min_binding_size,
+        ..
+    } = entry.ty
+    else {
+        anyhow::bail!("@binding({binding}) is not a uniform buffer");
+    };
+    anyhow::ensure!(
+        min_binding_size == Some(size),
+        "@binding({binding}) is {} bytes in shader but {size} bytes in Rust",
+        min_binding_size.map_or(0, NonZeroU64::get)
+    );
+    Ok(())
+}
+
+/// 頂点シェーダー `entry_point` の `@location` の入力が、`buffers` の頂点属性と同じ位置と型を持つかどうかを調べる
+pub fn check_vertex_inputs(
+    module: &naga::Module,
+    entry_point: &str,
+    buffers: &[w::VertexBufferLayout<'_>],
+) -> anyhow::Result<()> {
+    let function = &module
+        .entry_points
+        .iter()
+        .find(|ep| ep.name == entry_point && ep.stage == naga::ShaderStage::Vertex)
+        .with_context(|| format!("no vertex entry point `{entry_point}`"))?
+        .function;
+    for argument in &function.arguments {
+        if let Some(binding) = &argument.binding {
+            check_vertex_input(module, buffers, binding, argument.ty, &argument.name)?;
+        } else if let naga::TypeInner::Struct { members, .. } = &module.types[argument.ty].inner {
+            for member in members {
+                if let Some(binding) = &member.binding {
+                    check_vertex_input(module, buffers, binding, member.ty, &member.name)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_vertex_input(
+    module: &naga::Module,
+    buffers: &[w::VertexBufferLayout<'_>],
+    binding: &naga::Binding,
+    ty: naga::Handle<naga::Type>,
+    name: &Option<String>,
+) -> anyhow::Result<()> {
+    let naga::Binding::Location { location, .. } = *binding else {
+        return Ok(());
+    };
+    let name = name.as_deref().unwrap_or("_");
+    let attribute = buffers
+        .iter()
+        .flat_map(|buffer| buffer.attributes)
+        .find(|attribute| attribute.shader_location == location)
+        .with_context(|| {
+            format!("`{name}` expects @location({location}) but no vertex attribute has it")
+        })?;
+    let actual = match module.types[ty].inner {
+        naga::TypeInner::Scalar(scalar) => Some((scalar.kind, 1)),
+        naga::TypeInner::Vector { size, scalar } => Some((scalar.kind, size as u32)),
+        _ => None,
+    };
+    anyhow::ensure!(
+        actual.is_some() && actual == vertex_format_type(attribute.format),
+        "`{name}` at @location({location}) is {} but vertex attribute is {:?}",
+        actual.map_or_else(|| "not a scalar or vector".to_string(), wgsl_type_name),
+        attribute.format
+    );
+    Ok(())
+}
+
+fn binding_type(
+    module: &naga::Module,
+    layouter: &naga::proc::Layouter,
+    var: &naga::GlobalVariable,
+) -> anyhow::Result<w::BindingType> {
+    let min_binding_size = NonZeroU64::new(u64::from(layouter[var.ty].size));
+    let ty = match (var.space, &module.types[var.ty].inner) {
+        (naga::AddressSpace::Uniform, _) => w::BindingType::Buffer {
+            ty: w::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size,
+        },
+        (naga::AddressSpace::Storage { access }, _) => w::BindingType::Buffer {
+            ty: w::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            },
+            has_dynamic_offset: false,
+            min_binding_size,
+        },
+        (
+            naga::AddressSpace::Handle,
+            naga::TypeInner::Image {
+                dim,
+                arrayed,
+                class,
+            },
+        ) => {
+            let (sample_type, multisampled) = match *class {
+                naga::ImageClass::Sampled { kind, multi } => {
+                    let sample_type = match kind {
+                        naga::ScalarKind::Float => {
+                            w::TextureSampleType::Float { filterable: !multi }
+                        }
+                        naga::ScalarKind::Sint => w::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => w::TextureSampleType::Uint,
+                        _ => anyhow::bail!("unsupported texture sample type {kind:?}"),
+                    };
+                    (sample_type, multi)
+                }
+                naga::ImageClass::Depth { multi } => (w::TextureSampleType::Depth, multi),
+                _ => anyhow::bail!("unsupported texture class {class:?}"),
+            };
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, false) => w::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => w::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => w::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, false) => w::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => w::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => w::TextureViewDimension::CubeArray,
+                _ => anyhow::bail!("unsupported texture dimension {dim:?}"),
+            };
+            w::BindingType::Texture {
+                sample_type,
+                view_dimension,
+                multisampled,
+            }
+        }
+        (naga::AddressSpace::Handle, naga::TypeInner::Sampler { comparison }) => {
+            w::BindingType::Sampler(if *comparison {
+                w::SamplerBindingType::Comparison
+            } else {
+                w::SamplerBindingType::Filtering
+            })
+        }
+        (space, inner) => anyhow::bail!("unsupported binding {space:?} {inner:?}"),
+    };
+    Ok(ty)
+}
+
+/// シェーダーが `shader` として使うバインディングに `layout` のリソースを渡せるかどうか
+fn binding_compatible(shader: &w::BindingType, layout: &w::BindingType) -> bool {
+    match (shader, layout) {
+        (
+            w::BindingType::Buffer {
+                ty: shader_ty,
+                min_binding_size: shader_size,
+                ..
+            },
+            w::BindingType::Buffer {
+                ty: layout_ty,
+                min_binding_size: layout_size,
+                ..
+            },
+        ) => {
+            shader_ty == layout_ty
+                && layout_size
+                    .is_none_or(|layout_size| shader_size.is_some_and(|s| s <= layout_size))
+        }
+        (
+            w::BindingType::Texture {
+                sample_type: shader_sample,
+                view_dimension: shader_dimension,
+                multisampled: shader_multi,
+            },
+            w::BindingType::Texture {
+                sample_type: layout_sample,
+                view_dimension: layout_dimension,
+                multisampled: layout_multi,
+            },
+        ) => {
+            // フィルタできるかどうかはサンプラーとの組み合わせで決まるので比べない
+            let float = |sample: &w::TextureSampleType| {
+                matches!(sample, w::TextureSampleType::Float { .. })
+            };
+            (shader_sample == layout_sample || float(shader_sample) && float(layout_sample))
+                && shader_dimension == layout_dimension
+                && shader_multi == layout_multi
+        }
+        (w::BindingType::Sampler(shader), w::BindingType::Sampler(layout)) => {
+            (*shader == w::SamplerBindingType::Comparison)
+                == (*layout == w::SamplerBindingType::Comparison)
+        }
+        _ => false,
+    }
+}
+
+/// 頂点属性の形式をシェーダーで受け取るときの型
+const fn vertex_format_type(format: w::VertexFormat) -> Option<(naga::ScalarKind, u32)> {
+    use naga::ScalarKind::{Float, Sint, Uint};
+    use w::VertexFormat as F;
+    Some(match format {
+        F::Uint8 | F::Uint16 | F::Uint32 => (Uint, 1),
+        F::Uint8x2 | F::Uint16x2 | F::Uint32x2 => (Uint, 2),
+        F::Uint32x3 => (Uint, 3),
+        F::Uint8x4 | F::Uint16x4 | F::Uint32x4 => (Uint, 4),
+        F::Sint8 | F::Sint16 | F::Sint32 => (Sint, 1),
+        F::Sint8x2 | F::Sint16x2 | F::Sint32x2 => (Sint, 2),
+        F::Sint32x3 => (Sint, 3),
+        F::Sint8x4 | F::Sint16x4 | F::Sint32x4 => (Sint, 4),
+        F::Unorm8 | F::Snorm8 | F::Unorm16 | F::Snorm16 | F::Float16 | F::Float32 => (Float, 1),
+        F::Unorm8x2 | F::Snorm8x2 | F::Unorm16x2 | F::Snorm16x2 | F::Float16x2 | F::Float32x2 => {
+            (Float, 2)
+        }
+        F::Float32x3 => (Float, 3),
+        F::Unorm8x4
+        | F::Snorm8x4
+        | F::Unorm16x4
+        | F::Snorm16x4
+        | F::Float16x4
+        | F::Float32x4
+        | F::Unorm10_10_10_2
+        | F::Unorm8x4Bgra => (Float, 4),
+        _ => return None,
+    })
+}
+
+fn wgsl_type_name((kind, components): (naga::ScalarKind, u32)) -> String {
+    let scalar = match kind {
+        naga::ScalarKind::Float => "f32",
+        naga::ScalarKind::Sint => "i32",
+        naga::ScalarKind::Uint => "u32",
+        naga::ScalarKind::Bool => "bool",
+        _ => "?",
+    };
+    if components == 1 {
+        scalar.to_string()
+    } else {
+        format!("vec{components}<{scalar}>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encase::ShaderType;
+
+    use super::*;
+    use crate::{
+        model::Vertex,
+        render::{mesh, sprite, uniform, vertex::VertexLayout, wgsl},
+        scene::SpriteInstance,
+    };
+
+    fn module(name: &str, source: &str, defines: &[&str]) -> naga::Module {
+        parse(name, &wgsl::preprocess(name, source, defines)).unwrap()
+    }
+
+    #[test]
+    fn engine_shaders_match_rust_layouts() {
+        let sprite = module("sprite.wgsl", include_str!("./sprite.wgsl"), &[]);
+        check_vertex_inputs(&sprite, "vs_main", &[Vertex::DESC]).unwrap();
+        let camera = bind_group_layout_entries(&sprite, sprite::GROUP_CAMERA).unwrap();
+        check_uniform_size(
+            &camera,
+            sprite::BINDING_CAMERA.binding,
+            uniform::CameraUniform::min_size(),
+        )
+        .unwrap();
+
+        let instanced = module("sprite.wgsl", include_str!("./sprite.wgsl"), &["INSTANCED"]);
+        check_vertex_inputs(&instanced, "vs_main", &[Vertex::DESC, SpriteInstance::DESC]).unwrap();
+        // インスタンスの頂点属性がなければ位置が足りない
+        assert!(check_vertex_inputs(&instanced, "vs_main", &[Vertex::DESC]).is_err());
+
+        let mesh = module("mesh.wgsl", include_str!("./mesh.wgsl"), &[]);
+        check_vertex_inputs(&mesh, "vs_main", &[Vertex::DESC]).unwrap();
+        let textures = bind_group_layout_entries(&sprite, sprite::GROUP_TEXTURE).unwrap();
+        check_bind_group(&mesh, sprite::GROUP_TEXTURE, &textures).unwrap();
+        check_bind_group(&mesh, sprite::GROUP_CAMERA, &camera).unwrap();
+        let object = bind_group_layout_entries(&mesh, mesh::GROUP_OBJECT).unwrap();
+        check_uniform_size(
+            &object,
+            mesh::BINDING_OBJECT.binding,
+            uniform::ObjectUniform::min_size(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        let source = "
+@group(0) @binding(0) var tex: texture_2d<f32>;
+@group(0) @binding(1) var samp: sampler;
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>) -> @builtin(position) vec4<f32> {
+  return vec4<f32>(position, 0.0, 1.0);
+}
+";
+        let module = parse("test.wgsl", source).unwrap();
+
+        // 頂点属性の型が違う
+        let err = check_vertex_inputs(&module, "vs_main", &[Vertex::DESC]).unwrap_err();
+        assert!(err.to_string().contains("vec2<f32>"), "{err}");
+
+        // バインディングの番号が違う
+        let entries = bind_group_layout_entries(&module, 0).unwrap();
+        assert_eq!(entries.len(), 2);
+        let mut swapped = entries.clone();
+        swapped[0].binding = 1;
+        swapped[1].binding = 0;
+        assert!(check_bind_group(&module, 0, &entries).is_ok());
+        assert!(check_bind_group(&module, 0, &swapped).is_err());
+        assert!(check_bind_group(&module, 0, &entries[..1]).is_err());
+    }
+}
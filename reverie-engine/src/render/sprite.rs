@@ -1,19 +1,27 @@
 use std::borrow::Cow;
 
+use encase::ShaderType;
 use wgpu as w;
 
 use crate::{model::Vertex, render::vertex::VertexLayout};
 
-use super::{BindingId, texture::WgpuTexture, uniform};
+use super::{
+    BindingId,
+    blend::{self, BlendPipelines},
+    reflect,
+    uniform::CameraUniform,
+    wgsl,
+};
 
 pub static LOC_VERTEX: u32 = 0;
 pub static LOC_UV: u32 = 1;
 pub static LOC_NORMAL: u32 = 2;
+pub static LOC_COLOR: u32 = 3;
 pub static GROUP_TEXTURE: u32 = 0;
 pub static BINDING_TEXTURE: BindingId = BindingId::new(GROUP_TEXTURE, 0);
 pub static BINDING_SAMPLER: BindingId = BindingId::new(GROUP_TEXTURE, 1);
-pub static GROUP_TRANSFORM: u32 = 1;
-pub static BINDING_TRANSFORM: BindingId = BindingId::new(GROUP_TRANSFORM, 0);
+pub static GROUP_CAMERA: u32 = 1;
+pub static BINDING_CAMERA: BindingId = BindingId::new(GROUP_CAMERA, 0);
 
 impl VertexLayout for Vertex {
     const DESC: wgpu::VertexBufferLayout<'static> = w::VertexBufferLayout {
@@ -23,108 +31,94 @@ impl VertexLayout for Vertex {
             LOC_VERTEX => Float32x3,
             LOC_UV => Float32x2,
             LOC_NORMAL => Float32x3,
+            LOC_COLOR => Float32x4,
         ],
     };
 }
 
 #[derive(Debug)]
 pub struct SpriteRenderPipeline {
-    pub pipeline: w::RenderPipeline,
+    /// 合成方法ごとのパイプライン
+    pub pipelines: BlendPipelines,
     pub texture_bind_group_layout: w::BindGroupLayout,
-    pub uniform_bind_group: w::BindGroup,
+    pub camera_bind_group_layout: w::BindGroupLayout,
 }
 
 impl SpriteRenderPipeline {
-    pub fn new(
-        device: &w::Device,
-        surface_format: w::TextureFormat,
-        transform_uniform_buffer: &w::Buffer,
-    ) -> Self {
+    /// バインドグループのレイアウトは sprite.wgsl から求め、Rust 側の型と合わなければエラーにする
+    pub fn new(device: &w::Device, surface_format: w::TextureFormat) -> anyhow::Result<Self> {
+        let source = wgsl::preprocess("sprite.wgsl", include_str!("./sprite.wgsl"), &[]);
+        let module = reflect::parse("sprite.wgsl", &source)?;
+        reflect::check_vertex_inputs(&module, "vs_main", &[Vertex::DESC])?;
+        let texture_entries = reflect::bind_group_layout_entries(&module, GROUP_TEXTURE)?;
+        let camera_entries = reflect::bind_group_layout_entries(&module, GROUP_CAMERA)?;
+        reflect::check_uniform_size(
+            &camera_entries,
+            BINDING_CAMERA.binding,
+            CameraUniform::min_size(),
+        )?;
+
         let shader = device.create_shader_module(w::ShaderModuleDescriptor {
             label: Some("sprite.wgsl"),
-            source: w::ShaderSource::Wgsl(Cow::Borrowed(include_str!("./sprite.wgsl"))),
+            source: w::ShaderSource::Wgsl(Cow::Owned(source)),
         });
 
-        let texture_bind_group_layout = create_texture_binding_layout(device);
-        let (uniform_bind_group_layout, uniform_bind_group) = uniform::create_mat4_bind_group(
-            device,
-            transform_uniform_buffer,
-            BINDING_TRANSFORM.binding,
-        );
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+                label: Some("sprite texture bind group layout"),
+                entries: &texture_entries,
+            });
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+                label: Some("camera bind group layout"),
+                entries: &camera_entries,
+            });
 
         let pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
             label: Some("sprite model render pipeline layout"),
             bind_group_layouts: &[
                 Some(&texture_bind_group_layout),
-                Some(&uniform_bind_group_layout),
+                Some(&camera_bind_group_layout),
             ],
             immediate_size: 0,
         });
 
-        let pipeline = device.create_render_pipeline(&w::RenderPipelineDescriptor {
-            label: Some("sprite model render pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: w::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: &[Some(Vertex::DESC)],
-            },
-            fragment: Some(w::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(w::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(w::BlendState {
-                        color: w::BlendComponent {
-                            src_factor: w::BlendFactor::SrcAlpha,
-                            dst_factor: w::BlendFactor::OneMinusSrcAlpha,
-                            operation: w::BlendOperation::Add,
-                        },
-                        alpha: w::BlendComponent::OVER,
-                    }),
-                    write_mask: w::ColorWrites::ALL,
-                })],
-            }),
-            primitive: w::PrimitiveState {
-                topology: w::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: w::FrontFace::Ccw,
-                cull_mode: Some(w::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: w::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: Some(w::DepthStencilState {
-                format: w::TextureFormat::Depth32Float,
-                depth_write_enabled: Some(true),
-                depth_compare: Some(w::CompareFunction::Less),
-                stencil: w::StencilState::default(),
-                bias: w::DepthBiasState::default(),
-            }),
-            multisample: w::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: true,
-            },
-            cache: None,
-            multiview_mask: None,
+        let pipelines = BlendPipelines::new(|blend| {
+            device.create_render_pipeline(&w::RenderPipelineDescriptor {
+                label: Some(&format!("sprite model render pipeline ({blend:?})")),
+                layout: Some(&pipeline_layout),
+                vertex: w::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[Some(Vertex::DESC)],
+                },
+                fragment: Some(w::FragmentState {
+                    module: &shader,
+                    entry_point: Some(blend::fragment_entry_point(blend)),
+                    compilation_options: Default::default(),
+                    targets: &[Some(blend::color_target(surface_format, blend))],
+                }),
+                primitive: w::PrimitiveState {
+                    topology: w::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: w::FrontFace::Ccw,
+                    cull_mode: Some(w::Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: w::PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(blend::depth_stencil(blend)),
+                multisample: w::MultisampleState::default(),
+                cache: None,
+                multiview_mask: None,
+            })
         });
 
-        Self {
-            pipeline,
+        Ok(Self {
+            pipelines,
             texture_bind_group_layout,
-            uniform_bind_group,
-        }
+            camera_bind_group_layout,
+        })
     }
 }
-
-fn create_texture_binding_layout(device: &w::Device) -> w::BindGroupLayout {
-    WgpuTexture::bind_group_layout(
-        device,
-        Some("sprite texture bind group layout"),
-        BINDING_TEXTURE.binding,
-        BINDING_SAMPLER.binding,
-    )
-}
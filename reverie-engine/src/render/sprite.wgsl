@@ -1,45 +1,38 @@
-const LOC_VERTEX: u32 = 0;
-const LOC_UV: u32 = 1;
-const LOC_NORMAL: u32 = 2;
-const GROUP_TEXTURE: u32 = 0;
-const BINDING_TEXTURE: u32 = 0;
-const BINDING_SAMPLER: u32 = 1;
-const GROUP_TRANSFORM: u32 = 1;
-const BINDING_TRANSFORM: u32 = 0;
+// INSTANCED を定義すると SpriteInstances をインスタンシングで描画する
+#include "vertex.wgsl"
+#include "camera.wgsl"
+#include "textured.wgsl"
 
-struct VertexInput {
-  @location(LOC_VERTEX) position: vec3<f32>,
-  @location(LOC_UV) uv: vec2<f32>,
-  @location(LOC_NORMAL) normal: vec3<f32>
+#ifdef INSTANCED
+struct InstanceInput {
+  @location(LOC_MODEL_0) model_0: vec4<f32>,
+  @location(LOC_MODEL_1) model_1: vec4<f32>,
+  @location(LOC_MODEL_2) model_2: vec4<f32>,
+  @location(LOC_MODEL_3) model_3: vec4<f32>,
+  @location(LOC_UV_RECT) uv_rect: vec4<f32>,
+  @location(LOC_TINT) tint: vec4<f32>,
+  @location(LOC_LAYER) layer: f32
 }
 
-struct VertexOutput {
-  @location(0) uv: vec2<f32>,
-  @builtin(position) position: vec4<f32>
-}
-
-@group(GROUP_TEXTURE)
-@binding(BINDING_TEXTURE)
-var tex: texture_2d<f32>;
-
-@group(GROUP_TEXTURE)
-@binding(BINDING_SAMPLER)
-var samp: sampler;
-
-@group(GROUP_TRANSFORM)
-@binding(BINDING_TRANSFORM)
-var<uniform> transform: mat4x4<f32>;
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+  let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+  var world = model * vec4<f32>(in.position, 1.0);
+  world.z += instance.layer * world.w;
 
+  var out: VertexOutput;
+  out.uv = mix(instance.uv_rect.xy, instance.uv_rect.zw, in.uv);
+  out.color = in.color * instance.tint;
+  out.position = snap_to_pixel(camera.view_projection * world);
+  return out;
+}
+#else
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
   var out: VertexOutput;
   out.uv = in.uv;
-  out.position = transform * vec4<f32>(in.position, 1.0);
+  out.color = in.color;
+  out.position = snap_to_pixel(camera.view_projection * vec4<f32>(in.position, 1.0));
   return out;
 }
-
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-  //return vec4<f32>(0.5, 0.5, 0.5, 1.0);
-  return textureSample(tex, samp, in.uv);
-}
+#endif
@@ -50,6 +50,35 @@ impl WgpuTexture {
         Self { texture, view }
     }
 
+    /// カメラの描画先にするテクスチャを作る。描画先としてもシェーダーのテクスチャとしても使える。
+    pub fn create_render_target(
+        device: &w::Device,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        format: w::TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
+        let size = w::Extent3d {
+            width: width.get(),
+            height: height.get(),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&w::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: w::TextureDimension::D2,
+            format,
+            usage: w::TextureUsages::RENDER_ATTACHMENT | w::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&Default::default());
+
+        Self { texture, view }
+    }
+
     pub fn create_depth_texture(
         device: &w::Device,
         width: NonZeroU32,
@@ -85,35 +114,6 @@ impl WgpuTexture {
         self.texture.height()
     }
 
-    pub(crate) fn bind_group_layout(
-        device: &w::Device,
-        label: Option<&str>,
-        texture_binding: u32,
-        sampler_binding: u32,
-    ) -> w::BindGroupLayout {
-        device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
-            label,
-            entries: &[
-                w::BindGroupLayoutEntry {
-                    binding: texture_binding,
-                    visibility: w::ShaderStages::FRAGMENT,
-                    ty: w::BindingType::Texture {
-                        sample_type: w::TextureSampleType::Float { filterable: true },
-                        view_dimension: w::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                w::BindGroupLayoutEntry {
-                    binding: sampler_binding,
-                    visibility: w::ShaderStages::FRAGMENT,
-                    ty: w::BindingType::Sampler(w::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-        })
-    }
-
     pub(crate) fn create_bind_group(
         &self,
         device: &w::Device,
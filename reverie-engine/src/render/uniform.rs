@@ -1,32 +1,263 @@
+use encase::{
+    ShaderType,
+    internal::WriteInto,
+    matrix::{AsMutMatrixParts, AsRefMatrixParts, FromMatrixParts},
+    vector::{AsMutVectorParts, AsRefVectorParts, FromVectorParts},
+};
+use nalgebra::{Matrix4, Point3, Vector2};
 use wgpu as w;
 
-pub fn create_mat4_bind_group(
-    device: &w::Device,
-    mat4_buffer: &w::Buffer,
-    binding: u32,
-) -> (w::BindGroupLayout, w::BindGroup) {
-    let layout = device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
-        label: Some("Main Bind Group Layout"),
-        entries: &[w::BindGroupLayoutEntry {
-            binding,
-            visibility: w::ShaderStages::VERTEX,
-            ty: w::BindingType::Buffer {
-                ty: w::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: w::BufferSize::new(size_of::<[f32; 4 * 4]>() as u64),
+use crate::{
+    camera::{Camera, Projection, Viewport},
+    stats::RenderCounterCells,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// WGSL の `mat4x4<f32>` に対応する列優先の行列
+pub struct Mat4([[f32; 4]; 4]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// WGSL の `vec4<f32>` に対応するベクトル
+pub struct Vec4([f32; 4]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// WGSL の `vec3<f32>` に対応するベクトル
+pub struct Vec3([f32; 3]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// WGSL の `vec2<f32>` に対応するベクトル
+pub struct Vec2([f32; 2]);
+
+/// 配列をラップした newtype に encase の変換用トレイトを実装する
+macro_rules! impl_parts {
+    ($ty:ident, $parts:ty, $as_ref:ident, $as_mut:ident, $from:ident, $($generics:tt)*) => {
+        impl $as_ref<$($generics)*> for $ty {
+            fn as_ref_parts(&self) -> &$parts {
+                &self.0
+            }
+        }
+
+        impl $as_mut<$($generics)*> for $ty {
+            fn as_mut_parts(&mut self) -> &mut $parts {
+                &mut self.0
+            }
+        }
+
+        impl $from<$($generics)*> for $ty {
+            fn from_parts(parts: $parts) -> Self {
+                Self(parts)
+            }
+        }
+    };
+}
+
+impl_parts!(
+    Mat4,
+    [[f32; 4]; 4],
+    AsRefMatrixParts,
+    AsMutMatrixParts,
+    FromMatrixParts,
+    f32,
+    4,
+    4
+);
+impl_parts!(
+    Vec4,
+    [f32; 4],
+    AsRefVectorParts,
+    AsMutVectorParts,
+    FromVectorParts,
+    f32,
+    4
+);
+impl_parts!(
+    Vec3,
+    [f32; 3],
+    AsRefVectorParts,
+    AsMutVectorParts,
+    FromVectorParts,
+    f32,
+    3
+);
+impl_parts!(
+    Vec2,
+    [f32; 2],
+    AsRefVectorParts,
+    AsMutVectorParts,
+    FromVectorParts,
+    f32,
+    2
+);
+
+encase::impl_matrix!(4, 4, Mat4, f32);
+encase::impl_vector!(4, Vec4, f32);
+encase::impl_vector!(3, Vec3, f32);
+encase::impl_vector!(2, Vec2, f32);
+
+impl From<Matrix4<f32>> for Mat4 {
+    fn from(m: Matrix4<f32>) -> Self {
+        Self(m.into())
+    }
+}
+
+impl From<[f32; 4]> for Vec4 {
+    fn from(v: [f32; 4]) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Point3<f32>> for Vec3 {
+    fn from(p: Point3<f32>) -> Self {
+        Self(p.into())
+    }
+}
+
+impl From<Vector2<f32>> for Vec2 {
+    fn from(v: Vector2<f32>) -> Self {
+        Self(v.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ShaderType)]
+/// シェーダーに渡すカメラの情報
+///
+/// `include/camera.wgsl` の `Camera` 構造体と同じレイアウトになる。
+pub struct CameraUniform {
+    /// ワールド座標系からビュー座標系への変換
+    pub view: Mat4,
+    /// ビュー座標系からクリップ座標系への変換
+    pub projection: Mat4,
+    /// ワールド座標系からクリップ座標系への変換
+    pub view_projection: Mat4,
+    pub inverse_view: Mat4,
+    pub inverse_projection: Mat4,
+    pub inverse_view_projection: Mat4,
+    /// ワールド座標系でのカメラの位置
+    pub eye: Vec3,
+    /// ゲームの時間の経過時間 (秒)
+    pub time: f32,
+    /// カメラが描画する範囲の大きさ (ピクセル)。ピクセルパーフェクトのカメラでは仮想解像度。
+    pub viewport_size: Vec2,
+    /// 1.0 の場合、頂点をピクセルの格子に揃える
+    pub pixel_snap: f32,
+}
+
+impl CameraUniform {
+    pub fn new(camera: &Camera, viewport: &Viewport, time: f32) -> Self {
+        let view = camera.view_matrix();
+        let projection = camera.projection_matrix(viewport);
+        let view_projection = projection * view;
+        let inverse = |m: Matrix4<f32>| m.try_inverse().unwrap_or_else(Matrix4::identity);
+        let (width, height) = camera.render_size(viewport);
+        Self {
+            view: view.into(),
+            projection: projection.into(),
+            view_projection: view_projection.into(),
+            inverse_view: inverse(view).into(),
+            inverse_projection: inverse(projection).into(),
+            inverse_view_projection: inverse(view_projection).into(),
+            eye: Point3::from(camera.effective_pose().translation.vector).into(),
+            time,
+            viewport_size: Vector2::new(width, height).into(),
+            pixel_snap: if matches!(camera.projection, Projection::PixelPerfect(_)) {
+                1.0
+            } else {
+                0.0
             },
-            count: None,
-        }],
-    });
-
-    let bind_group = device.create_bind_group(&w::BindGroupDescriptor {
-        label: Some("Main Bind Group"),
-        layout: &layout,
-        entries: &[w::BindGroupEntry {
-            binding,
-            resource: mat4_buffer.as_entire_binding(),
-        }],
-    });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ShaderType)]
+/// シェーダーに渡す描画するオブジェクトの情報
+///
+/// `include/object.wgsl` の `Object` 構造体と同じレイアウトになる。
+pub struct ObjectUniform {
+    /// モデル座標系からワールド座標系への変換
+    pub model: Mat4,
+    /// テクスチャ座標の範囲 (左上の u, v, 右下の u, v)。アトラスのテクスチャでは一部になる。
+    pub uv_rect: Vec4,
+}
+
+impl ObjectUniform {
+    pub fn new(model: &Matrix4<f32>, uv_rect: (f32, f32, f32, f32)) -> Self {
+        Self {
+            model: (*model).into(),
+            uv_rect: <[f32; 4]>::from(uv_rect).into(),
+        }
+    }
+}
 
-    (layout, bind_group)
+#[derive(Debug)]
+/// [`ShaderType`] を実装した値を 1 つ格納するユニフォームバッファ
+pub struct UniformBuffer<T> {
+    pub(crate) buffer: w::Buffer,
+    last: Option<T>,
+}
+
+impl<T: ShaderType + WriteInto + PartialEq + Copy> UniformBuffer<T> {
+    pub fn new(device: &w::Device, label: Option<&str>) -> Self {
+        let buffer = device.create_buffer(&w::BufferDescriptor {
+            label,
+            size: T::min_size().get(),
+            usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self { buffer, last: None }
+    }
+
+    /// 値を GPU に送信する。前回と同じ値の場合は何もしない。
+    pub fn write(&mut self, queue: &w::Queue, counters: &RenderCounterCells, value: &T) {
+        if self.last.as_ref() == Some(value) {
+            return;
+        }
+        let mut data = encase::UniformBuffer::new(Vec::<u8>::new());
+        data.write(value)
+            .expect("failed: encode uniform buffer contents");
+        let data = data.into_inner();
+        queue.write_buffer(&self.buffer, 0, &data);
+        counters.add_uploaded_bytes(data.len() as u64);
+        self.last = Some(*value);
+    }
+
+    /// `layout` の `binding` にこのバッファを持つバインドグループを作る
+    pub fn create_bind_group_with_layout(
+        &self,
+        device: &w::Device,
+        label: Option<&str>,
+        layout: &w::BindGroupLayout,
+        binding: u32,
+    ) -> w::BindGroup {
+        device.create_bind_group(&w::BindGroupDescriptor {
+            label,
+            layout,
+            entries: &[w::BindGroupEntry {
+                binding,
+                resource: self.buffer.as_entire_binding(),
+            }],
+        })
+    }
+}
+
+#[derive(Debug)]
+/// 1 つのカメラのユニフォームバッファとバインドグループ
+pub struct CameraBinding {
+    pub uniform: UniformBuffer<CameraUniform>,
+    pub bind_group: w::BindGroup,
+}
+
+impl CameraBinding {
+    pub fn new(device: &w::Device, layout: &w::BindGroupLayout, binding: u32) -> Self {
+        let uniform = UniformBuffer::new(device, Some("camera uniform buffer"));
+        let bind_group = uniform.create_bind_group_with_layout(
+            device,
+            Some("camera bind group"),
+            layout,
+            binding,
+        );
+        Self {
+            uniform,
+            bind_group,
+        }
+    }
 }
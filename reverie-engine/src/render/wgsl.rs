@@ -0,0 +1,68 @@
+//! エンジンのシェーダーの前処理
+use std::fmt::Write;
+
+use crate::shader::ShaderPreprocessor;
+
+use super::{BindingId, instanced, mesh, sprite};
+
+/// Rust 側の頂点属性の位置とバインディングを WGSL の定数として並べたソース
+///
+/// `#include "bindings.wgsl"` で使う。
+pub fn bindings() -> String {
+    let locations = [
+        ("LOC_VERTEX", sprite::LOC_VERTEX),
+        ("LOC_UV", sprite::LOC_UV),
+        ("LOC_NORMAL", sprite::LOC_NORMAL),
+        ("LOC_COLOR", sprite::LOC_COLOR),
+        ("LOC_MODEL_0", instanced::LOC_MODEL_0),
+        ("LOC_MODEL_1", instanced::LOC_MODEL_1),
+        ("LOC_MODEL_2", instanced::LOC_MODEL_2),
+        ("LOC_MODEL_3", instanced::LOC_MODEL_3),
+        ("LOC_UV_RECT", instanced::LOC_UV_RECT),
+        ("LOC_TINT", instanced::LOC_TINT),
+        ("LOC_LAYER", instanced::LOC_LAYER),
+        ("GROUP_TEXTURE", sprite::GROUP_TEXTURE),
+        ("GROUP_CAMERA", sprite::GROUP_CAMERA),
+        ("GROUP_OBJECT", mesh::GROUP_OBJECT),
+        ("GROUP_MATERIAL", mesh::GROUP_MATERIAL),
+    ];
+    let bindings: [(&str, BindingId); 6] = [
+        ("BINDING_TEXTURE", sprite::BINDING_TEXTURE),
+        ("BINDING_SAMPLER", sprite::BINDING_SAMPLER),
+        ("BINDING_CAMERA", sprite::BINDING_CAMERA),
+        ("BINDING_OBJECT", mesh::BINDING_OBJECT),
+        ("BINDING_MATERIAL_PARAMS", mesh::BINDING_MATERIAL_PARAMS),
+        ("BINDING_MATERIAL_SAMPLER", mesh::BINDING_MATERIAL_SAMPLER),
+    ];
+    let mut source = String::new();
+    for (name, value) in locations {
+        writeln!(source, "const {name}: u32 = {value};").unwrap();
+    }
+    for (name, binding) in bindings {
+        writeln!(source, "const {name}: u32 = {};", binding.binding).unwrap();
+    }
+    source
+}
+
+/// エンジンの共通のスニペットを `#include` できる前処理
+///
+/// [`Shader`](crate::shader::Shader) もこの前処理を通すため、マテリアルのシェーダーからも使える。
+pub fn preprocessor() -> ShaderPreprocessor {
+    ShaderPreprocessor::default()
+        .with_include("bindings.wgsl", bindings())
+        .with_include("vertex.wgsl", include_str!("./include/vertex.wgsl"))
+        .with_include("camera.wgsl", include_str!("./include/camera.wgsl"))
+        .with_include("object.wgsl", include_str!("./include/object.wgsl"))
+        .with_include("textured.wgsl", include_str!("./include/textured.wgsl"))
+}
+
+/// 組み込みのシェーダーを前処理する。組み込みのシェーダーのすべての組み合わせはテストで検証している。
+pub fn preprocess(name: &str, source: &str, defines: &[&str]) -> String {
+    defines
+        .iter()
+        .fold(preprocessor(), |preprocessor, define| {
+            preprocessor.with_define(*define)
+        })
+        .process(name, source)
+        .expect("failed: preprocess built-in shader")
+}
@@ -1,60 +1,174 @@
 //! シーンに関するモジュール
 
+use std::{cell::RefCell, cmp::Ordering, collections::HashSet};
+
 use crate::{
-    camera::{Camera, OrthographicCamera, PerspectiveCamera},
-    model::{Material, MaterialKey, Mesh, MeshKey},
-    render::{RenderingResource, sprite},
-    scene::frame::Frame,
-    texture::TextureRegistry,
+    camera::{Camera, CameraKey, CameraTarget, LayerMask, PerspectiveProjection},
+    culling::Frustum,
+    model::{BlendMode, Material, MaterialKey, Mesh, MeshKey, Vertex},
+    render::{RenderingResource, blend::RenderPhase, sprite},
+    scene::{frame::Frame, schedule::Scheduler},
+    shader::{Shader, ShaderKey},
+    stats::RenderCounterCells,
+    texture::{TextureIndex, TextureRegistry},
+    time::Time,
 };
 
 mod components;
 pub mod frame;
+pub mod schedule;
 
 pub use components::{
-    model::ModelComponent, sprite::SpriteComponent, transform::TransformComponent,
+    model::ModelComponent,
+    sprite::{SourceRect, SpriteComponent, SpriteInstance, SpriteInstances, SpriteSize},
+    transform::TransformComponent,
 };
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Isometry3, Matrix4, Point3, Vector3};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// ビュー座標系での深度が同じ半透明のものを描画する順番の決め方
+///
+/// どの方法でも [`SpriteComponent::sorting_layer`] が小さいスプライトを先に描画する。メッシュなどはレイヤー 0 として扱う。
+/// 順番が決まらない場合はゲームオブジェクトのキーの順になる。
+pub enum SpriteSortMode {
+    /// [`SpriteComponent::order_in_layer`] の小さい順
+    #[default]
+    Order,
+    /// ワールド座標系の Y の大きい順。見下ろし型のゲームで画面の上にあるものを奥に描く。
+    Y,
+    /// カメラから遠い順
+    CameraDistance,
+}
+
+/// [`Scene::sprite_quads`] が返すテクスチャ、合成方法、頂点
+pub type SpriteQuad = (TextureIndex, BlendMode, [Vertex; 4]);
+
+/// [`Scene::visible_meshes`] が返すゲームオブジェクト、メッシュ、マテリアル、ワールド座標系への変換行列
+pub type VisibleMesh = (GameObjectKey, MeshKey, MaterialKey, Matrix4<f32>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// 描画するものの種類。前後が決まらない半透明のものはこの順に描画する。
+pub enum DrawKind {
+    /// [`ModelComponent`] のメッシュ
+    Mesh,
+    /// [`SpriteComponent`]
+    Sprite,
+    /// [`SpriteInstances`]
+    Instances,
+}
+
+#[derive(Debug)]
+/// [`Scene::draw_list`] が返す、1 つのカメラで描画するものを種類ごとに描画する順番に並べたもの
+///
+/// 種類ごとの最初の区切りは不透明なもの、それ以降の区切りは半透明のもののうち同じ種類が続く部分になる。
+pub struct DrawList {
+    pub meshes: Vec<Vec<VisibleMesh>>,
+    pub sprites: Vec<Vec<(GameObjectKey, Matrix4<f32>)>>,
+    pub instances: Vec<Vec<GameObjectKey>>,
+    /// 半透明の区切りを描画する順番
+    pub transparent: Vec<DrawKind>,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// 半透明のものを描画する順番を決めるための値
+struct TransparentOrder {
+    /// ビュー座標系での深度
+    depth: f32,
+    layer: i32,
+    /// [`Scene::sprite_sort`] で決まる値
+    secondary: f32,
+    order: i32,
+    kind: DrawKind,
+    key: GameObjectKey,
+}
+
+impl TransparentOrder {
+    /// 奥から手前の順に並べ、深度が同じ場合はソートレイヤーと [`Scene::sprite_sort`] で決める
+    fn compare(&self, other: &Self) -> Ordering {
+        other
+            .depth
+            .total_cmp(&self.depth)
+            .then_with(|| self.layer.cmp(&other.layer))
+            .then_with(|| self.secondary.total_cmp(&other.secondary))
+            .then_with(|| self.order.cmp(&other.order))
+            .then_with(|| self.kind.cmp(&other.kind))
+            .then_with(|| self.key.cmp(&other.key))
+    }
+}
+
+#[derive(Debug)]
+/// [`Scene::draw_list`] で並べる半透明のもの
+enum TransparentDraw {
+    Mesh(VisibleMesh),
+    Sprite(GameObjectKey, Matrix4<f32>),
+    Instances(GameObjectKey),
+}
+
+/// `groups` の最後の区切りに `item` を加える。`new_group` の場合は新しい区切りを始める。
+fn push_to_group<T>(groups: &mut Vec<Vec<T>>, new_group: bool, item: T) {
+    match groups.last_mut() {
+        Some(group) if !new_group => group.push(item),
+        _ => groups.push(vec![item]),
+    }
+}
 
 #[derive(Debug)]
 pub struct Scene {
     pub meshes: Registry<MeshKey, Mesh>,
     pub materials: Registry<MaterialKey, Material>,
+    /// [`Material::shader`] で使うシェーダー
+    pub shaders: Registry<ShaderKey, Shader>,
     pub game_objects: DenseRegistry<GameObjectKey, GameObject>,
+    /// ゲームオブジェクトの親に対する位置、回転、拡大縮小
+    pub transforms: slotmap::SecondaryMap<GameObjectKey, TransformComponent>,
+    /// ゲームオブジェクトの位置に描画するスプライト
+    pub sprites: slotmap::SecondaryMap<GameObjectKey, SpriteComponent>,
+    /// インスタンシングで描画するスプライト
+    pub sprite_instances: slotmap::SecondaryMap<GameObjectKey, SpriteInstances>,
+    /// ゲームオブジェクトの位置に描画するメッシュ
+    pub models: slotmap::SecondaryMap<GameObjectKey, ModelComponent>,
     pub textures: TextureRegistry,
     /// Skybox color
     pub skybox: wgpu::Color,
-    /// Main (and the only for now) camera
-    pub camera: Camera,
+    /// スプライトを描画する順番の決め方
+    pub sprite_sort: SpriteSortMode,
+    /// シーンを描画するカメラ。[`Camera::priority`] の小さい順に描画される。
+    pub cameras: slotmap::SlotMap<CameraKey, Camera>,
+    /// [`Scene::default`] で作られるカメラ
+    pub main_camera: CameraKey,
+    /// フレーム時間のグラフと計測値を画面に重ねて表示するかどうか
+    pub show_performance_overlay: bool,
+    /// シーンの時間。[`Game::update`](crate::Game::update) の前に進められる。
+    pub time: Time,
+    /// ゲームの時間に従って実行されるタイマーとコルーチン
+    pub scheduler: Scheduler,
+    /// 自身の描画先のテクスチャを使うものがあると警告したカメラとテクスチャの組
+    warned_own_targets: RefCell<HashSet<(CameraKey, TextureIndex)>>,
 }
 
 impl Default for Scene {
     fn default() -> Self {
-        let camera = if true {
-            PerspectiveCamera::new(
-                &Point3::new(0.0, 0.0, -0.5),
-                &Point3::new(0.0, 0.0, 0.0),
-                &Vector3::new(0.0, 1.0, 0.0),
-                90.0_f32.to_radians(),
-                0.1,
-                100.0,
-            )
-            .into()
-        } else {
-            OrthographicCamera {
-                eye: Point3::new(0.0, 0.0, -0.5),
-                target: Point3::new(0.0, 0.0, 0.0),
-                up: Vector3::new(0.0, 1.0, 0.0),
-                size: 0.5,
+        let camera = Camera::looking_at(
+            &Point3::new(0.0, 0.0, -0.5),
+            &Point3::new(0.0, 0.0, 0.0),
+            &Vector3::new(0.0, 1.0, 0.0),
+            PerspectiveProjection {
+                fov_y_rad: 90.0_f32.to_radians(),
                 z_near: 0.1,
                 z_far: 100.0,
-            }
-            .into()
-        };
+            },
+        );
+        let mut cameras = slotmap::SlotMap::with_key();
+        let main_camera = cameras.insert(camera);
         Self {
             meshes: Default::default(),
             materials: Default::default(),
+            shaders: Default::default(),
             game_objects: Default::default(),
+            transforms: Default::default(),
+            sprites: Default::default(),
+            sprite_instances: Default::default(),
+            models: Default::default(),
             textures: Default::default(),
             skybox: wgpu::Color {
                 r: 54.0 / 255.0,
@@ -62,7 +176,13 @@ impl Default for Scene {
                 b: 118.0 / 255.0,
                 a: 1.0,
             },
-            camera,
+            sprite_sort: SpriteSortMode::default(),
+            cameras,
+            main_camera,
+            show_performance_overlay: false,
+            time: Time::default(),
+            scheduler: Scheduler::default(),
+            warned_own_targets: RefCell::default(),
         }
     }
 }
@@ -79,15 +199,510 @@ impl Scene {
         );
     }
 
-    pub const fn update(&mut self, _frame: &Frame<'_>, _resource: &RenderingResource<'_>) {}
+    /// フレームの始めに [`Game::update`](crate::Game::update) より前に呼ばれる。時間を進める。
+    pub fn begin_frame(&mut self, frame: &Frame<'_>) {
+        self.time.advance(frame.delta_time);
+        self.scheduler.set_now(self.time.game.elapsed());
+    }
 
-    pub fn render(&mut self, rp: &mut wgpu::RenderPass<'_>, resource: &RenderingResource<'_>) {
-        rp.set_pipeline(&resource.sprite_pipeline.pipeline);
-        rp.set_bind_group(
-            crate::render::sprite::GROUP_TRANSFORM,
-            &resource.sprite_pipeline.uniform_bind_group,
-            &[],
-        );
+    /// フレームごとに [`Game::update`](crate::Game::update) の後に呼ばれる。GPU のリソースには触れない。
+    ///
+    /// 時刻が来たタイマーとコルーチンを実行し、ゲームオブジェクトに取り付けられたカメラを動かし、カメラの演出を進める。
+    pub fn update(&mut self, frame: &Frame<'_>) {
+        schedule::run(self, frame);
+        self.update_camera_attachment();
+        let now = self.time.game.interpolation_time();
+        for camera in self.cameras.values_mut() {
+            camera.effects.update(now);
+        }
+    }
+
+    fn update_camera_attachment(&mut self) {
+        let poses: Vec<_> = self
+            .cameras
+            .iter()
+            .filter_map(|(camera, c)| c.attached_to.map(|key| (camera, key, self.world_pose(key))))
+            .collect();
+        for (camera, key, pose) in poses {
+            if let Some(pose) = pose {
+                let transform = &mut self.cameras[camera].transform;
+                transform.translation = pose.translation;
+                transform.rotation = pose.rotation;
+            } else {
+                tracing::warn!(?key, "camera is attached to a removed game object");
+            }
+        }
+    }
+
+    /// [`Scene::main_camera`] のカメラ
+    pub fn main_camera(&self) -> Option<&Camera> {
+        self.cameras.get(self.main_camera)
+    }
+
+    pub fn main_camera_mut(&mut self) -> Option<&mut Camera> {
+        self.cameras.get_mut(self.main_camera)
+    }
+
+    /// カメラを描画する順番に並べる。[`Camera::priority`] が同じ場合の順番は決まっていない。
+    ///
+    /// ウィンドウに描画するカメラがテクスチャを表示できるように、テクスチャに描画するカメラを先に並べる。
+    pub fn cameras_in_render_order(&self) -> Vec<CameraKey> {
+        let mut keys: Vec<_> = self.cameras.keys().collect();
+        keys.sort_by_key(|key| {
+            let camera = &self.cameras[*key];
+            (camera.target == CameraTarget::Surface, camera.priority)
+        });
+        keys
+    }
+
+    /// カメラの描画先が `texture` かどうか。カメラとテクスチャの組ごとに最初の 1 回だけ警告を出す。
+    ///
+    /// 描画先のテクスチャは同じレンダーパスで読めないため、そのテクスチャを使うものはそのカメラでは描画しない。
+    /// ピクセルパーフェクトのカメラも、仮想解像度のテクスチャを経て同じテクスチャに描画するため同様に扱う。
+    pub(crate) fn samples_own_target(&self, camera: CameraKey, texture: TextureIndex) -> bool {
+        if self.cameras[camera].target != CameraTarget::Texture(texture) {
+            return false;
+        }
+        if self
+            .warned_own_targets
+            .borrow_mut()
+            .insert((camera, texture))
+        {
+            tracing::warn!(
+                ?camera,
+                ?texture,
+                "skipped drawing that uses the render target of its own camera"
+            );
+        }
+        true
+    }
+
+    /// ゲームオブジェクトがカメラの [`Camera::layers`] に含まれるかどうか
+    pub fn is_visible_to(&self, key: GameObjectKey, camera: &Camera) -> bool {
+        self.game_objects
+            .map
+            .get(key)
+            .is_some_and(|game_object| game_object.layers.intersects(camera.layers))
+    }
+
+    /// ゲームオブジェクトのワールド座標系での位置と向き
+    ///
+    /// 親をたどって [`Scene::transforms`] を合成する。拡大縮小は無視する。
+    /// `transforms` を持たないゲームオブジェクトは親と同じ位置と向きにあるものとする。
+    pub fn world_pose(&self, key: GameObjectKey) -> Option<Isometry3<f32>> {
+        let mut pose = Isometry3::identity();
+        self.for_each_ancestor(key, |transform| pose = transform.to_isometry3() * pose)?;
+        Some(pose)
+    }
+
+    /// ゲームオブジェクトのモデル座標系からワールド座標系への変換行列
+    ///
+    /// 親をたどって [`Scene::transforms`] を拡大縮小も含めて合成する。
+    pub fn world_matrix(&self, key: GameObjectKey) -> Option<Matrix4<f32>> {
+        let mut matrix = Matrix4::identity();
+        self.for_each_ancestor(key, |transform| {
+            matrix = transform.to_affine3().matrix() * matrix;
+        })?;
+        Some(matrix)
+    }
+
+    /// ゲームオブジェクト自身から根まで親をたどり、[`Scene::transforms`] を持つものについて `f` を呼ぶ
+    ///
+    /// 存在しないゲームオブジェクトに行き着いた場合と、親が循環している場合は `None` を返す。
+    fn for_each_ancestor(
+        &self,
+        key: GameObjectKey,
+        mut f: impl FnMut(&TransformComponent),
+    ) -> Option<()> {
+        let mut current = Some(key);
+        // 循環していなければ、たどる数はゲームオブジェクトの数を超えない
+        for _ in 0..self.game_objects.map.len() {
+            let Some(key) = current else {
+                return Some(());
+            };
+            let game_object = self.game_objects.map.get(key)?;
+            if let Some(transform) = self.transforms.get(key) {
+                f(transform);
+            }
+            current = game_object.parent;
+        }
+        if current.is_some() {
+            tracing::warn!(?key, "parent of game object forms a cycle");
+            return None;
+        }
+        Some(())
+    }
+
+    /// カメラに映るスプライトとそのワールド座標系への変換行列
+    ///
+    /// 視錐台の外にあるスプライトは `counters` に数える。カメラの描画先のテクスチャを使うスプライトは含めない。
+    pub fn visible_sprites(
+        &self,
+        camera: CameraKey,
+        frustum: &Frustum,
+        counters: &RenderCounterCells,
+    ) -> Vec<(GameObjectKey, Matrix4<f32>)> {
+        self.sprites
+            .keys()
+            .filter(|key| self.is_visible_to(*key, &self.cameras[camera]))
+            .filter(|key| {
+                !self.samples_own_target(camera, *self.sprites[*key].texture().get_texture_index())
+            })
+            .filter_map(|key| {
+                let world = self.world_matrix(key)?;
+                let bounds = match self.sprites[key].bounds(&self.textures, &world) {
+                    Ok(bounds) => bounds,
+                    Err(err) => {
+                        tracing::warn!(?err, ?key, "skipped sprite");
+                        return None;
+                    }
+                };
+                if frustum.intersects_aabb(&bounds) {
+                    Some((key, world))
+                } else {
+                    counters.add_sprites_culled(1);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// カメラに映るメッシュとそのワールド座標系への変換行列
+    ///
+    /// 視錐台の外にあるメッシュは `counters` に数える。カメラの描画先のテクスチャを使うマテリアルのメッシュは含めない。
+    pub fn visible_meshes(
+        &self,
+        camera: CameraKey,
+        frustum: &Frustum,
+        counters: &RenderCounterCells,
+    ) -> Vec<VisibleMesh> {
+        let mut visible = Vec::new();
+        for (key, model) in &self.models {
+            if !self.is_visible_to(key, &self.cameras[camera]) {
+                continue;
+            }
+            let Some(world) = self.world_matrix(key) else {
+                continue;
+            };
+            for &(mesh, material) in &model.meshes {
+                if self.materials.map.get(material).is_some_and(|material| {
+                    std::iter::once(material.texture)
+                        .chain(material.textures().map(|(_, texture)| texture))
+                        .any(|texture| {
+                            self.samples_own_target(camera, *texture.get_texture_index())
+                        })
+                }) {
+                    continue;
+                }
+                let Some(bounds) = self.meshes.map.get(mesh).and_then(|mesh| mesh.bounds) else {
+                    continue;
+                };
+                if frustum.intersects_aabb(&bounds.transformed(&world)) {
+                    visible.push((key, mesh, material, world));
+                } else {
+                    counters.add_meshes_culled(1);
+                }
+            }
+        }
+        visible
+    }
+
+    /// カメラに映る [`SpriteInstances`] のゲームオブジェクト
+    ///
+    /// インスタンスのないものと、カメラの描画先のテクスチャを使うものは含めない。
+    pub fn visible_instances(&self, camera: CameraKey) -> Vec<GameObjectKey> {
+        self.sprite_instances
+            .iter()
+            .filter(|(key, set)| {
+                !set.instances().is_empty()
+                    && self.is_visible_to(*key, &self.cameras[camera])
+                    && !self.samples_own_target(camera, set.texture)
+            })
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// スプライトを描画する順番に並べる
+    ///
+    /// 不透明なスプライトを手前から奥の順に先に並べ、半透明のスプライトを奥から手前の順に後に並べる。
+    /// 同じ深度の半透明のスプライトは、ソートレイヤーと [`Scene::sprite_sort`] に従って並べる。
+    /// 同じ深度の不透明なスプライトは、半透明の場合に後に描くものほど先に並べ、深度テストで手前に残るようにする。
+    pub fn sort_sprites(&self, camera: &Camera, sprites: &mut [(GameObjectKey, Matrix4<f32>)]) {
+        let view = camera.view_matrix();
+        let order = |(key, world): &(GameObjectKey, Matrix4<f32>)| {
+            self.transparent_order(
+                &view,
+                DrawKind::Sprite,
+                *key,
+                &world.transform_point(&Point3::origin()),
+            )
+        };
+        sprites.sort_by(|a, b| {
+            let (opaque_a, opaque_b) = (
+                self.sprites[a.0].blend.is_opaque(),
+                self.sprites[b.0].blend.is_opaque(),
+            );
+            opaque_b.cmp(&opaque_a).then_with(|| {
+                let (order_a, order_b) = (order(a), order(b));
+                if opaque_a {
+                    // 同じ深度では先に描いたものが深度テストで残るので、手前のレイヤーを先に描く
+                    order_a
+                        .depth
+                        .total_cmp(&order_b.depth)
+                        .then_with(|| order_a.compare(&order_b).reverse())
+                } else {
+                    order_a.compare(&order_b)
+                }
+            })
+        });
+    }
+
+    /// メッシュを描画する順番に並べる
+    ///
+    /// 不透明なメッシュを手前から奥の順に先に並べ、半透明のメッシュを奥から手前の順に後に並べる。
+    /// 前後はメッシュを囲む直方体の中心のビュー座標系での深度で決める。
+    pub fn sort_meshes(&self, camera: &Camera, meshes: &mut [VisibleMesh]) {
+        let view = camera.view_matrix();
+        let order = |visible: &VisibleMesh| {
+            self.transparent_order(&view, DrawKind::Mesh, visible.0, &self.mesh_center(visible))
+        };
+        meshes.sort_by(|a, b| {
+            let (opaque_a, opaque_b) = (self.is_opaque_mesh(a), self.is_opaque_mesh(b));
+            opaque_b
+                .cmp(&opaque_a)
+                .then_with(|| {
+                    let (order_a, order_b) = (order(a), order(b));
+                    if opaque_a {
+                        order_a.depth.total_cmp(&order_b.depth)
+                    } else {
+                        order_a.compare(&order_b)
+                    }
+                })
+                .then_with(|| a.0.cmp(&b.0))
+        });
+    }
+
+    /// カメラに映るものを描画する順番に並べる
+    ///
+    /// 不透明なものは種類ごとに [`Scene::sort_meshes`] と [`Scene::sort_sprites`] で並べる。
+    /// 半透明のものはメッシュ、スプライト、インスタンシングのスプライトをまとめて、ビュー座標系での深度の奥から手前の順に並べる。
+    /// 深度が同じ場合は、ソートレイヤーと [`Scene::sprite_sort`] に従って並べる。
+    ///
+    /// 深度は、メッシュは囲む直方体の中心、スプライトはゲームオブジェクトの位置、
+    /// [`SpriteInstances`] はインスタンスの位置の平均で決める。インスタンス同士は並んだ順に描画する。
+    pub fn draw_list(
+        &self,
+        camera: CameraKey,
+        frustum: &Frustum,
+        counters: &RenderCounterCells,
+    ) -> DrawList {
+        let view = self.cameras[camera].view_matrix();
+        let mut meshes = self.visible_meshes(camera, frustum, counters);
+        self.sort_meshes(&self.cameras[camera], &mut meshes);
+        let mut sprites = self.visible_sprites(camera, frustum, counters);
+        self.sort_sprites(&self.cameras[camera], &mut sprites);
+        let (instances, transparent_instances): (Vec<_>, Vec<_>) = self
+            .visible_instances(camera)
+            .into_iter()
+            .partition(|key| self.sprite_instances[*key].blend.is_opaque());
+
+        let mut transparent = Vec::new();
+        let opaque_meshes = meshes.partition_point(|visible| self.is_opaque_mesh(visible));
+        for visible in meshes.split_off(opaque_meshes) {
+            let order = self.transparent_order(
+                &view,
+                DrawKind::Mesh,
+                visible.0,
+                &self.mesh_center(&visible),
+            );
+            transparent.push((order, TransparentDraw::Mesh(visible)));
+        }
+        let opaque_sprites =
+            sprites.partition_point(|(key, _)| self.sprites[*key].blend.is_opaque());
+        for (key, world) in sprites.split_off(opaque_sprites) {
+            let position = world.transform_point(&Point3::origin());
+            let order = self.transparent_order(&view, DrawKind::Sprite, key, &position);
+            transparent.push((order, TransparentDraw::Sprite(key, world)));
+        }
+        for key in transparent_instances {
+            let Some(center) = self.sprite_instances[key].center() else {
+                continue;
+            };
+            let order = self.transparent_order(&view, DrawKind::Instances, key, &center);
+            transparent.push((order, TransparentDraw::Instances(key)));
+        }
+        transparent.sort_by(|(a, _), (b, _)| a.compare(b));
+
+        let mut list = DrawList {
+            meshes: vec![meshes],
+            sprites: vec![sprites],
+            instances: vec![instances],
+            transparent: Vec::new(),
+        };
+        for (order, draw) in transparent {
+            let new_group = list.transparent.last() != Some(&order.kind);
+            if new_group {
+                list.transparent.push(order.kind);
+            }
+            match draw {
+                TransparentDraw::Mesh(visible) => {
+                    push_to_group(&mut list.meshes, new_group, visible);
+                }
+                TransparentDraw::Sprite(key, world) => {
+                    push_to_group(&mut list.sprites, new_group, (key, world));
+                }
+                TransparentDraw::Instances(key) => {
+                    push_to_group(&mut list.instances, new_group, key);
+                }
+            }
+        }
+        list
+    }
+
+    /// 半透明のものを描画する順番を決めるための値
+    ///
+    /// * `position`: ワールド座標系での位置
+    fn transparent_order(
+        &self,
+        view: &Matrix4<f32>,
+        kind: DrawKind,
+        key: GameObjectKey,
+        position: &Point3<f32>,
+    ) -> TransparentOrder {
+        let depth = (view * position.to_homogeneous()).z;
+        let (layer, order) = match kind {
+            DrawKind::Sprite => {
+                let sprite = &self.sprites[key];
+                (sprite.sorting_layer, sprite.order_in_layer)
+            }
+            DrawKind::Mesh | DrawKind::Instances => (0, 0),
+        };
+        let secondary = match self.sprite_sort {
+            SpriteSortMode::Order => order as f32,
+            SpriteSortMode::Y => -position.y,
+            SpriteSortMode::CameraDistance => -depth,
+        };
+        TransparentOrder {
+            depth,
+            layer,
+            secondary,
+            order,
+            kind,
+            key,
+        }
+    }
+
+    /// マテリアルが不透明なメッシュかどうか。マテリアルがない場合も不透明として扱う。
+    fn is_opaque_mesh(&self, (_, _, material, _): &VisibleMesh) -> bool {
+        self.materials
+            .map
+            .get(*material)
+            .is_none_or(|material| material.blend.is_opaque())
+    }
+
+    /// メッシュを囲む直方体の中心 (ワールド座標系)
+    fn mesh_center(&self, (_, mesh, _, world): &VisibleMesh) -> Point3<f32> {
+        let center = self
+            .meshes
+            .map
+            .get(*mesh)
+            .and_then(|mesh| mesh.bounds)
+            .map_or_else(Point3::origin, |bounds| bounds.center());
+        world.transform_point(&center)
+    }
+
+    /// カメラに映るスプライトのテクスチャと頂点。描画する順番に並ぶ。
+    ///
+    /// アトラスのスプライトは、アトラス全体のテクスチャの [`TextureIndex`] になる。
+    pub fn sprite_quads(
+        &self,
+        camera: CameraKey,
+        frustum: &Frustum,
+        counters: &RenderCounterCells,
+    ) -> Vec<SpriteQuad> {
+        let mut sprites = self.visible_sprites(camera, frustum, counters);
+        self.sort_sprites(&self.cameras[camera], &mut sprites);
+        sprites
+            .into_iter()
+            .filter_map(|(key, world)| self.sprite_quad(key, &world))
+            .collect()
+    }
+
+    /// スプライトのテクスチャと頂点。頂点を求められない場合は警告を出して `None` を返す。
+    ///
+    /// * `world`: [`Scene::visible_sprites`] が返すワールド座標系への変換行列
+    pub fn sprite_quad(&self, key: GameObjectKey, world: &Matrix4<f32>) -> Option<SpriteQuad> {
+        let sprite = &self.sprites[key];
+        match sprite.vertices(&self.textures, world) {
+            Ok(vertices) => Some((
+                *sprite.texture().get_texture_index(),
+                sprite.blend,
+                vertices,
+            )),
+            Err(err) => {
+                tracing::warn!(?err, ?key, "skipped sprite");
+                None
+            }
+        }
+    }
+
+    /// `pass` 番目のカメラから見たシーンを描画する。ビューポートは呼び出し側で設定する。
+    ///
+    /// 不透明なものを種類ごとに描画してから、半透明のものを [`Scene::draw_list`] で並べた順に描画する。
+    ///
+    /// * `pass`: [`RenderingResource::camera_bindings`] の添字
+    pub fn render(
+        &self,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &RenderingResource<'_>,
+        pass: usize,
+    ) {
+        for kind in [DrawKind::Mesh, DrawKind::Sprite, DrawKind::Instances] {
+            self.render_phase(rp, resource, pass, kind, RenderPhase::Opaque);
+        }
+        let mut groups = [0; 3];
+        for &kind in resource.transparent_order.get(pass).into_iter().flatten() {
+            let group = &mut groups[kind as usize];
+            self.render_phase(rp, resource, pass, kind, RenderPhase::Transparent(*group));
+            *group += 1;
+        }
+    }
+
+    /// `pass` 番目のカメラの `kind` の描画のうち、`phase` のものを描画する
+    fn render_phase(
+        &self,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &RenderingResource<'_>,
+        pass: usize,
+        kind: DrawKind,
+        phase: RenderPhase,
+    ) {
+        let camera_bind_group = &resource.camera_bindings[pass].bind_group;
+        let counters = &resource.counters;
+        match kind {
+            DrawKind::Mesh => {
+                resource
+                    .mesh_renderer
+                    .render(rp, self, counters, camera_bind_group, pass, phase)
+            }
+            DrawKind::Sprite => resource.sprite_batcher.render(
+                rp,
+                &self.textures,
+                counters,
+                &resource.sprite_pipeline.pipelines,
+                camera_bind_group,
+                pass,
+                phase,
+            ),
+            DrawKind::Instances => resource.instanced_sprites.render(
+                rp,
+                &self.textures,
+                counters,
+                camera_bind_group,
+                pass,
+                phase,
+            ),
+        }
     }
 
     pub fn new_game_object(
@@ -95,7 +710,11 @@ impl Scene {
         name: String,
         parent: Option<GameObjectKey>,
     ) -> GameObjectKey {
-        let game_object = GameObject { name, parent };
+        let game_object = GameObject {
+            name,
+            parent,
+            layers: LayerMask::DEFAULT,
+        };
         self.game_objects.map.insert(game_object)
     }
 }
@@ -138,6 +757,8 @@ impl<K: slotmap::Key, V> Default for DenseRegistry<K, V> {
 pub struct GameObject {
     pub name: String,
     pub parent: Option<GameObjectKey>,
+    /// 属するレイヤー。カメラの [`Camera::layers`] と共通のレイヤーがある場合に描画される。
+    pub layers: LayerMask,
 }
 
 slotmap::new_key_type! { pub struct GameObjectKey; }
@@ -157,3 +778,397 @@ impl<K: slotmap::Key, V> std::fmt::Debug for DenseRegistry<K, V> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use image::RgbaImage;
+    use nalgebra::{Translation3, UnitQuaternion};
+
+    use super::*;
+    use crate::camera::Viewport;
+
+    #[test]
+    fn attached_camera_follows_game_object() {
+        let mut scene = Scene::default();
+        let parent = scene.new_game_object("parent".to_owned(), None);
+        let child = scene.new_game_object("child".to_owned(), Some(parent));
+        scene.transforms.insert(
+            parent,
+            TransformComponent::with_translation_and_rotation(
+                Translation3::new(1.0, 0.0, 0.0),
+                UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 90.0_f32.to_radians()),
+            ),
+        );
+        scene.transforms.insert(
+            child,
+            TransformComponent::with_translation(Translation3::new(0.0, 0.0, 2.0)),
+        );
+
+        scene.main_camera_mut().unwrap().attached_to = Some(child);
+        scene.update_camera_attachment();
+
+        let camera = scene.main_camera().unwrap();
+        assert!((camera.position() - Point3::new(3.0, 0.0, 0.0)).norm() < 1e-5);
+        assert!((camera.forward().into_inner() - Vector3::x()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn parent_cycle_has_no_world_transform() {
+        let mut scene = Scene::default();
+        let a = scene.new_game_object("a".to_owned(), None);
+        let b = scene.new_game_object("b".to_owned(), Some(a));
+        assert!(scene.world_matrix(b).is_some());
+
+        scene.game_objects.map[a].parent = Some(b);
+        assert!(scene.world_pose(b).is_none());
+        assert!(scene.world_matrix(a).is_none());
+    }
+
+    #[test]
+    fn cameras_render_by_priority_and_layer() {
+        let mut scene = Scene::default();
+        let mut minimap = scene.main_camera().unwrap().clone();
+        minimap.priority = 10;
+        minimap.layers = LayerMask::layer(1);
+        let minimap = scene.cameras.insert(minimap);
+        scene.cameras[scene.main_camera].priority = 1;
+        let background = scene
+            .cameras
+            .insert(scene.cameras[scene.main_camera].clone());
+        scene.cameras[background].priority = -5;
+
+        assert_eq!(
+            scene.cameras_in_render_order(),
+            vec![background, scene.main_camera, minimap]
+        );
+
+        let mut textures = TextureRegistry::default();
+        let monitor = textures.create_render_target(
+            NonZeroU32::new(64).unwrap(),
+            NonZeroU32::new(64).unwrap(),
+            None,
+        );
+        let mut security_camera = scene.cameras[minimap].clone();
+        security_camera.target = CameraTarget::Texture(monitor);
+        let security_camera = scene.cameras.insert(security_camera);
+        assert_eq!(scene.cameras_in_render_order()[0], security_camera);
+
+        let player = scene.new_game_object("player".to_owned(), None);
+        assert!(scene.is_visible_to(player, &scene.cameras[scene.main_camera]));
+        assert!(!scene.is_visible_to(player, &scene.cameras[minimap]));
+        scene.game_objects.map[player].layers = LayerMask::DEFAULT.with(1);
+        assert!(scene.is_visible_to(player, &scene.cameras[minimap]));
+    }
+
+    #[test]
+    fn sprites_outside_frustum_are_culled() {
+        let mut scene = Scene::default();
+        let texture = scene.textures.new_texture(RgbaImage::new(16, 16), None);
+        let mut add_sprite = |name: &str, x: f32| {
+            let key = scene.new_game_object(name.to_owned(), None);
+            scene.transforms.insert(
+                key,
+                TransformComponent::with_translation(Translation3::new(x, 0.0, 1.0)),
+            );
+            scene
+                .sprites
+                .insert(key, SpriteComponent::new(texture.into()));
+            key
+        };
+        let visible = add_sprite("visible", 0.0);
+        let _culled = add_sprite("culled", 50.0);
+        let hidden = add_sprite("hidden", 0.0);
+        scene.game_objects.map[hidden].layers = LayerMask::layer(3);
+        scene.main_camera_mut().unwrap().layers = LayerMask::DEFAULT;
+
+        let camera = scene.main_camera().unwrap();
+        let viewport = Viewport::new(NonZeroU32::new(800).unwrap(), NonZeroU32::new(600).unwrap());
+        let counters = RenderCounterCells::default();
+        let sprites =
+            scene.visible_sprites(scene.main_camera, &camera.frustum(&viewport), &counters);
+        assert_eq!(
+            sprites.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            vec![visible]
+        );
+        assert_eq!(counters.take().sprites_culled, 1);
+    }
+
+    #[test]
+    fn camera_does_not_draw_its_own_render_target() {
+        let mut scene = Scene::default();
+        let image = scene.textures.new_texture(RgbaImage::new(16, 16), None);
+        let monitor = scene.textures.create_render_target(
+            NonZeroU32::new(16).unwrap(),
+            NonZeroU32::new(16).unwrap(),
+            None,
+        );
+        let mut add_sprite = |name: &str, texture: TextureIndex| {
+            let key = scene.new_game_object(name.to_owned(), None);
+            scene.transforms.insert(
+                key,
+                TransformComponent::with_translation(Translation3::new(0.0, 0.0, 1.0)),
+            );
+            scene
+                .sprites
+                .insert(key, SpriteComponent::new(texture.into()));
+            key
+        };
+        let player = add_sprite("player", image);
+        let screen = add_sprite("screen", monitor);
+
+        // 監視カメラはモニターに描画し、メインカメラがモニターを映す
+        let mut security = scene.main_camera().unwrap().clone();
+        security.target = CameraTarget::Texture(monitor);
+        security.layers = LayerMask::ALL;
+        let security = scene.cameras.insert(security);
+        let mut other = scene.cameras[security].clone();
+        other.target = CameraTarget::Texture(image);
+        let other = scene.cameras.insert(other);
+
+        let viewport = Viewport::new(NonZeroU32::new(800).unwrap(), NonZeroU32::new(600).unwrap());
+        let counters = RenderCounterCells::default();
+        let keys = |camera: CameraKey| {
+            let frustum = scene.cameras[camera].frustum(&viewport);
+            let mut keys: Vec<_> = scene
+                .visible_sprites(camera, &frustum, &counters)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+            keys.sort();
+            keys
+        };
+        let mut both = vec![player, screen];
+        both.sort();
+        assert_eq!(keys(scene.main_camera), both);
+        assert_eq!(keys(security), vec![player]);
+
+        let frustum = scene.cameras[security].frustum(&viewport);
+        let quads = scene.sprite_quads(security, &frustum, &counters);
+        assert!(quads.iter().all(|(texture, _, _)| *texture != monitor));
+
+        // 警告はカメラとテクスチャの組ごとに覚える
+        assert_eq!(keys(other), vec![screen]);
+        let warned = scene.warned_own_targets.borrow();
+        assert!(warned.contains(&(security, monitor)));
+        assert!(warned.contains(&(other, image)));
+        assert_eq!(warned.len(), 2);
+    }
+
+    #[test]
+    fn sprites_are_sorted_by_layer_then_mode() {
+        let mut scene = Scene::default();
+        let texture = scene.textures.new_texture(RgbaImage::new(16, 16), None);
+        let mut add_sprite = |y: f32, layer: i32, order: i32| {
+            let key = scene.new_game_object(String::new(), None);
+            scene.transforms.insert(
+                key,
+                TransformComponent::with_translation(Translation3::new(0.0, y, 1.0)),
+            );
+            scene.sprites.insert(
+                key,
+                SpriteComponent::new(texture.into()).with_sorting(layer, order),
+            );
+            key
+        };
+        let foreground = add_sprite(0.0, 1, 0);
+        let low = add_sprite(-1.0, 0, 1);
+        let high = add_sprite(1.0, 0, 2);
+
+        let camera = scene.main_camera().unwrap();
+        let mut sprites: Vec<_> = [foreground, low, high]
+            .into_iter()
+            .map(|key| (key, scene.world_matrix(key).unwrap()))
+            .collect();
+        let order = |sprites: &[(GameObjectKey, Matrix4<f32>)]| {
+            sprites.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        };
+
+        scene.sort_sprites(camera, &mut sprites);
+        assert_eq!(order(&sprites), vec![low, high, foreground]);
+
+        // 見下ろし型では画面の上にあるスプライトを先に描画する
+        scene.sprite_sort = SpriteSortMode::Y;
+        let camera = scene.main_camera().unwrap();
+        scene.sort_sprites(camera, &mut sprites);
+        assert_eq!(order(&sprites), vec![high, low, foreground]);
+    }
+
+    #[test]
+    fn coplanar_opaque_sprites_draw_front_layer_first() {
+        let mut scene = Scene::default();
+        let texture = scene.textures.new_texture(RgbaImage::new(16, 16), None);
+        let mut add_sprite = |layer: i32| {
+            let key = scene.new_game_object(String::new(), None);
+            scene.transforms.insert(
+                key,
+                TransformComponent::with_translation(Translation3::new(0.0, 0.0, 1.0)),
+            );
+            scene.sprites.insert(
+                key,
+                SpriteComponent::new(texture.into())
+                    .with_sorting(layer, 0)
+                    .with_blend(BlendMode::Opaque),
+            );
+            key
+        };
+        // キーの順では奥のレイヤーが先になるようにする
+        let back = add_sprite(0);
+        let front = add_sprite(1);
+
+        let camera = scene.main_camera().unwrap();
+        let mut sprites: Vec<_> = [back, front]
+            .into_iter()
+            .map(|key| (key, scene.world_matrix(key).unwrap()))
+            .collect();
+        scene.sort_sprites(camera, &mut sprites);
+        // 深度テストは先に描いたものを残すので、手前のレイヤーを先に描く
+        assert_eq!(
+            sprites.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            vec![front, back]
+        );
+    }
+
+    #[test]
+    fn meshes_are_sorted_by_phase_and_depth() {
+        let mut scene = Scene::default();
+        let texture = scene.textures.new_texture(RgbaImage::new(16, 16), None);
+        let vertex = |x: f32| Vertex {
+            position: [x, 0.0, 0.0],
+            uv: [0.0, 0.0],
+            normal: [0.0, 0.0, -1.0],
+            color: [1.0; 4],
+        };
+        let mesh = scene.meshes.map.insert(Mesh::new(
+            "triangle".to_owned(),
+            vec![vertex(0.0), vertex(1.0), vertex(2.0)],
+            vec![0, 1, 2],
+        ));
+        let opaque = scene.materials.map.insert(Material::new(texture.into()));
+        let transparent = scene
+            .materials
+            .map
+            .insert(Material::new(texture.into()).with_blend(BlendMode::Alpha));
+        let mut add_model = |z: f32, material: MaterialKey| {
+            let key = scene.new_game_object(String::new(), None);
+            scene.transforms.insert(
+                key,
+                TransformComponent::with_translation(Translation3::new(0.0, 0.0, z)),
+            );
+            scene.models.insert(
+                key,
+                ModelComponent {
+                    meshes: vec![(mesh, material)],
+                },
+            );
+            key
+        };
+        let far_opaque = add_model(3.0, opaque);
+        let near_opaque = add_model(1.0, opaque);
+        let near_transparent = add_model(1.0, transparent);
+        let far_transparent = add_model(3.0, transparent);
+
+        let camera = scene.main_camera().unwrap();
+        let viewport = Viewport::new(NonZeroU32::new(800).unwrap(), NonZeroU32::new(600).unwrap());
+        let counters = RenderCounterCells::default();
+        let mut meshes =
+            scene.visible_meshes(scene.main_camera, &camera.frustum(&viewport), &counters);
+        scene.sort_meshes(camera, &mut meshes);
+        // 不透明なものは手前から、半透明のものは奥から描画する
+        assert_eq!(
+            meshes.iter().map(|(key, ..)| *key).collect::<Vec<_>>(),
+            vec![near_opaque, far_opaque, far_transparent, near_transparent]
+        );
+    }
+
+    #[test]
+    fn transparent_draws_are_sorted_across_kinds() {
+        let mut scene = Scene::default();
+        let texture = scene.textures.new_texture(RgbaImage::new(16, 16), None);
+        let place = |scene: &mut Scene, z: f32| {
+            let key = scene.new_game_object(String::new(), None);
+            scene.transforms.insert(
+                key,
+                TransformComponent::with_translation(Translation3::new(0.0, 0.0, z)),
+            );
+            key
+        };
+        let add_sprite = |scene: &mut Scene, z: f32, layer: i32, blend: BlendMode| {
+            let key = place(scene, z);
+            scene.sprites.insert(
+                key,
+                SpriteComponent::new(texture.into())
+                    .with_sorting(layer, 0)
+                    .with_blend(blend),
+            );
+            key
+        };
+        let far = add_sprite(&mut scene, 3.0, 0, BlendMode::Alpha);
+        let opaque = add_sprite(&mut scene, 2.0, 0, BlendMode::Opaque);
+        // 深度が同じ場合はレイヤーの順になる
+        let near_front = add_sprite(&mut scene, 1.0, 1, BlendMode::Alpha);
+        let near_back = add_sprite(&mut scene, 1.0, 0, BlendMode::Alpha);
+
+        let vertex = |x: f32| Vertex {
+            position: [x, 0.0, 0.0],
+            uv: [0.0, 0.0],
+            normal: [0.0, 0.0, -1.0],
+            color: [1.0; 4],
+        };
+        let mesh = scene.meshes.map.insert(Mesh::new(
+            "triangle".to_owned(),
+            vec![vertex(-0.5), vertex(0.0), vertex(0.5)],
+            vec![0, 1, 2],
+        ));
+        let material = scene
+            .materials
+            .map
+            .insert(Material::new(texture.into()).with_blend(BlendMode::Alpha));
+        let model = place(&mut scene, 2.0);
+        scene.models.insert(
+            model,
+            ModelComponent {
+                meshes: vec![(mesh, material)],
+            },
+        );
+
+        let instances = place(&mut scene, 0.0);
+        let mut set = SpriteInstances::new(texture);
+        set.set_instances(
+            [1.25, 1.75]
+                .map(|z| SpriteInstance::new(&Matrix4::new_translation(&Vector3::new(0.0, 0.0, z))))
+                .to_vec(),
+        );
+        scene.sprite_instances.insert(instances, set);
+
+        let camera = scene.main_camera().unwrap();
+        let viewport = Viewport::new(NonZeroU32::new(800).unwrap(), NonZeroU32::new(600).unwrap());
+        let counters = RenderCounterCells::default();
+        let list = scene.draw_list(scene.main_camera, &camera.frustum(&viewport), &counters);
+        // 半透明のものは種類をまたいで奥から描画する
+        assert_eq!(
+            list.transparent,
+            vec![
+                DrawKind::Sprite,
+                DrawKind::Mesh,
+                DrawKind::Instances,
+                DrawKind::Sprite
+            ]
+        );
+        let keys = |group: &[(GameObjectKey, Matrix4<f32>)]| {
+            group.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            list.sprites
+                .iter()
+                .map(|group| keys(group))
+                .collect::<Vec<_>>(),
+            vec![vec![opaque], vec![far], vec![near_back, near_front]]
+        );
+        assert_eq!(list.meshes.len(), 2);
+        assert!(list.meshes[0].is_empty());
+        assert_eq!(list.meshes[1][0].0, model);
+        assert_eq!(list.instances, vec![vec![], vec![instances]]);
+    }
+}
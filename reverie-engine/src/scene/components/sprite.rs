@@ -1,114 +1,410 @@
 #![allow(dead_code)]
-use anyhow::Context;
-use nalgebra::{Matrix4, Point3, Vector3};
-use tracing_unwrap::ResultExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+use tracing_unwrap::OptionExt;
 
 use crate::{
-    model::Vertex,
-    render::{RenderingResource, buffer::VertexIndexBuffer, sprite},
-    scene::{Scene, TransformComponent},
-    texture::TextureId,
+    culling::Aabb,
+    model::{BlendMode, Vertex},
+    texture::{TextureId, TextureIndex, TextureRegistry},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// スプライトのモデル座標系での大きさ
+pub enum SpriteSize {
+    /// 固定の大きさ
+    Fixed(Vector2<f32>),
+    /// テクスチャ (または [`SpriteComponent::source_rect`]) のピクセル数を `pixels_per_unit` で割った大きさ
+    Pixels { pixels_per_unit: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// テクスチャの一部を指す矩形 (ピクセル)。アトラスのテクスチャではアロケーションの左上からの位置になる。
+pub struct SourceRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SourceRect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 /// エンティティの見た目を表すコンポーネント
+///
+/// 描画はレンダラーが同じテクスチャのスプライトをまとめて行う。
 pub struct SpriteComponent {
     texture: TextureId,
-    buffer: Option<VertexIndexBuffer<Vertex>>,
+    /// テクスチャの色に掛ける色 (RGBA)。アルファは不透明度になる。
+    pub tint: [f32; 4],
+    /// 左右を反転する
+    pub flip_x: bool,
+    /// 上下を反転する
+    pub flip_y: bool,
+    /// ゲームオブジェクトの位置に合わせる点。(0.0, 0.0) が左下、(1.0, 1.0) が右上。
+    pub pivot: Vector2<f32>,
+    pub size: SpriteSize,
+    /// 描画するテクスチャの範囲。`None` の場合はテクスチャ全体。
+    pub source_rect: Option<SourceRect>,
+    /// ソートレイヤー。深度が同じ場合、小さいレイヤーのスプライトが先 (奥) に描画される。
+    pub sorting_layer: i32,
+    /// 同じソートレイヤーでの順番。小さいスプライトが先 (奥) に描画される。
+    pub order_in_layer: i32,
+    pub blend: BlendMode,
 }
 
+/// 四角形の頂点の位置 (0.0 から 1.0)。左上、右上、左下、右下の順。
+const POINTS: [[f32; 2]; 4] = [[0.0, 1.0], [1.0, 1.0], [0.0, 0.0], [1.0, 0.0]];
+
 impl SpriteComponent {
+    /// テクスチャ全体を原点を中心とする 1x1 の四角形に描くスプライトを作る
     pub const fn new(texture: TextureId) -> Self {
         Self {
             texture,
-            buffer: None,
+            tint: [1.0; 4],
+            flip_x: false,
+            flip_y: false,
+            pivot: Vector2::new(0.5, 0.5),
+            size: SpriteSize::Fixed(Vector2::new(1.0, 1.0)),
+            source_rect: None,
+            sorting_layer: 0,
+            order_in_layer: 0,
+            blend: BlendMode::Alpha,
+        }
+    }
+
+    pub const fn texture(&self) -> TextureId {
+        self.texture
+    }
+
+    pub const fn with_tint(mut self, tint: [f32; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    /// 不透明度 (0.0 から 1.0) を設定する
+    pub const fn with_opacity(mut self, opacity: f32) -> Self {
+        self.tint[3] = opacity;
+        self
+    }
+
+    pub const fn with_flip(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+
+    pub const fn with_pivot(mut self, pivot: Vector2<f32>) -> Self {
+        self.pivot = pivot;
+        self
+    }
+
+    pub const fn with_size(mut self, size: SpriteSize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// テクスチャの 1 ピクセルがモデル座標系で `1.0 / pixels_per_unit` になる大きさにする
+    pub const fn with_pixels_per_unit(self, pixels_per_unit: f32) -> Self {
+        self.with_size(SpriteSize::Pixels { pixels_per_unit })
+    }
+
+    pub const fn with_source_rect(mut self, rect: SourceRect) -> Self {
+        self.source_rect = Some(rect);
+        self
+    }
+
+    pub const fn with_sorting(mut self, sorting_layer: i32, order_in_layer: i32) -> Self {
+        self.sorting_layer = sorting_layer;
+        self.order_in_layer = order_in_layer;
+        self
+    }
+
+    pub const fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// 描画するテクスチャの範囲の大きさ (ピクセル)
+    fn source_size(&self, textures: &TextureRegistry) -> anyhow::Result<Vector2<f32>> {
+        let (width, height) = match self.source_rect {
+            Some(rect) => (rect.width, rect.height),
+            None => textures.get_size(self.texture)?,
+        };
+        Ok(Vector2::new(width as f32, height as f32))
+    }
+
+    /// モデル座標系での四角形の左下と右上
+    fn local_rect(
+        &self,
+        textures: &TextureRegistry,
+    ) -> anyhow::Result<(Vector2<f32>, Vector2<f32>)> {
+        let size = match self.size {
+            SpriteSize::Fixed(size) => size,
+            SpriteSize::Pixels { pixels_per_unit } => self.source_size(textures)? / pixels_per_unit,
+        };
+        let min = -self.pivot.component_mul(&size);
+        Ok((min, min + size))
+    }
+
+    /// テクスチャ座標の範囲 (左上の u, v, 右下の u, v)。反転している場合は左右や上下が入れ替わる。
+    fn uv_rect(&self, textures: &TextureRegistry) -> anyhow::Result<[f32; 4]> {
+        let (mut min_u, mut min_v, mut max_u, mut max_v) = textures.get_uv(self.texture)?;
+        if let Some(rect) = self.source_rect {
+            let (width, height) = textures.get_size(self.texture)?;
+            let (du, dv) = (
+                (max_u - min_u) / width as f32,
+                (max_v - min_v) / height as f32,
+            );
+            min_u += du * rect.x as f32;
+            min_v += dv * rect.y as f32;
+            max_u = du.mul_add(rect.width as f32, min_u);
+            max_v = dv.mul_add(rect.height as f32, min_v);
+        }
+        if self.flip_x {
+            std::mem::swap(&mut min_u, &mut max_u);
+        }
+        if self.flip_y {
+            std::mem::swap(&mut min_v, &mut max_v);
+        }
+        Ok([min_u, min_v, max_u, max_v])
+    }
+
+    /// ワールド座標系での四角形の頂点。左上、右上、左下、右下の順。
+    ///
+    /// * `world`: モデル座標系からワールド座標系への変換行列
+    pub fn corners(
+        &self,
+        textures: &TextureRegistry,
+        world: &Matrix4<f32>,
+    ) -> anyhow::Result<[Point3<f32>; 4]> {
+        let (min, max) = self.local_rect(textures)?;
+        Ok(POINTS.map(|[x, y]| {
+            world.transform_point(&Point3::new(
+                (max.x - min.x).mul_add(x, min.x),
+                (max.y - min.y).mul_add(y, min.y),
+                0.0,
+            ))
+        }))
+    }
+
+    /// ワールド座標系で四角形を囲む直方体
+    pub fn bounds(&self, textures: &TextureRegistry, world: &Matrix4<f32>) -> anyhow::Result<Aabb> {
+        Ok(Aabb::from_points(&self.corners(textures, world)?).unwrap_or_log())
+    }
+
+    /// ワールド座標系での四角形の頂点データ。左上、右上、左下、右下の順。
+    ///
+    /// * `world`: モデル座標系からワールド座標系への変換行列
+    pub fn vertices(
+        &self,
+        textures: &TextureRegistry,
+        world: &Matrix4<f32>,
+    ) -> anyhow::Result<[Vertex; 4]> {
+        let [min_u, min_v, max_u, max_v] = self.uv_rect(textures)?;
+        let [tl, tr, bl, br] = self.corners(textures, world)?;
+        let normal = world
+            .transform_vector(&Vector3::new(0., 0., 1.))
+            .normalize()
+            .into();
+        let vertex = |position: Point3<f32>, uv| Vertex {
+            position: position.into(),
+            uv,
+            normal,
+            color: self.tint,
+        };
+        Ok([
+            vertex(tl, [min_u, min_v]),
+            vertex(tr, [max_u, min_v]),
+            vertex(bl, [min_u, max_v]),
+            vertex(br, [max_u, max_v]),
+        ])
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+/// インスタンシングで描画する 1 つのスプライト
+pub struct SpriteInstance {
+    /// モデル座標系からワールド座標系への変換行列 (列優先)。四角形は原点を中心とする 1x1 の大きさ。
+    pub model: [[f32; 4]; 4],
+    /// テクスチャ座標の範囲 (min_u, min_v, max_u, max_v)
+    pub uv_rect: [f32; 4],
+    /// テクスチャの色に掛ける色
+    pub tint: [f32; 4],
+    /// ワールド座標系の Z に足す値。メッシュとの前後関係に使う。インスタンス同士は並んだ順に描画される。
+    pub layer: f32,
+}
+
+impl SpriteInstance {
+    /// テクスチャ全体を白で描くインスタンスを作る
+    pub fn new(model: &Matrix4<f32>) -> Self {
+        Self {
+            model: (*model).into(),
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            tint: [1.0; 4],
+            layer: 0.0,
+        }
+    }
+
+    /// テクスチャ座標の範囲を `textures` に登録された `id` の範囲にする
+    pub fn with_texture(
+        mut self,
+        textures: &TextureRegistry,
+        id: TextureId,
+    ) -> anyhow::Result<Self> {
+        self.uv_rect = textures.get_uv(id)?.into();
+        Ok(self)
+    }
+
+    pub const fn with_tint(mut self, tint: [f32; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub const fn with_layer(mut self, layer: f32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+#[derive(Debug)]
+/// 同じテクスチャを使う大量のスプライトを 1 回のドローコールで描画するコンポーネント
+///
+/// 弾やパーティクルのように数が多いものに使う。
+/// インスタンスの位置はワールド座標系で指定し、ゲームオブジェクトの位置の影響を受けない。
+/// ゲームオブジェクトはレイヤーによる表示の切り替えにだけ使われる。視錐台カリングは行わない。
+///
+/// 半透明の場合、セット全体を [`SpriteInstances::center`] の深度で他のものと奥から手前の順に並べる。
+/// セットの中のインスタンスは並んだ順に描画される。
+///
+/// インスタンスは [`SpriteInstances::instances_mut`] などで書き換えたときだけ GPU に送信し直す。
+pub struct SpriteInstances {
+    /// 単一のテクスチャか、アトラス全体のテクスチャ
+    pub texture: TextureIndex,
+    pub blend: BlendMode,
+    instances: Vec<SpriteInstance>,
+    /// インスタンスを書き換えるたびに変わる番号
+    revision: u64,
+}
+
+impl SpriteInstances {
+    pub fn new(texture: TextureIndex) -> Self {
+        Self {
+            texture,
+            blend: BlendMode::Alpha,
+            instances: Vec::new(),
+            revision: next_revision(),
         }
     }
 
-    pub(crate) fn setup(&mut self, resource: &RenderingResource<'_>) {
-        let buffer = VertexIndexBuffer::new(&resource.device, 4, 6, None).unwrap_or_log();
-        self.buffer = Some(buffer);
-    }
-
-    pub(crate) fn render(
-        &mut self,
-        scene: &Scene,
-        rp: &mut wgpu::RenderPass<'_>,
-        resource: &RenderingResource<'_>,
-        transform: &TransformComponent,
-    ) {
-        if let Some(buffer) = &mut self.buffer {
-            // バッファのアップデート
-            {
-                let mut update = buffer.start_update(&resource.queue);
-                let (min_u, min_v, max_u, max_v) =
-                    scene.textures.get_uv(self.texture).unwrap_or_log();
-                let affine = transform.to_affine3();
-                const POINTS: Matrix4<f32> = Matrix4::new(
-                    -0.5, 0.5, -0.5, 0.5, //
-                    0.5, 0.5, -0.5, -0.5, //
-                    0.0, 0.0, 0.0, 0.0, //
-                    1.0, 1.0, 1.0, 1.0, //
-                );
-                let points = affine.matrix() * POINTS;
-                let tl = Point3::from_homogeneous(points.column(0).into()).unwrap();
-                let tr = Point3::from_homogeneous(points.column(1).into()).unwrap();
-                let bl = Point3::from_homogeneous(points.column(2).into()).unwrap();
-                let br = Point3::from_homogeneous(points.column(3).into()).unwrap();
-
-                let normal = transform
-                    .rotation
-                    .transform_vector(&Vector3::new(0., 0., 1.))
-                    .into();
-
-                let range = {
-                    let v = update.vertex_mut();
-                    v.clear();
-                    v.push(Vertex {
-                        position: tl.into(),
-                        uv: [min_u, min_v],
-                        normal,
-                    });
-                    v.push(Vertex {
-                        position: tr.into(),
-                        uv: [max_u, min_v],
-                        normal,
-                    });
-                    v.push(Vertex {
-                        position: bl.into(),
-                        uv: [min_u, max_v],
-                        normal,
-                    });
-                    v.push(Vertex {
-                        position: br.into(),
-                        uv: [max_u, max_v],
-                        normal,
-                    });
-                    0..v.len()
-                };
-                update.set_vertex_update(range);
-
-                let range = {
-                    let i = update.index_mut();
-                    i.clear();
-                    i.extend_from_slice(&[0, 3, 1, 0, 2, 3]);
-                    0..i.len()
-                };
-                update.set_index_update(range.clone());
-                update.set_render_range(range.start as u32..range.end as u32);
-            }
-
-            let bind_group = scene
-                .textures
-                .get_bind_group(self.texture)
-                .context("texture not found for index")
-                .unwrap_or_log();
-            rp.set_bind_group(sprite::GROUP_TEXTURE, bind_group, &[]);
-            rp.set_index_buffer(buffer.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            rp.set_vertex_buffer(0, buffer.vertex_buffer.slice(..));
-            rp.draw_indexed(buffer.index_buffer_range.clone(), 0, 0..1);
-        } else {
-            tracing::warn!("buffer is not initialized");
+    pub const fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn instances(&self) -> &[SpriteInstance] {
+        &self.instances
+    }
+
+    /// インスタンスを書き換える。呼ぶたびに GPU に送信し直す。
+    pub fn instances_mut(&mut self) -> &mut Vec<SpriteInstance> {
+        self.revision = next_revision();
+        &mut self.instances
+    }
+
+    /// インスタンスを置き換える
+    pub fn set_instances(&mut self, instances: Vec<SpriteInstance>) {
+        *self.instances_mut() = instances;
+    }
+
+    /// インスタンスの位置の平均 (ワールド座標系)。インスタンスがない場合は `None` を返す。
+    ///
+    /// 半透明のセットを他のものと奥から手前の順に並べるときに使う。
+    pub fn center(&self) -> Option<Point3<f32>> {
+        if self.instances.is_empty() {
+            return None;
         }
+        let sum = self
+            .instances
+            .iter()
+            .fold(Vector3::zeros(), |sum, instance| {
+                let [x, y, z, _] = instance.model[3];
+                sum + Vector3::new(x, y, z + instance.layer)
+            });
+        Some(Point3::from(sum / self.instances.len() as f32))
+    }
+
+    /// インスタンスを書き換えるたびに変わる番号
+    ///
+    /// 同じゲームオブジェクトに作り直したコンポーネントを入れても前と重ならないように、すべてのコンポーネントで通し番号にする。
+    pub const fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+fn next_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::*;
+
+    #[test]
+    fn sprite_options_change_vertices() {
+        let mut textures = TextureRegistry::default();
+        let texture = textures.new_texture(RgbaImage::new(32, 16), None);
+        let sprite = SpriteComponent::new(texture.into())
+            .with_pixels_per_unit(16.0)
+            .with_pivot(Vector2::new(0.0, 0.0))
+            .with_source_rect(SourceRect::new(8, 0, 16, 16))
+            .with_flip(true, false)
+            .with_opacity(0.5);
+        let [tl, tr, bl, br] = sprite
+            .vertices(
+                &textures,
+                &Matrix4::new_translation(&Vector3::new(1.0, 0.0, 0.0)),
+            )
+            .unwrap();
+
+        // 16 ピクセルが 1.0 になり、左下がゲームオブジェクトの位置になる
+        assert_eq!(bl.position, [1.0, 0.0, 0.0]);
+        assert_eq!(tr.position, [2.0, 1.0, 0.0]);
+        // 左右が反転した、テクスチャの中央の半分
+        assert_eq!(tl.uv, [0.75, 0.0]);
+        assert_eq!(br.uv, [0.25, 1.0]);
+        assert_eq!(tl.color, [1.0, 1.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn instance_revision_changes_when_mutated() {
+        let mut textures = TextureRegistry::default();
+        let texture = textures.new_texture(RgbaImage::new(16, 16), None);
+        let mut set = SpriteInstances::new(texture);
+        let revision = set.revision();
+        assert!(set.instances().is_empty());
+
+        set.instances_mut()
+            .push(SpriteInstance::new(&Matrix4::identity()));
+        assert_ne!(set.revision(), revision);
+        let revision = set.revision();
+        set.set_instances(Vec::new());
+        assert_ne!(set.revision(), revision);
+        // 作り直したコンポーネントとも重ならない
+        assert_ne!(SpriteInstances::new(texture).revision(), set.revision());
     }
 }
@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 use nalgebra::{Affine3, Isometry3, Matrix4, Scale3, Translation3, UnitQuaternion};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 /// エンティティの位置、回転、拡大縮小を表すコンポーネント
 pub struct TransformComponent {
     pub translation: Translation3<f32>,
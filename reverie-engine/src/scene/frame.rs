@@ -1,17 +1,138 @@
-use std::time::{Duration, Instant};
+use std::{
+    cell::Cell,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
 
 use winit::{
     dpi::PhysicalPosition,
     event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase},
+    keyboard::{Key, KeyCode, KeyLocation, PhysicalKey, SmolStr},
 };
 
+use crate::{camera::Viewport, stats::FrameStats};
+
 #[derive(Debug)]
 /// フレームごとに更新される情報
 pub struct Frame<'a> {
     pub now: Instant,
     pub delta_time: Duration,
-    pub key_events: &'a [KeyEvent],
+    pub key_events: &'a [KeyInput],
     pub mouse_clicks: &'a [(ElementState, MouseButton, PhysicalPosition<f64>)],
     pub mouse_wheels: &'a [(MouseScrollDelta, TouchPhase, PhysicalPosition<f64>)],
     pub mouse_position: PhysicalPosition<f64>,
+    /// ウィンドウの描画領域の大きさ
+    pub viewport: Viewport,
+    /// 前のフレームまでの計測値
+    pub stats: &'a FrameStats,
+    redraw_requested: &'a Cell<bool>,
+}
+
+impl Frame<'_> {
+    /// 次のフレームの描画を要求する
+    ///
+    /// [`UpdateMode::Reactive`](crate::pacing::UpdateMode::Reactive) のとき、
+    /// 入力がなくてもアニメーションを続けたい場合に呼ぶ。
+    pub fn request_redraw(&self) {
+        self.redraw_requested.set(true);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// キーボード入力
+///
+/// winit の [`KeyEvent`] はプラットフォーム固有のフィールドを持つため外部から構築できない。
+/// テストなどで合成できるように、必要なフィールドだけを写した構造体を使う。
+pub struct KeyInput {
+    pub physical_key: PhysicalKey,
+    pub logical_key: Key,
+    pub text: Option<SmolStr>,
+    pub location: KeyLocation,
+    pub state: ElementState,
+    pub repeat: bool,
+}
+
+impl KeyInput {
+    /// 物理キーだけを指定してキー入力を作る
+    pub const fn from_key_code(key_code: KeyCode, state: ElementState) -> Self {
+        Self {
+            physical_key: PhysicalKey::Code(key_code),
+            logical_key: Key::Unidentified(winit::keyboard::NativeKey::Unidentified),
+            text: None,
+            location: KeyLocation::Standard,
+            state,
+            repeat: false,
+        }
+    }
+}
+
+impl From<KeyEvent> for KeyInput {
+    fn from(event: KeyEvent) -> Self {
+        Self {
+            physical_key: event.physical_key,
+            logical_key: event.logical_key,
+            text: event.text,
+            location: event.location,
+            state: event.state,
+            repeat: event.repeat,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// 次のフレームまでに溜まった入力を保持するバッファ
+pub(crate) struct InputBuffer {
+    pub key_events: Vec<KeyInput>,
+    pub mouse_clicks: Vec<(ElementState, MouseButton, PhysicalPosition<f64>)>,
+    pub mouse_wheels: Vec<(MouseScrollDelta, TouchPhase, PhysicalPosition<f64>)>,
+    pub mouse_position: PhysicalPosition<f64>,
+    pub viewport: Viewport,
+    redraw_requested: Cell<bool>,
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self {
+            key_events: Vec::new(),
+            mouse_clicks: Vec::new(),
+            mouse_wheels: Vec::new(),
+            mouse_position: PhysicalPosition::new(0.0, 0.0),
+            viewport: Viewport::new(NonZeroU32::MIN, NonZeroU32::MIN),
+            redraw_requested: Cell::new(false),
+        }
+    }
+}
+
+impl InputBuffer {
+    /// 溜まった入力から [`Frame`] を作る
+    pub const fn frame<'a>(
+        &'a self,
+        now: Instant,
+        delta_time: Duration,
+        stats: &'a FrameStats,
+    ) -> Frame<'a> {
+        Frame {
+            now,
+            delta_time,
+            key_events: self.key_events.as_slice(),
+            mouse_clicks: self.mouse_clicks.as_slice(),
+            mouse_wheels: self.mouse_wheels.as_slice(),
+            mouse_position: self.mouse_position,
+            viewport: self.viewport,
+            stats,
+            redraw_requested: &self.redraw_requested,
+        }
+    }
+
+    /// フレーム中に [`Frame::request_redraw`] が呼ばれたかどうかを返し、リセットする
+    pub fn take_redraw_request(&self) -> bool {
+        self.redraw_requested.take()
+    }
+
+    /// フレームの終わりに入力を破棄する。マウスの位置は保持する。
+    pub fn clear(&mut self) {
+        self.key_events.clear();
+        self.mouse_clicks.clear();
+        self.mouse_wheels.clear();
+    }
 }
@@ -0,0 +1,584 @@
+//! シーンの時計に従ってコールバックやコルーチンを実行するモジュール
+//!
+//! 時間は [`Time::game`](crate::time::Time::game) に従うため、ゲームを一時停止するとタイマーとコルーチンも止まる。
+use std::{
+    any::Any,
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use crate::scene::{Scene, frame::Frame};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// [`Scheduler`] に登録したタイマーやコルーチンを指すハンドル
+pub struct TaskHandle(u64);
+
+type TimerCallback = Box<dyn FnMut(&mut Scene)>;
+type WaitCondition = Box<dyn for<'a> FnMut(&Scene, &Frame<'a>) -> bool>;
+type AccessRequest = Box<dyn FnOnce(&mut Scene) -> Box<dyn Any>>;
+
+struct Timer {
+    handle: TaskHandle,
+    due: Duration,
+    interval: Option<Duration>,
+    callback: TimerCallback,
+}
+
+struct Coroutine {
+    handle: TaskHandle,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    state: Rc<RefCell<CoroutineState>>,
+}
+
+#[derive(Default)]
+/// コルーチンとスケジューラの間で共有する状態
+struct CoroutineState {
+    /// スケジューラの現在時刻
+    now: Duration,
+    /// コルーチンを再開する条件
+    wait: Wait,
+    /// コルーチンからのシーンへのアクセス要求
+    request: Option<AccessRequest>,
+    /// アクセス要求の結果
+    result: Option<Box<dyn Any>>,
+}
+
+#[derive(Default)]
+enum Wait {
+    /// 毎フレーム再開する
+    #[default]
+    None,
+    /// 指定した時刻になったら再開する
+    Until(Duration),
+    /// 条件を満たしたら再開する
+    Condition(WaitCondition),
+}
+
+/// シーンの時計に従ってタイマーとコルーチンを実行するスケジューラ
+///
+/// [`Scene::update`] の中で 1 フレームに 1 回実行される。
+#[derive(Default)]
+pub struct Scheduler {
+    now: Duration,
+    next_id: u64,
+    timers: Vec<Timer>,
+    coroutines: Vec<Coroutine>,
+    cancelled: Vec<TaskHandle>,
+}
+
+impl Scheduler {
+    const fn new_handle(&mut self) -> TaskHandle {
+        self.next_id += 1;
+        TaskHandle(self.next_id)
+    }
+
+    /// スケジューラの現在時刻。ゲームの時間の経過時間と同じ。
+    pub const fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// `delay` 後に 1 回だけ `callback` を呼ぶ
+    pub fn after(
+        &mut self,
+        delay: Duration,
+        callback: impl FnOnce(&mut Scene) + 'static,
+    ) -> TaskHandle {
+        let mut callback = Some(callback);
+        self.add_timer(delay, None, move |scene| {
+            if let Some(callback) = callback.take() {
+                callback(scene);
+            }
+        })
+    }
+
+    /// `interval` ごとに `callback` を呼ぶ。`interval` が 0 の場合は毎フレーム呼ぶ。
+    pub fn every(
+        &mut self,
+        interval: Duration,
+        callback: impl FnMut(&mut Scene) + 'static,
+    ) -> TaskHandle {
+        self.add_timer(interval, Some(interval), callback)
+    }
+
+    fn add_timer(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: impl FnMut(&mut Scene) + 'static,
+    ) -> TaskHandle {
+        let handle = self.new_handle();
+        self.timers.push(Timer {
+            handle,
+            due: self.now + delay,
+            interval,
+            callback: Box::new(callback),
+        });
+        handle
+    }
+
+    /// コルーチンを開始する。コルーチンは次の [`Scene::update`] から 1 フレームに 1 回ポーリングされる。
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use reverie_engine::scene::Scene;
+    /// # let mut scene = Scene::default();
+    /// scene.scheduler.spawn(|co| async move {
+    ///     co.wait(Duration::from_secs_f32(1.5)).await;
+    ///     co.access(|scene| scene.show_performance_overlay = true).await;
+    ///     co.wait_until(|_, frame| !frame.key_events.is_empty()).await;
+    /// });
+    /// ```
+    pub fn spawn<F, Fut>(&mut self, f: F) -> TaskHandle
+    where
+        F: FnOnce(CoroutineContext) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let handle = self.new_handle();
+        let state = Rc::new(RefCell::new(CoroutineState {
+            now: self.now,
+            ..Default::default()
+        }));
+        let future = f(CoroutineContext {
+            state: state.clone(),
+        });
+        self.coroutines.push(Coroutine {
+            handle,
+            future: Box::pin(future),
+            state,
+        });
+        handle
+    }
+
+    /// タイマーまたはコルーチンを止める。すでに終わっている場合は何もしない。
+    pub fn cancel(&mut self, handle: TaskHandle) {
+        self.timers.retain(|t| t.handle != handle);
+        self.coroutines.retain(|c| c.handle != handle);
+        self.cancelled.push(handle);
+    }
+
+    /// タイマーまたはコルーチンがまだ有効かどうか
+    pub fn is_active(&self, handle: TaskHandle) -> bool {
+        self.timers.iter().any(|t| t.handle == handle)
+            || self.coroutines.iter().any(|c| c.handle == handle)
+    }
+
+    /// 有効なタイマーとコルーチンの数
+    pub const fn len(&self) -> usize {
+        self.timers.len() + self.coroutines.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// スケジューラの時刻を合わせる
+    pub(crate) const fn set_now(&mut self, now: Duration) {
+        self.now = now;
+    }
+
+    fn is_cancelled(&self, handle: TaskHandle) -> bool {
+        self.cancelled.contains(&handle)
+    }
+}
+
+/// 時刻が来たタイマーを実行し、コルーチンを再開する
+///
+/// コールバックやコルーチンの中から `scene.scheduler` に新しいタスクを追加したり、止めたりできる。
+pub(crate) fn run(scene: &mut Scene, frame: &Frame<'_>) {
+    let now = scene.scheduler.now;
+
+    let mut timers = std::mem::take(&mut scene.scheduler.timers);
+    timers.retain_mut(|timer| {
+        while timer.due <= now {
+            if scene.scheduler.is_cancelled(timer.handle) {
+                return false;
+            }
+            (timer.callback)(scene);
+            match timer.interval {
+                Some(interval) if interval.is_zero() => {
+                    timer.due = now + Duration::from_nanos(1);
+                }
+                Some(interval) => timer.due += interval,
+                None => return false,
+            }
+        }
+        true
+    });
+
+    let mut coroutines = std::mem::take(&mut scene.scheduler.coroutines);
+    coroutines.retain_mut(|coroutine| {
+        if scene.scheduler.is_cancelled(coroutine.handle) {
+            return false;
+        }
+        let ready = {
+            let mut state = coroutine.state.borrow_mut();
+            state.now = now;
+            match &mut state.wait {
+                Wait::None => true,
+                Wait::Until(t) => *t <= now,
+                Wait::Condition(condition) => condition(scene, frame),
+            }
+        };
+        if !ready {
+            return true;
+        }
+        coroutine.state.borrow_mut().wait = Wait::None;
+        resume(coroutine, scene)
+    });
+
+    // 実行中に止められたものを取り除き、実行中に追加されたものと合わせる
+    let scheduler = &mut scene.scheduler;
+    let cancelled = std::mem::take(&mut scheduler.cancelled);
+    timers.retain(|t| !cancelled.contains(&t.handle));
+    coroutines.retain(|c| !cancelled.contains(&c.handle));
+    timers.append(&mut scheduler.timers);
+    coroutines.append(&mut scheduler.coroutines);
+    scheduler.timers = timers;
+    scheduler.coroutines = coroutines;
+}
+
+/// コルーチンを次の待機まで進める。終わった場合は `false` を返す。
+fn resume(coroutine: &mut Coroutine, scene: &mut Scene) -> bool {
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if coroutine.future.as_mut().poll(&mut cx).is_ready() {
+            return false;
+        }
+        // シーンへのアクセス要求があれば実行し、同じフレームのうちに結果を返して再開する
+        let request = coroutine.state.borrow_mut().request.take();
+        match request {
+            Some(request) => {
+                let result = request(scene);
+                coroutine.state.borrow_mut().result = Some(result);
+            }
+            None => return true,
+        }
+    }
+}
+
+#[derive(Clone)]
+/// コルーチンの中でスケジューラと通信するためのコンテキスト
+pub struct CoroutineContext {
+    state: Rc<RefCell<CoroutineState>>,
+}
+
+impl CoroutineContext {
+    /// ゲームの時間で `duration` 待つ
+    pub fn wait(&self, duration: Duration) -> WaitFor {
+        let deadline = self.state.borrow().now + duration;
+        WaitFor {
+            state: self.state.clone(),
+            deadline,
+        }
+    }
+
+    /// 次のフレームまで待つ
+    pub fn next_frame(&self) -> WaitUntil {
+        self.wait_until(|_, _| true)
+    }
+
+    /// `condition` が `true` を返すフレームまで待つ。条件は次のフレームから毎フレーム評価される。
+    pub fn wait_until(
+        &self,
+        condition: impl for<'a> FnMut(&Scene, &Frame<'a>) -> bool + 'static,
+    ) -> WaitUntil {
+        WaitUntil {
+            state: self.state.clone(),
+            condition: Some(Box::new(condition)),
+        }
+    }
+
+    /// シーンにアクセスし、`f` の戻り値を返す。`f` は同じフレームのうちに実行される。
+    pub fn access<F, R>(&self, f: F) -> Access<F>
+    where
+        F: FnOnce(&mut Scene) -> R + 'static,
+        R: 'static,
+    {
+        Access {
+            state: self.state.clone(),
+            f: Some(f),
+        }
+    }
+
+    /// ゲームの時間の現在時刻
+    pub fn now(&self) -> Duration {
+        self.state.borrow().now
+    }
+}
+
+impl std::fmt::Debug for CoroutineContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CoroutineContext")
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+/// [`CoroutineContext::wait`] が返す Future
+pub struct WaitFor {
+    state: Rc<RefCell<CoroutineState>>,
+    deadline: Duration,
+}
+
+impl std::fmt::Debug for WaitFor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitFor")
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+impl Future for WaitFor {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.borrow_mut();
+        if state.now >= self.deadline {
+            Poll::Ready(())
+        } else {
+            state.wait = Wait::Until(self.deadline);
+            Poll::Pending
+        }
+    }
+}
+
+/// [`CoroutineContext::wait_until`] が返す Future
+pub struct WaitUntil {
+    state: Rc<RefCell<CoroutineState>>,
+    condition: Option<WaitCondition>,
+}
+
+impl Future for WaitUntil {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        match self.condition.take() {
+            // スケジューラは条件を満たしたときだけ再びポーリングする
+            None => Poll::Ready(()),
+            Some(condition) => {
+                self.state.borrow_mut().wait = Wait::Condition(condition);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for WaitUntil {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitUntil")
+            .field("registered", &self.condition.is_none())
+            .finish()
+    }
+}
+
+/// [`CoroutineContext::access`] が返す Future
+pub struct Access<F> {
+    state: Rc<RefCell<CoroutineState>>,
+    f: Option<F>,
+}
+
+// `f` をピン留めされた参照として扱うことはないので、`F` によらず `Unpin` にできる
+impl<F> Unpin for Access<F> {}
+
+impl<F, R> Future for Access<F>
+where
+    F: FnOnce(&mut Scene) -> R + 'static,
+    R: 'static,
+{
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<R> {
+        if let Some(f) = self.f.take() {
+            self.state.borrow_mut().request =
+                Some(Box::new(move |scene| Box::new(f(scene)) as Box<dyn Any>));
+            return Poll::Pending;
+        }
+        let result = self
+            .state
+            .borrow_mut()
+            .result
+            .take()
+            .expect("access result must be set by the scheduler");
+        Poll::Ready(*result.downcast::<R>().expect("access result type mismatch"))
+    }
+}
+
+impl<F> std::fmt::Debug for Access<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Access")
+            .field("requested", &self.f.is_none())
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("now", &self.now)
+            .field("timers", &self.timers.len())
+            .field("coroutines", &self.coroutines.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Instant};
+
+    use winit::{event::ElementState, keyboard::KeyCode};
+
+    use super::*;
+    use crate::{
+        scene::frame::{InputBuffer, KeyInput},
+        stats::FrameStats,
+    };
+
+    /// `n` フレーム分シーンを進める
+    fn step(scene: &mut Scene, input: &mut InputBuffer, delta: Duration, n: usize) {
+        let stats = FrameStats::default();
+        for _ in 0..n {
+            let frame = input.frame(Instant::now(), delta, &stats);
+            scene.begin_frame(&frame);
+            scene.update(&frame);
+            input.clear();
+        }
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn after_fires_once() {
+        let mut scene = Scene::default();
+        let mut input = InputBuffer::default();
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        let handle = scene.scheduler.after(ms(25), move |_| c.set(c.get() + 1));
+
+        step(&mut scene, &mut input, ms(10), 2);
+        assert_eq!(count.get(), 0);
+        step(&mut scene, &mut input, ms(10), 1);
+        assert_eq!(count.get(), 1);
+        assert!(!scene.scheduler.is_active(handle));
+        step(&mut scene, &mut input, ms(10), 5);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn every_repeats_until_cancelled() {
+        let mut scene = Scene::default();
+        let mut input = InputBuffer::default();
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        let handle = scene.scheduler.every(ms(10), move |_| c.set(c.get() + 1));
+
+        step(&mut scene, &mut input, ms(10), 3);
+        assert_eq!(count.get(), 3);
+        // 1 フレームで複数回分の時間が経過した場合はその回数だけ呼ぶ
+        step(&mut scene, &mut input, ms(30), 1);
+        assert_eq!(count.get(), 6);
+
+        scene.scheduler.cancel(handle);
+        step(&mut scene, &mut input, ms(10), 3);
+        assert_eq!(count.get(), 6);
+    }
+
+    #[test]
+    fn callbacks_can_schedule_and_cancel() {
+        let mut scene = Scene::default();
+        let mut input = InputBuffer::default();
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        let repeating = scene.scheduler.every(ms(10), move |_| c.set(c.get() + 1));
+        scene.scheduler.after(ms(20), move |scene| {
+            scene.scheduler.cancel(repeating);
+            scene.show_performance_overlay = true;
+        });
+
+        step(&mut scene, &mut input, ms(10), 5);
+        assert!(scene.show_performance_overlay);
+        assert!(!scene.scheduler.is_active(repeating));
+        // 同じフレームでは登録した順に呼ぶので、20ms の回は止められる前に呼ばれる
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn pause_freezes_timers() {
+        let mut scene = Scene::default();
+        let mut input = InputBuffer::default();
+        let fired = Rc::new(Cell::new(false));
+        let f = fired.clone();
+        scene.scheduler.after(ms(20), move |_| f.set(true));
+
+        scene.time.pause();
+        step(&mut scene, &mut input, ms(10), 10);
+        assert!(!fired.get());
+
+        scene.time.resume();
+        step(&mut scene, &mut input, ms(10), 2);
+        assert!(fired.get());
+    }
+
+    #[test]
+    fn coroutine_cutscene() {
+        let mut scene = Scene::default();
+        let mut input = InputBuffer::default();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let l = log.clone();
+        let handle = scene.scheduler.spawn(move |co| async move {
+            l.borrow_mut().push("start");
+            co.wait(Duration::from_secs_f32(1.5)).await;
+            let was_shown = co
+                .access(|scene| std::mem::replace(&mut scene.show_performance_overlay, true))
+                .await;
+            assert!(!was_shown);
+            l.borrow_mut().push("moved");
+            co.wait_until(|_, frame| !frame.key_events.is_empty()).await;
+            l.borrow_mut().push("input");
+        });
+
+        step(&mut scene, &mut input, ms(250), 1);
+        assert_eq!(*log.borrow(), vec!["start"]);
+        step(&mut scene, &mut input, ms(250), 5);
+        assert_eq!(*log.borrow(), vec!["start"]);
+        step(&mut scene, &mut input, ms(250), 1);
+        assert_eq!(*log.borrow(), vec!["start", "moved"]);
+        assert!(scene.show_performance_overlay);
+
+        step(&mut scene, &mut input, ms(250), 3);
+        assert_eq!(log.borrow().len(), 2);
+        input.key_events.push(KeyInput::from_key_code(
+            KeyCode::Enter,
+            ElementState::Pressed,
+        ));
+        step(&mut scene, &mut input, ms(10), 1);
+        assert_eq!(*log.borrow(), vec!["start", "moved", "input"]);
+        assert!(!scene.scheduler.is_active(handle));
+    }
+
+    #[test]
+    fn cancel_coroutine() {
+        let mut scene = Scene::default();
+        let mut input = InputBuffer::default();
+        let frames = Rc::new(Cell::new(0));
+        let f = frames.clone();
+        let handle = scene.scheduler.spawn(move |co| async move {
+            loop {
+                f.set(f.get() + 1);
+                co.next_frame().await;
+            }
+        });
+
+        step(&mut scene, &mut input, ms(10), 3);
+        assert_eq!(frames.get(), 3);
+        scene.scheduler.cancel(handle);
+        step(&mut scene, &mut input, ms(10), 3);
+        assert_eq!(frames.get(), 3);
+        assert!(scene.scheduler.is_empty());
+    }
+}
@@ -0,0 +1,465 @@
+//! マテリアルで使うシェーダーに関するモジュール
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::{
+    model::{Material, MaterialParam},
+    render::{
+        mesh::{BINDING_MATERIAL_PARAMS, BINDING_MATERIAL_SAMPLER, GROUP_MATERIAL},
+        reflect, wgsl,
+    },
+    scene::Registry,
+};
+
+mod preprocess;
+
+pub use preprocess::ShaderPreprocessor;
+
+slotmap::new_key_type! { pub struct ShaderKey; }
+
+#[derive(Debug)]
+/// [`Material`] で使う WGSL のシェーダー
+///
+/// 組み込みのメッシュのシェーダーと同じく、次のバインドグループと頂点属性を使える。
+///
+/// * `@group(0)`: `@binding(0)` に [`Material::texture`]、`@binding(1)` にサンプラー
+/// * `@group(1) @binding(0)`: カメラのユニフォーム
+/// * `@group(2) @binding(0)`: オブジェクトの変換行列 `model` と UV の範囲 `uv_rect`
+/// * `@group(3)`: マテリアルのパラメータ。`@binding(0)` にテクスチャ以外の [`Material::params`] を
+///   並べた構造体、`@binding(1)` にサンプラー、`@binding(2)` 以降にテクスチャのパラメータを順に置く。
+/// * `@location(0..=3)`: 頂点の位置、UV、法線、色
+///
+/// ソースは [`ShaderPreprocessor`] で前処理され、エンジンのシェーダーと共通の次のスニペットを `#include` できる。
+///
+/// * `"bindings.wgsl"`: `LOC_VERTEX` や `GROUP_CAMERA`、`BINDING_CAMERA` などの定数
+/// * `"vertex.wgsl"`: 頂点属性の構造体 `VertexInput`
+/// * `"camera.wgsl"`: カメラのユニフォーム `camera` と `snap_to_pixel`
+/// * `"object.wgsl"`: オブジェクトのユニフォーム `object`
+/// * `"textured.wgsl"`: テクスチャ `tex` とサンプラー `samp`、それを使う `fs_main` と `fs_opaque`
+///
+/// エントリポイントは `vs_main` と `fs_main` が必要。`fs_opaque` がある場合は
+/// [`BlendMode::Opaque`](crate::model::BlendMode::Opaque) で描画するときに使う。
+///
+/// [`Shader::load`] でファイルから読み込んだシェーダーは、ファイルが書き換えられると
+/// [`Shader::reload_if_changed`] で読み込み直せる。
+pub struct Shader {
+    name: String,
+    source: String,
+    /// 前処理した WGSL
+    wgsl: String,
+    module: naga::Module,
+    /// 読み込んだファイル
+    path: Option<PathBuf>,
+    /// 最後に読み込んだときのファイルの更新日時
+    modified: Option<SystemTime>,
+    /// 読み込み直した回数。パイプラインを作り直すかどうかの判定に使う。
+    generation: u64,
+}
+
+impl Shader {
+    /// WGSL のソースを前処理して読み込み、naga で検証する
+    pub fn from_wgsl(name: impl Into<String>, source: impl Into<String>) -> anyhow::Result<Self> {
+        let name = name.into();
+        let source = source.into();
+        let wgsl = wgsl::preprocessor().process(&name, &source)?;
+        let module = reflect::parse(&name, &wgsl)?;
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::default(),
+        )
+        .validate(&module)
+        .map_err(|err| anyhow::anyhow!("{}", err.emit_to_string_with_path(&wgsl, &name)))?;
+        let shader = Self {
+            name,
+            source,
+            wgsl,
+            module,
+            path: None,
+            modified: None,
+            generation: 0,
+        };
+        for (entry_point, stage) in [
+            ("vs_main", naga::ShaderStage::Vertex),
+            ("fs_main", naga::ShaderStage::Fragment),
+        ] {
+            anyhow::ensure!(
+                shader.entry_point(entry_point) == Some(stage),
+                "{}: no {stage:?} entry point `{entry_point}`",
+                shader.name
+            );
+        }
+        Ok(shader)
+    }
+
+    /// WGSL のファイルを読み込み、naga で検証する
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let modified = modified_time(path)?;
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed: read {}", path.display()))?;
+        let mut shader = Self::from_wgsl(path.display().to_string(), source)?;
+        shader.path = Some(path.to_path_buf());
+        shader.modified = Some(modified);
+        Ok(shader)
+    }
+
+    /// ファイルが書き換えられていれば読み込み直し、読み込み直したかどうかを返す
+    ///
+    /// 新しいソースが検証に失敗した場合はエラーを返し、最後に読み込めたソースを使い続ける。
+    /// 同じ更新日時のファイルは再び読み込まない。
+    pub fn reload_if_changed(&mut self) -> anyhow::Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = modified_time(path)?;
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        self.modified = Some(modified);
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed: read {}", path.display()))?;
+        let reloaded = Self::from_wgsl(self.name.clone(), source)?;
+        self.source = reloaded.source;
+        self.wgsl = reloaded.wgsl;
+        self.module = reloaded.module;
+        self.generation += 1;
+        Ok(true)
+    }
+
+    /// 前処理した WGSL
+    pub fn wgsl(&self) -> &str {
+        &self.wgsl
+    }
+
+    /// naga で解析したモジュール
+    pub(crate) const fn module(&self) -> &naga::Module {
+        &self.module
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// `name` という名前のエントリポイントのステージ
+    pub fn entry_point(&self, name: &str) -> Option<naga::ShaderStage> {
+        self.module
+            .entry_points
+            .iter()
+            .find(|entry_point| entry_point.name == name)
+            .map(|entry_point| entry_point.stage)
+    }
+
+    /// シェーダーのマテリアルのバインドグループが `material` のパラメータと合うかどうかを調べる
+    pub fn check_material(&self, material: &Material) -> anyhow::Result<()> {
+        for (_, var) in self.module.global_variables.iter() {
+            let Some(binding) = &var.binding else {
+                continue;
+            };
+            if binding.group != GROUP_MATERIAL {
+                continue;
+            }
+            let name = var.name.as_deref().unwrap_or("_");
+            let inner = &self.module.types[var.ty].inner;
+            let result = match binding.binding {
+                b if b == BINDING_MATERIAL_PARAMS.binding => {
+                    self.check_params(var.space, inner, material)
+                }
+                b if b == BINDING_MATERIAL_SAMPLER.binding => check_sampler(inner, material),
+                b => check_texture(b, inner, material),
+            };
+            result.with_context(|| {
+                format!(
+                    "{}: @group({}) @binding({}) var {name}",
+                    self.name, binding.group, binding.binding
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// パラメータの構造体のメンバーが、同じ名前のパラメータと同じ型とオフセットを持つかどうかを調べる
+    fn check_params(
+        &self,
+        space: naga::AddressSpace,
+        inner: &naga::TypeInner,
+        material: &Material,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            space == naga::AddressSpace::Uniform,
+            "expected var<uniform>"
+        );
+        let naga::TypeInner::Struct { members, span } = inner else {
+            anyhow::bail!("expected struct");
+        };
+        for member in members {
+            let name = member.name.as_deref().unwrap_or("_");
+            let (_, param, offset) = material
+                .uniform_members()
+                .find(|(param, _, _)| *param == name)
+                .with_context(|| format!("material has no parameter `{name}`"))?;
+            anyhow::ensure!(
+                param_matches(param, &self.module.types[member.ty].inner),
+                "type of `{name}` does not match {param:?}"
+            );
+            anyhow::ensure!(
+                member.offset == offset,
+                "offset of `{name}` is {} but parameter is at {offset}",
+                member.offset
+            );
+        }
+        anyhow::ensure!(
+            *span <= material.uniform_size(),
+            "struct is larger than parameters"
+        );
+        Ok(())
+    }
+}
+
+fn modified_time(path: &Path) -> anyhow::Result<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("failed: stat {}", path.display()))
+}
+
+#[derive(Debug)]
+/// ファイルから読み込んだシェーダーの変更を一定の間隔で調べる
+pub(crate) struct ShaderWatcher {
+    interval: Duration,
+    next_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub const fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            next_poll: now,
+        }
+    }
+
+    /// 次に [`ShaderWatcher::poll`] で調べる時刻
+    pub const fn next_poll(&self) -> Instant {
+        self.next_poll
+    }
+
+    /// 前回から `interval` 経っていれば、書き換えられたシェーダーを読み込み直す。読み込み直した場合は `true` を返す。
+    ///
+    /// 読み込みに失敗したシェーダーはエラーをログに出し、前のソースのまま使い続ける。
+    pub fn poll(&mut self, now: Instant, shaders: &mut Registry<ShaderKey, Shader>) -> bool {
+        if now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + self.interval;
+        let mut reloaded = false;
+        for (key, shader) in &mut shaders.map {
+            match shader.reload_if_changed() {
+                Ok(true) => {
+                    tracing::info!(?key, name = shader.name(), "reloaded shader");
+                    reloaded = true;
+                }
+                Ok(false) => {}
+                Err(err) => tracing::error!(?key, "failed: reload shader: {err:#}"),
+            }
+        }
+        reloaded
+    }
+}
+
+fn check_sampler(inner: &naga::TypeInner, material: &Material) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        material.textures().next().is_some(),
+        "material has no texture parameters"
+    );
+    anyhow::ensure!(
+        matches!(inner, naga::TypeInner::Sampler { comparison: false }),
+        "expected sampler"
+    );
+    Ok(())
+}
+
+/// `binding` 番のテクスチャのパラメータが 2D のテクスチャかどうかを調べる
+fn check_texture(binding: u32, inner: &naga::TypeInner, material: &Material) -> anyhow::Result<()> {
+    let index = binding - BINDING_MATERIAL_SAMPLER.binding - 1;
+    let (name, _) = material
+        .textures()
+        .nth(index as usize)
+        .with_context(|| format!("material has no texture parameter #{index}"))?;
+    anyhow::ensure!(
+        matches!(
+            inner,
+            naga::TypeInner::Image {
+                dim: naga::ImageDimension::D2,
+                arrayed: false,
+                class: naga::ImageClass::Sampled {
+                    kind: naga::ScalarKind::Float,
+                    multi: false,
+                },
+            }
+        ),
+        "expected texture_2d<f32> for `{name}`"
+    );
+    Ok(())
+}
+
+/// `param` を WGSL の型 `inner` として渡せるかどうか
+fn param_matches(param: &MaterialParam, inner: &naga::TypeInner) -> bool {
+    let size = match param {
+        MaterialParam::Float(_) => {
+            return matches!(inner, naga::TypeInner::Scalar(naga::Scalar::F32));
+        }
+        MaterialParam::Vec2(_) => naga::VectorSize::Bi,
+        MaterialParam::Vec3(_) => naga::VectorSize::Tri,
+        MaterialParam::Vec4(_) | MaterialParam::Color(_) => naga::VectorSize::Quad,
+        MaterialParam::Texture(_) => return false,
+    };
+    *inner
+        == naga::TypeInner::Vector {
+            size,
+            scalar: naga::Scalar::F32,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::TextureIndex;
+
+    const SOURCE: &str = r"
+struct Params {
+  strength: f32,
+  tint: vec4<f32>
+}
+
+@group(3) @binding(0) var<uniform> params: Params;
+@group(3) @binding(1) var samp: sampler;
+@group(3) @binding(2) var mask: texture_2d<f32>;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+  return vec4<f32>(position * params.strength, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+  return textureSample(mask, samp, vec2<f32>(0.0)) * params.tint;
+}
+";
+
+    #[test]
+    fn reload_keeps_last_good_source() {
+        let path = std::env::temp_dir().join(format!("reverie-reload-{}.wgsl", std::process::id()));
+        let write = |source: &str, secs: u64| {
+            std::fs::write(&path, source).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+
+        write(SOURCE, 1);
+        let mut shader = Shader::load(&path).unwrap();
+        assert!(!shader.reload_if_changed().unwrap());
+
+        write("fn vs_main( {", 2);
+        assert!(shader.reload_if_changed().is_err());
+        assert_eq!(shader.source(), SOURCE);
+        assert_eq!(shader.generation(), 0);
+        // 壊れたファイルは書き換えられるまで読み込み直さない
+        assert!(!shader.reload_if_changed().unwrap());
+
+        let fixed = SOURCE.replace("params.tint", "params.tint * 0.5");
+        write(&fixed, 3);
+        assert!(shader.reload_if_changed().unwrap());
+        assert_eq!(shader.source(), fixed);
+        assert_eq!(shader.generation(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn watcher_reports_reloads_after_interval() {
+        let path = std::env::temp_dir().join(format!("reverie-watch-{}.wgsl", std::process::id()));
+        let write = |source: &str, secs: u64| {
+            std::fs::write(&path, source).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        write(SOURCE, 1);
+        let mut shaders = Registry::default();
+        shaders.map.insert(Shader::load(&path).unwrap());
+
+        let start = Instant::now();
+        let mut watcher = ShaderWatcher::new(Duration::from_millis(500), start);
+        assert!(!watcher.poll(start, &mut shaders));
+        assert_eq!(watcher.next_poll(), start + Duration::from_millis(500));
+
+        write(&SOURCE.replace("params.tint", "params.tint * 0.5"), 2);
+        // 間隔が経つまでは調べない
+        assert!(!watcher.poll(start + Duration::from_millis(100), &mut shaders));
+        assert!(watcher.poll(start + Duration::from_millis(500), &mut shaders));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn material() -> Material {
+        Material::new(TextureIndex::default().into())
+            .with_param("strength", MaterialParam::Float(1.0))
+            .with_param(
+                "mask",
+                MaterialParam::Texture(TextureIndex::default().into()),
+            )
+            .with_param("tint", MaterialParam::Color([1.0; 4]))
+    }
+
+    #[test]
+    fn shader_is_validated_on_load() {
+        assert!(Shader::from_wgsl("ok.wgsl", SOURCE).is_ok());
+        let builtin = Shader::from_wgsl("mesh.wgsl", include_str!("./render/mesh.wgsl")).unwrap();
+        assert!(
+            builtin
+                .check_material(&Material::new(TextureIndex::default().into()))
+                .is_ok()
+        );
+        assert!(Shader::from_wgsl("syntax.wgsl", "fn vs_main( {").is_err());
+        let no_fragment = SOURCE.replace("fn fs_main", "fn fs_other");
+        assert!(Shader::from_wgsl("no_fragment.wgsl", no_fragment).is_err());
+    }
+
+    #[test]
+    fn material_params_are_checked_against_shader() {
+        let shader = Shader::from_wgsl("test.wgsl", SOURCE).unwrap();
+        assert!(shader.check_material(&material()).is_ok());
+
+        let wrong_type = material().with_param("tint", MaterialParam::Vec3([1.0; 3]));
+        assert!(shader.check_material(&wrong_type).is_err());
+
+        let mut missing = material();
+        missing.params.retain(|(name, _)| name != "strength");
+        assert!(shader.check_material(&missing).is_err());
+
+        let mut no_texture = material();
+        no_texture.params.retain(|(name, _)| name != "mask");
+        assert!(shader.check_material(&no_texture).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! WGSL の前処理
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Default)]
+/// WGSL のソースの `#` で始まる行を処理する
+///
+/// * `#include "name"`: [`ShaderPreprocessor::with_include`] で登録したソースに置き換える。
+///   同じソースは最初の 1 回だけ展開する。
+/// * `#define NAME`: 以降の `#ifdef NAME` を有効にする
+/// * `#ifdef NAME` / `#ifndef NAME` / `#else` / `#endif`: `NAME` が定義されているかどうかで行を残すか決める
+pub struct ShaderPreprocessor {
+    includes: HashMap<String, String>,
+    defines: HashSet<String>,
+}
+
+impl ShaderPreprocessor {
+    /// `#include "name"` で展開するソースを登録する
+    pub fn with_include(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.includes.insert(name.into(), source.into());
+        self
+    }
+
+    /// ソースの先頭で `#define name` したものとして扱う
+    pub fn with_define(mut self, name: impl Into<String>) -> Self {
+        self.defines.insert(name.into());
+        self
+    }
+
+    /// `source` を前処理した WGSL のソースを返す
+    ///
+    /// * `name`: エラーメッセージに使うソースの名前
+    pub fn process(&self, name: &str, source: &str) -> anyhow::Result<String> {
+        let mut state = State {
+            includes: &self.includes,
+            defines: self.defines.clone(),
+            included: HashSet::new(),
+            output: String::with_capacity(source.len()),
+        };
+        state.process(name, source)?;
+        Ok(state.output)
+    }
+}
+
+#[derive(Debug)]
+/// `#ifdef` の中にいるときの状態
+struct Condition {
+    /// 親の `#ifdef` も含めて、この分岐の行を残すかどうか
+    active: bool,
+    /// 親の `#ifdef` の行を残すかどうか
+    parent_active: bool,
+    in_else: bool,
+}
+
+struct State<'a> {
+    includes: &'a HashMap<String, String>,
+    defines: HashSet<String>,
+    /// 展開済みの `#include`
+    included: HashSet<String>,
+    output: String,
+}
+
+impl State<'_> {
+    fn process(&mut self, name: &str, source: &str) -> anyhow::Result<()> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let active = conditions.last().is_none_or(|condition| condition.active);
+            self.process_line(line, active, &mut conditions)
+                .with_context(|| format!("{name}:{}: {line}", i + 1))?;
+        }
+        anyhow::ensure!(conditions.is_empty(), "{name}: missing #endif");
+        Ok(())
+    }
+
+    fn process_line(
+        &mut self,
+        line: &str,
+        active: bool,
+        conditions: &mut Vec<Condition>,
+    ) -> anyhow::Result<()> {
+        let Some(directive) = line.trim_start().strip_prefix('#') else {
+            if active {
+                self.output.push_str(line);
+                self.output.push('\n');
+            }
+            return Ok(());
+        };
+        let (directive, argument) = directive
+            .split_once(char::is_whitespace)
+            .map_or((directive, ""), |(directive, argument)| {
+                (directive, argument.trim())
+            });
+        match directive {
+            "ifdef" | "ifndef" => {
+                let defined = self.defines.contains(identifier(argument)?);
+                conditions.push(Condition {
+                    active: active && defined == (directive == "ifdef"),
+                    parent_active: active,
+                    in_else: false,
+                });
+            }
+            "else" => {
+                let condition = conditions.last_mut().context("#else without #ifdef")?;
+                anyhow::ensure!(!condition.in_else, "duplicate #else");
+                condition.in_else = true;
+                condition.active = condition.parent_active && !condition.active;
+            }
+            "endif" => {
+                conditions.pop().context("#endif without #ifdef")?;
+            }
+            "define" if active => {
+                self.defines.insert(identifier(argument)?.to_string());
+            }
+            "include" if active => {
+                let name = argument
+                    .strip_prefix('"')
+                    .and_then(|argument| argument.strip_suffix('"'))
+                    .context("expected #include \"name\"")?;
+                let source = self
+                    .includes
+                    .get(name)
+                    .with_context(|| format!("no such include: {name}"))?;
+                if self.included.insert(name.to_string()) {
+                    self.process(name, source)?;
+                }
+            }
+            "define" | "include" => {}
+            _ => anyhow::bail!("unknown directive: #{directive}"),
+        }
+        Ok(())
+    }
+}
+
+fn identifier(argument: &str) -> anyhow::Result<&str> {
+    anyhow::ensure!(
+        !argument.is_empty()
+            && argument
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        "expected identifier"
+    );
+    Ok(argument)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes_are_expanded_once() {
+        let preprocessor = ShaderPreprocessor::default()
+            .with_include("a", "#include \"b\"\nconst A: u32 = B;")
+            .with_include("b", "const B: u32 = 1;");
+        let output = preprocessor
+            .process("main", "#include \"a\"\n#include \"b\"\nconst C: u32 = A;")
+            .unwrap();
+        assert_eq!(
+            output,
+            "const B: u32 = 1;\nconst A: u32 = B;\nconst C: u32 = A;\n"
+        );
+        assert!(preprocessor.process("main", "#include \"c\"").is_err());
+    }
+
+    #[test]
+    fn conditions_select_lines() {
+        let source = "\
+#ifdef A
+a
+#ifndef B
+not b
+#else
+b
+#endif
+#else
+not a
+#endif
+#define B
+#ifdef B
+defined b
+#endif
+";
+        let preprocessor = ShaderPreprocessor::default();
+        assert_eq!(
+            preprocessor.process("main", source).unwrap(),
+            "not a\ndefined b\n"
+        );
+        assert_eq!(
+            preprocessor
+                .with_define("A")
+                .process("main", source)
+                .unwrap(),
+            "a\nnot b\ndefined b\n"
+        );
+    }
+
+    #[test]
+    fn malformed_directives_are_errors() {
+        let preprocessor = ShaderPreprocessor::default();
+        for source in [
+            "#ifdef A",
+            "#endif",
+            "#ifdef A\n#else\n#else\n#endif",
+            "#pragma once",
+            "#ifdef",
+        ] {
+            assert!(preprocessor.process("main", source).is_err(), "{source}");
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! フレームの計測値に関するモジュール
+use std::{cell::Cell, collections::VecDeque, time::Duration};
+
+/// [`TimeSamples`] が保持するサンプル数のデフォルト値
+pub const DEFAULT_SAMPLE_COUNT: usize = 240;
+
+#[derive(Debug, Clone)]
+/// 直近の時間のサンプルを一定数保持し、統計値を計算する
+pub struct TimeSamples {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl Default for TimeSamples {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_SAMPLE_COUNT)
+    }
+}
+
+impl TimeSamples {
+    pub fn with_capacity(capacity: usize) -> Self {
+        debug_assert_ne!(capacity, 0);
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// サンプルを追加する。保持数を超えた場合は古いサンプルから捨てる。
+    pub fn push(&mut self, sample: Duration) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// 古い順にサンプルを返す
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Duration> + '_ {
+        self.samples.iter().copied()
+    }
+
+    /// 最も新しいサンプル
+    pub fn latest(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total: Duration = self.samples.iter().sum();
+        Some(total / self.samples.len() as u32)
+    }
+
+    /// パーセンタイル値を返す。`p` は [0.0, 1.0] の範囲で指定する。
+    ///
+    /// 最近傍順位法で計算する。
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (p.clamp(0.0, 1.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(0.99)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// 1 フレームのレンダリングで数えた値
+pub struct RenderCounters {
+    /// 描画したスプライトの数
+    pub sprites_drawn: u64,
+    /// 視錐台の外にあるため描画しなかったスプライトの数
+    pub sprites_culled: u64,
+    /// 描画したメッシュの数
+    pub meshes_drawn: u64,
+    /// 視錐台の外にあるため描画しなかったメッシュの数
+    pub meshes_culled: u64,
+    /// ドローコールの数
+    pub draw_calls: u64,
+    /// GPU のバッファに送信したバイト数
+    pub uploaded_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+/// レンダリング中に [`RenderCounters`] を数えるためのカウンタ
+///
+/// レンダリングは `&RenderingResource` を通して行われるため、内部可変性を使う。
+pub struct RenderCounterCells {
+    sprites_drawn: Cell<u64>,
+    sprites_culled: Cell<u64>,
+    meshes_drawn: Cell<u64>,
+    meshes_culled: Cell<u64>,
+    draw_calls: Cell<u64>,
+    uploaded_bytes: Cell<u64>,
+}
+
+impl RenderCounterCells {
+    pub(crate) fn add_sprites_drawn(&self, n: u64) {
+        self.sprites_drawn.set(self.sprites_drawn.get() + n);
+    }
+
+    pub(crate) fn add_sprites_culled(&self, n: u64) {
+        self.sprites_culled.set(self.sprites_culled.get() + n);
+    }
+
+    pub(crate) fn add_meshes_drawn(&self, n: u64) {
+        self.meshes_drawn.set(self.meshes_drawn.get() + n);
+    }
+
+    pub(crate) fn add_meshes_culled(&self, n: u64) {
+        self.meshes_culled.set(self.meshes_culled.get() + n);
+    }
+
+    pub(crate) fn add_draw_calls(&self, n: u64) {
+        self.draw_calls.set(self.draw_calls.get() + n);
+    }
+
+    pub(crate) fn add_uploaded_bytes(&self, n: u64) {
+        self.uploaded_bytes.set(self.uploaded_bytes.get() + n);
+    }
+
+    /// 現在の値を取り出し、カウンタを 0 に戻す
+    pub(crate) fn take(&self) -> RenderCounters {
+        RenderCounters {
+            sprites_drawn: self.sprites_drawn.take(),
+            sprites_culled: self.sprites_culled.take(),
+            meshes_drawn: self.meshes_drawn.take(),
+            meshes_culled: self.meshes_culled.take(),
+            draw_calls: self.draw_calls.take(),
+            uploaded_bytes: self.uploaded_bytes.take(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// フレームの計測値
+///
+/// [`Frame`](crate::scene::frame::Frame) には前のフレームまでの値が入る。
+pub struct FrameStats {
+    /// フレーム間の時間
+    pub frame_time: TimeSamples,
+    /// [`Game::update`](crate::Game::update) と [`Scene::update`](crate::scene::Scene::update) にかかった時間
+    pub update_time: TimeSamples,
+    /// レンダリングにかかった CPU 時間
+    pub render_time: TimeSamples,
+    /// 直前のフレームのレンダリングのカウンタ
+    pub counters: RenderCounters,
+    /// 計測したフレーム数
+    pub frame_count: u64,
+}
+
+impl FrameStats {
+    /// 1 フレーム分の計測値を追加する
+    pub fn record(
+        &mut self,
+        frame_time: Duration,
+        update_time: Duration,
+        render_time: Duration,
+        counters: RenderCounters,
+    ) {
+        self.frame_time.push(frame_time);
+        self.update_time.push(update_time);
+        self.render_time.push(render_time);
+        self.counters = counters;
+        self.frame_count += 1;
+    }
+
+    /// 平均フレーム時間から計算した FPS
+    pub fn fps(&self) -> Option<f64> {
+        self.frame_time
+            .average()
+            .filter(|t| !t.is_zero())
+            .map(|t| 1.0 / t.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn empty_samples() {
+        let samples = TimeSamples::default();
+        assert_eq!(samples.min(), None);
+        assert_eq!(samples.average(), None);
+        assert_eq!(samples.p99(), None);
+        assert_eq!(FrameStats::default().fps(), None);
+    }
+
+    #[test]
+    fn rolling_window_drops_old_samples() {
+        let mut samples = TimeSamples::with_capacity(3);
+        for n in [100, 1, 2, 3] {
+            samples.push(ms(n));
+        }
+        assert_eq!(samples.len(), 3);
+        assert_eq!(
+            samples.iter().collect::<Vec<_>>(),
+            vec![ms(1), ms(2), ms(3)]
+        );
+        assert_eq!(samples.min(), Some(ms(1)));
+        assert_eq!(samples.max(), Some(ms(3)));
+        assert_eq!(samples.average(), Some(ms(2)));
+        assert_eq!(samples.latest(), Some(ms(3)));
+    }
+
+    #[test]
+    fn percentile() {
+        let mut samples = TimeSamples::with_capacity(100);
+        for n in (1..=100).rev() {
+            samples.push(ms(n));
+        }
+        assert_eq!(samples.p99(), Some(ms(99)));
+        assert_eq!(samples.percentile(0.5), Some(ms(50)));
+        assert_eq!(samples.percentile(0.0), Some(ms(1)));
+        assert_eq!(samples.percentile(1.0), Some(ms(100)));
+    }
+
+    #[test]
+    fn fps_from_frame_time() {
+        let mut stats = FrameStats::default();
+        stats.record(ms(20), ms(1), ms(2), RenderCounters::default());
+        stats.record(ms(20), ms(1), ms(2), RenderCounters::default());
+        approx_eq(stats.fps().unwrap(), 50.0);
+        assert_eq!(stats.frame_count, 2);
+    }
+
+    #[test]
+    fn counter_cells_reset_on_take() {
+        let cells = RenderCounterCells::default();
+        cells.add_draw_calls(2);
+        cells.add_sprites_drawn(3);
+        cells.add_sprites_culled(4);
+        cells.add_uploaded_bytes(128);
+        assert_eq!(
+            cells.take(),
+            RenderCounters {
+                sprites_drawn: 3,
+                sprites_culled: 4,
+                meshes_drawn: 0,
+                meshes_culled: 0,
+                draw_calls: 2,
+                uploaded_bytes: 128,
+            }
+        );
+        assert_eq!(cells.take(), RenderCounters::default());
+    }
+
+    fn approx_eq(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+}
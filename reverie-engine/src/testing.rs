@@ -0,0 +1,244 @@
+//! ウィンドウや GPU なしでゲームループを進めるためのモジュール
+//!
+//! ゲームロジックのユニットテストに使う。
+use std::{
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase},
+    keyboard::KeyCode,
+};
+
+use crate::{
+    camera::Viewport,
+    game::{self, Game},
+    scene::frame::{InputBuffer, KeyInput},
+    stats::{FrameStats, RenderCounters},
+};
+
+/// 合成した入力で [`Game`] を 1 フレームずつ進めるテストランナー
+///
+/// 入力は次の [`TestRunner::step`] で 1 回だけ [`Frame`](crate::scene::frame::Frame) に渡され、
+/// その後破棄される。マウスの位置とビューポートはフレームをまたいで保持される。
+/// ビューポートの初期値は 800x600。
+#[derive(Debug)]
+pub struct TestRunner<G: Game> {
+    game: G,
+    now: Instant,
+    delta_time: Duration,
+    input: InputBuffer,
+    stats: FrameStats,
+    redraw_requested: bool,
+}
+
+impl<G: Game> TestRunner<G> {
+    /// 60 FPS 相当の `delta_time` でテストランナーを作る。[`Game::init`] が呼ばれる。
+    pub fn new(game: G) -> Self {
+        Self::with_delta_time(game, Duration::from_secs(1) / 60)
+    }
+
+    /// `delta_time` を指定してテストランナーを作る。[`Game::init`] が呼ばれる。
+    pub fn with_delta_time(mut game: G, delta_time: Duration) -> Self {
+        game.init();
+        let mut input = InputBuffer::default();
+        input.viewport =
+            Viewport::new(NonZeroU32::new(800).unwrap(), NonZeroU32::new(600).unwrap());
+        Self {
+            game,
+            now: Instant::now(),
+            delta_time,
+            input,
+            stats: FrameStats::default(),
+            redraw_requested: false,
+        }
+    }
+
+    pub const fn game(&self) -> &G {
+        &self.game
+    }
+
+    pub const fn game_mut(&mut self) -> &mut G {
+        &mut self.game
+    }
+
+    pub fn into_game(self) -> G {
+        self.game
+    }
+
+    /// これまでに進めたフレーム数
+    pub const fn frame_count(&self) -> u64 {
+        self.stats.frame_count
+    }
+
+    /// これまでのフレームの計測値。レンダリングは行わないため描画時間とカウンタは 0 になる。
+    pub const fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// 最後のフレームの時刻
+    pub const fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// 最後のフレームで [`Frame::request_redraw`](crate::scene::frame::Frame::request_redraw) が呼ばれたかどうか
+    pub const fn redraw_requested(&self) -> bool {
+        self.redraw_requested
+    }
+
+    /// 以降のフレームの `delta_time` を設定する
+    pub const fn set_delta_time(&mut self, delta_time: Duration) {
+        self.delta_time = delta_time;
+    }
+
+    /// ウィンドウの描画領域の大きさを変える
+    pub const fn set_viewport(&mut self, viewport: Viewport) {
+        self.input.viewport = viewport;
+    }
+
+    pub fn push_key_event(&mut self, event: KeyInput) {
+        self.input.key_events.push(event);
+    }
+
+    pub fn press_key(&mut self, key_code: KeyCode) {
+        self.push_key_event(KeyInput::from_key_code(key_code, ElementState::Pressed));
+    }
+
+    pub fn release_key(&mut self, key_code: KeyCode) {
+        self.push_key_event(KeyInput::from_key_code(key_code, ElementState::Released));
+    }
+
+    /// マウスカーソルを移動する。位置は物理ピクセルで指定する。
+    pub const fn move_mouse(&mut self, position: PhysicalPosition<f64>) {
+        self.input.mouse_position = position;
+    }
+
+    /// 現在のマウスの位置でマウスボタンの入力を発生させる
+    pub fn mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        let position = self.input.mouse_position;
+        self.input.mouse_clicks.push((state, button, position));
+    }
+
+    /// 現在のマウスの位置でボタンを押して離す
+    pub fn click(&mut self, button: MouseButton) {
+        self.mouse_button(button, ElementState::Pressed);
+        self.mouse_button(button, ElementState::Released);
+    }
+
+    /// 現在のマウスの位置でホイールの入力を発生させる
+    pub fn scroll(&mut self, delta: MouseScrollDelta) {
+        let position = self.input.mouse_position;
+        self.input
+            .mouse_wheels
+            .push((delta, TouchPhase::Moved, position));
+    }
+
+    /// 1 フレーム進める。[`Game::update`] と [`Scene::update`](crate::scene::Scene::update) が呼ばれる。
+    pub fn step(&mut self) {
+        self.now += self.delta_time;
+        let update_start = Instant::now();
+        let frame = self.input.frame(self.now, self.delta_time, &self.stats);
+        game::step_frame(&mut self.game, &frame);
+        let update_time = update_start.elapsed();
+        self.input.clear();
+        self.redraw_requested = self.input.take_redraw_request();
+        self.stats.record(
+            self.delta_time,
+            update_time,
+            Duration::ZERO,
+            RenderCounters::default(),
+        );
+    }
+
+    /// `n` フレーム進める。溜まっている入力は最初のフレームにだけ渡される。
+    pub fn step_n(&mut self, n: usize) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::PhysicalKey;
+
+    use super::*;
+    use crate::scene::{Scene, frame::Frame};
+
+    #[derive(Default)]
+    struct CounterGame {
+        scene: Scene,
+        initialized: bool,
+        elapsed: Duration,
+        jumps: u32,
+        clicks: Vec<PhysicalPosition<f64>>,
+        frames: u32,
+    }
+
+    impl Game for CounterGame {
+        fn init(&mut self) {
+            self.initialized = true;
+        }
+
+        fn get_scene_for_rendering(&mut self) -> &Scene {
+            &self.scene
+        }
+
+        fn get_scene_mut_for_rendering(&mut self) -> &mut Scene {
+            &mut self.scene
+        }
+
+        fn update<'a>(&mut self, frame: &'a Frame<'a>) {
+            self.frames += 1;
+            self.elapsed += frame.delta_time;
+            self.jumps += frame
+                .key_events
+                .iter()
+                .filter(|e| {
+                    e.physical_key == PhysicalKey::Code(KeyCode::Space)
+                        && e.state == ElementState::Pressed
+                })
+                .count() as u32;
+            self.clicks.extend(
+                frame
+                    .mouse_clicks
+                    .iter()
+                    .filter(|(state, ..)| *state == ElementState::Pressed)
+                    .map(|(.., pos)| *pos),
+            );
+        }
+    }
+
+    #[test]
+    fn steps_without_window() {
+        let mut runner =
+            TestRunner::with_delta_time(CounterGame::default(), Duration::from_millis(10));
+        assert!(runner.game().initialized);
+
+        runner.step_n(5);
+        assert_eq!(runner.frame_count(), 5);
+        assert_eq!(runner.game().frames, 5);
+        assert_eq!(runner.game().elapsed, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn input_is_delivered_once() {
+        let mut runner = TestRunner::new(CounterGame::default());
+        runner.press_key(KeyCode::Space);
+        runner.release_key(KeyCode::Space);
+        runner.step_n(3);
+        assert_eq!(runner.game().jumps, 1);
+
+        runner.move_mouse(PhysicalPosition::new(10.0, 20.0));
+        runner.click(MouseButton::Left);
+        runner.step();
+        runner.click(MouseButton::Left);
+        runner.step();
+        assert_eq!(
+            runner.game().clicks,
+            vec![PhysicalPosition::new(10.0, 20.0); 2]
+        );
+    }
+}
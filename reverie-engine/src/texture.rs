@@ -1,4 +1,6 @@
 //! テクスチャに関するモジュール
+use std::num::NonZeroU32;
+
 use anyhow::Context;
 use etagere::{AtlasAllocator, size2};
 use image::{GenericImage, RgbaImage};
@@ -11,20 +13,30 @@ struct Texture {
     data: TextureData,
     usage: TextureUsage,
     label: Option<String>,
+    /// GPU 上のテクスチャを作った回数。作り直されたテクスチャを使うバインドグループを作り直すために使う。
+    generation: u64,
 }
 
 impl Texture {
     pub fn width(&self) -> u32 {
+        if let TextureUsage::RenderTarget(target) = &self.usage {
+            return target.width.get();
+        }
         match &self.data {
             TextureData::Cpu(image) => image.width(),
             TextureData::Gpu(texture, _) => texture.width(),
+            TextureData::Empty => 0,
         }
     }
 
     pub fn height(&self) -> u32 {
+        if let TextureUsage::RenderTarget(target) = &self.usage {
+            return target.height.get();
+        }
         match &self.data {
             TextureData::Cpu(image) => image.height(),
             TextureData::Gpu(texture, _) => texture.height(),
+            TextureData::Empty => 0,
         }
     }
 
@@ -50,8 +62,9 @@ impl Texture {
                     sampler_binding,
                 );
                 self.data = TextureData::Gpu(texture, bind_group);
+                self.generation += 1;
             }
-            TextureData::Gpu(_, _) => {}
+            TextureData::Gpu(_, _) | TextureData::Empty => {}
         }
     }
 }
@@ -60,17 +73,21 @@ impl Texture {
 /// テクスチャのデータ
 ///
 /// テクスチャがCPU上にある場合は[`TextureData::Cpu`]、GPU上にある場合は[`TextureData::Gpu`]となる。
+/// レンダーターゲットが GPU 上に作られる前は[`TextureData::Empty`]となる。
 enum TextureData {
     Cpu(Box<RgbaImage>),
     Gpu(WgpuTexture, wgpu::BindGroup),
+    Empty,
 }
 
 /// テクスチャの使用方法
 ///
-/// 1つのテクスチャを使いまわす場合は[`TextureUsage::Single`]、複数のテクスチャをアトラステクスチャとして使う場合は[`TextureUsage::Atlas`]となる。
+/// 1つのテクスチャを使いまわす場合は[`TextureUsage::Single`]、複数のテクスチャをアトラステクスチャとして使う場合は[`TextureUsage::Atlas`]、
+/// カメラの描画先にする場合は[`TextureUsage::RenderTarget`]となる。
 enum TextureUsage {
     Single,
     Atlas(AtlasAllocator),
+    RenderTarget(RenderTarget),
 }
 
 impl std::fmt::Debug for TextureUsage {
@@ -78,10 +95,22 @@ impl std::fmt::Debug for TextureUsage {
         match self {
             Self::Single => write!(f, "Single"),
             Self::Atlas(_) => write!(f, "Atlas(AtlasAllocator{{*}})"),
+            Self::RenderTarget(target) => write!(f, "RenderTarget({target:?})"),
         }
     }
 }
 
+#[derive(Debug)]
+/// カメラの描画先になるテクスチャの情報
+struct RenderTarget {
+    /// 次に GPU 上に作るときの幅
+    width: NonZeroU32,
+    /// 次に GPU 上に作るときの高さ
+    height: NonZeroU32,
+    /// このテクスチャに描画するときに使う深度バッファ
+    depth: Option<WgpuTexture>,
+}
+
 slotmap::new_key_type! {
     /// [`TextureRegistry`]に登録された単一のテクスチャを指すインデックス
     pub struct TextureIndex;
@@ -131,6 +160,7 @@ impl TextureRegistry {
             data: TextureData::Cpu(Box::new(image)),
             usage: TextureUsage::Single,
             label,
+            generation: 0,
         };
         self.0.map.insert(texture)
     }
@@ -146,10 +176,132 @@ impl TextureRegistry {
             data: TextureData::Cpu(image),
             usage: TextureUsage::Atlas(AtlasAllocator::new(size2(width as i32, height as i32))),
             label,
+            generation: 0,
         };
         self.0.map.insert(texture)
     }
 
+    /// カメラの描画先にするテクスチャを作る
+    ///
+    /// GPU 上のテクスチャは次の描画の前に作られる。
+    /// スプライトなどで表示できるが、そのテクスチャに描画するカメラ自身は、このテクスチャを使うものを描画しない。
+    pub fn create_render_target(
+        &mut self,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        label: Option<String>,
+    ) -> TextureIndex {
+        let texture = Texture {
+            data: TextureData::Empty,
+            usage: TextureUsage::RenderTarget(RenderTarget {
+                width,
+                height,
+                depth: None,
+            }),
+            label,
+            generation: 0,
+        };
+        self.0.map.insert(texture)
+    }
+
+    /// レンダーターゲットの大きさを変える。GPU 上のテクスチャは次の描画の前に作り直される。
+    pub fn resize_render_target(
+        &mut self,
+        index: TextureIndex,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> anyhow::Result<()> {
+        let texture = self
+            .0
+            .map
+            .get_mut(index)
+            .with_context(|| format!("no such texture: {:?}", index))?;
+        if let TextureUsage::RenderTarget(target) = &mut texture.usage {
+            target.width = width;
+            target.height = height;
+            Ok(())
+        } else {
+            anyhow::bail!("texture is not a render target")
+        }
+    }
+
+    /// レンダーターゲットの大きさ
+    pub fn render_target_size(&self, index: TextureIndex) -> Option<(NonZeroU32, NonZeroU32)> {
+        match &self.0.map.get(index)?.usage {
+            TextureUsage::RenderTarget(target) => Some((target.width, target.height)),
+            _ => None,
+        }
+    }
+
+    /// GPU 上にないレンダーターゲットと、大きさが変わったレンダーターゲットを作り直す
+    pub(crate) fn prepare_render_targets(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        texture_binding: u32,
+        sampler_binding: u32,
+    ) {
+        for (_, texture) in self.0.map.iter_mut() {
+            let TextureUsage::RenderTarget(target) = &mut texture.usage else {
+                continue;
+            };
+            let up_to_date = matches!(
+                &texture.data,
+                TextureData::Gpu(color, _)
+                    if color.width() == target.width.get() && color.height() == target.height.get()
+            );
+            if up_to_date {
+                continue;
+            }
+            let label = texture.label.as_deref();
+            let color = WgpuTexture::create_render_target(
+                device,
+                target.width,
+                target.height,
+                format,
+                label,
+            );
+            let bind_group_label = label.map(|s| format!("{s} bind_group"));
+            let bind_group = color.create_bind_group(
+                device,
+                bind_group_label.as_deref(),
+                bind_group_layout,
+                sampler,
+                texture_binding,
+                sampler_binding,
+            );
+            let depth_label = label.map(|s| format!("{s} depth"));
+            target.depth = Some(WgpuTexture::create_depth_texture(
+                device,
+                target.width,
+                target.height,
+                depth_label.as_deref(),
+            ));
+            texture.data = TextureData::Gpu(color, bind_group);
+            texture.generation += 1;
+        }
+    }
+
+    /// レンダーターゲットの色と深度のテクスチャビュー。GPU 上にない場合は `None` を返す。
+    pub(crate) fn render_target_views(
+        &self,
+        index: TextureIndex,
+    ) -> Option<(&wgpu::TextureView, &wgpu::TextureView)> {
+        match self.0.map.get(index)? {
+            Texture {
+                data: TextureData::Gpu(color, _),
+                usage:
+                    TextureUsage::RenderTarget(RenderTarget {
+                        depth: Some(depth), ..
+                    }),
+                ..
+            } => Some((&color.view, &depth.view)),
+            _ => None,
+        }
+    }
+
     pub fn allocate_sub_image(
         &mut self,
         index: TextureIndex,
@@ -209,6 +361,24 @@ impl TextureRegistry {
         }
     }
 
+    /// テクスチャの大きさ (ピクセル)。アトラスのアロケーションの場合はその部分の大きさになる。
+    pub fn get_size(&self, id: TextureId) -> anyhow::Result<(u32, u32)> {
+        let index = id.get_texture_index();
+        let texture = self
+            .0
+            .map
+            .get(*index)
+            .with_context(|| format!("no such texture: {:?}", index))?;
+        match (id, &texture.usage) {
+            (TextureId::Single(_), _) => Ok((texture.width(), texture.height())),
+            (TextureId::Atlas(allocation), TextureUsage::Atlas(allocator)) => {
+                let size = allocator.get(allocation.1).size();
+                Ok((size.width as u32, size.height as u32))
+            }
+            (TextureId::Atlas(_), _) => anyhow::bail!("texture is not for atlas"),
+        }
+    }
+
     /// CPU 上のテクスチャを GPU に送信する
     pub fn send_all_to_gpu(
         &mut self,
@@ -231,6 +401,33 @@ impl TextureRegistry {
         }
     }
 
+    /// GPU 上のテクスチャのビュー。アトラスのアロケーションの場合はアトラス全体のビューを返す。
+    pub(crate) fn get_view(&self, id: TextureId) -> anyhow::Result<&wgpu::TextureView> {
+        let index = id.get_texture_index();
+        let texture = self
+            .0
+            .map
+            .get(*index)
+            .with_context(|| format!("no such texture: {:?}", index))?;
+        if let Texture {
+            data: TextureData::Gpu(texture, _),
+            ..
+        } = texture
+        {
+            Ok(&texture.view)
+        } else {
+            anyhow::bail!("texture is not on GPU")
+        }
+    }
+
+    /// GPU 上のテクスチャを作った回数。レンダーターゲットの大きさが変わって作り直されると増える。
+    pub(crate) fn generation(&self, id: TextureId) -> u64 {
+        self.0
+            .map
+            .get(*id.get_texture_index())
+            .map_or(0, |texture| texture.generation)
+    }
+
     pub fn get_bind_group(&self, id: TextureId) -> anyhow::Result<&wgpu::BindGroup> {
         let index = id.get_texture_index();
         let texture = self
@@ -249,3 +446,38 @@ impl TextureRegistry {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_render_target() {
+        let size = |n| NonZeroU32::new(n).unwrap();
+        let mut textures = TextureRegistry::default();
+        let target = textures.create_render_target(size(64), size(32), None);
+        assert_eq!(
+            textures.render_target_size(target),
+            Some((size(64), size(32)))
+        );
+
+        textures
+            .resize_render_target(target, size(128), size(128))
+            .unwrap();
+        assert_eq!(
+            textures.render_target_size(target),
+            Some((size(128), size(128)))
+        );
+
+        // GPU 上のテクスチャは次の描画の前に作り直されるので、まだ増えない
+        assert_eq!(textures.generation(target.into()), 0);
+
+        let image = textures.new_texture(RgbaImage::new(4, 4), None);
+        assert_eq!(textures.render_target_size(image), None);
+        assert!(
+            textures
+                .resize_render_target(image, size(8), size(8))
+                .is_err()
+        );
+    }
+}
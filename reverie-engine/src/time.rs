@@ -0,0 +1,242 @@
+//! ゲーム内の時間に関するモジュール
+use std::time::Duration;
+
+use reverie_util::interpolation::types::{Time as InterpolationTime, TimeSpan};
+
+/// 1 フレームで進む時間の上限のデフォルト値
+///
+/// ウィンドウのドラッグなどでフレームが大きく遅れたときに、ゲームの時間が一気に進むのを防ぐ。
+pub const DEFAULT_MAX_DELTA: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone)]
+/// フレームごとに進む時計
+pub struct Clock {
+    elapsed: Duration,
+    delta: Duration,
+    frame_count: u64,
+    scale: f64,
+    paused: bool,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            delta: Duration::ZERO,
+            frame_count: 0,
+            scale: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl Clock {
+    /// 時計を `delta` に速度倍率を掛けた分だけ進める。停止中は進まない。
+    ///
+    /// 進む時間が [`Duration`] に収まらない場合は [`Duration::MAX`] で止める。
+    pub fn tick(&mut self, delta: Duration) {
+        if self.paused {
+            self.delta = Duration::ZERO;
+            return;
+        }
+        self.delta =
+            Duration::try_from_secs_f64(delta.as_secs_f64() * self.scale).unwrap_or(Duration::MAX);
+        self.elapsed = self.elapsed.saturating_add(self.delta);
+        self.frame_count += 1;
+    }
+
+    /// 時計が始まってからの経過時間
+    pub const fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// 直前のフレームで進んだ時間
+    pub const fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// 直前のフレームで進んだ時間 (秒)
+    pub const fn delta_secs(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    /// 時計が進んだフレームの数。停止中のフレームは数えない。
+    pub const fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// 速度倍率
+    pub const fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// 速度倍率を設定する。負の値は 0 として扱う。
+    pub const fn set_scale(&mut self, scale: f64) {
+        self.scale = scale.max(0.0);
+    }
+
+    pub const fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub const fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub const fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// [`reverie_util::interpolation::Interpolation`] で使う現在時刻 (ミリ秒)
+    pub const fn interpolation_time(&self) -> InterpolationTime {
+        self.elapsed.as_millis() as InterpolationTime
+    }
+}
+
+/// [`Duration`] を [`reverie_util::interpolation::Interpolation`] で使う時間の長さ (ミリ秒) に変換する
+pub fn interpolation_time_span(duration: Duration) -> TimeSpan {
+    duration.as_millis().min(TimeSpan::MAX as u128) as TimeSpan
+}
+
+#[derive(Debug, Clone)]
+/// シーンの時間
+///
+/// 用途ごとに 3 つの時計を持つ。
+///
+/// * `real`: 実時間。速度倍率や 1 フレームの上限の影響を受けない。計測などに使う。
+/// * `game`: ゲームの時間。スローモーションや一時停止の影響を受ける。ゲームロジックやトゥイーンに使う。
+/// * `ui`: UI の時間。ゲームを一時停止しても進み続ける。メニューのアニメーションなどに使う。
+pub struct Time {
+    pub real: Clock,
+    pub game: Clock,
+    pub ui: Clock,
+    /// `game` と `ui` が 1 フレームで進む時間の上限
+    pub max_delta: Duration,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            real: Clock::default(),
+            game: Clock::default(),
+            ui: Clock::default(),
+            max_delta: DEFAULT_MAX_DELTA,
+        }
+    }
+}
+
+impl Time {
+    /// すべての時計をフレーム間の実時間 `real_delta` に従って進める
+    pub fn advance(&mut self, real_delta: Duration) {
+        self.real.tick(real_delta);
+        let delta = real_delta.min(self.max_delta);
+        self.game.tick(delta);
+        self.ui.tick(delta);
+    }
+
+    /// ゲームの時間の速度倍率を設定する。1.0 で等速、0.5 でスローモーションになる。
+    pub const fn set_time_scale(&mut self, scale: f64) {
+        self.game.set_scale(scale);
+    }
+
+    /// ゲームの時間を止める。UI の時間と実時間は進み続ける。
+    pub const fn pause(&mut self) {
+        self.game.pause();
+    }
+
+    /// ゲームの時間を再開する
+    pub const fn resume(&mut self) {
+        self.game.resume();
+    }
+
+    pub const fn is_paused(&self) -> bool {
+        self.game.is_paused()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reverie_util::interpolation::Interpolation;
+
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn clocks_advance_together() {
+        let mut time = Time::default();
+        time.advance(ms(16));
+        time.advance(ms(16));
+        for clock in [&time.real, &time.game, &time.ui] {
+            assert_eq!(clock.elapsed(), ms(32));
+            assert_eq!(clock.delta(), ms(16));
+            assert_eq!(clock.frame_count(), 2);
+        }
+    }
+
+    #[test]
+    fn time_scale_affects_only_game_clock() {
+        let mut time = Time::default();
+        time.set_time_scale(0.5);
+        time.advance(ms(20));
+        assert_eq!(time.game.delta(), ms(10));
+        assert_eq!(time.ui.delta(), ms(20));
+        assert_eq!(time.real.delta(), ms(20));
+    }
+
+    #[test]
+    fn huge_time_scale_saturates() {
+        let mut time = Time::default();
+        time.set_time_scale(1e30);
+        time.advance(ms(16));
+        time.advance(ms(16));
+        assert_eq!(time.game.delta(), Duration::MAX);
+        assert_eq!(time.game.elapsed(), Duration::MAX);
+    }
+
+    #[test]
+    fn pause_freezes_game_clock() {
+        let mut time = Time::default();
+        time.advance(ms(10));
+        time.pause();
+        time.advance(ms(10));
+        time.advance(ms(10));
+        assert_eq!(time.game.elapsed(), ms(10));
+        assert_eq!(time.game.delta(), Duration::ZERO);
+        assert_eq!(time.game.frame_count(), 1);
+        assert_eq!(time.ui.elapsed(), ms(30));
+        assert_eq!(time.ui.frame_count(), 3);
+
+        time.resume();
+        time.advance(ms(10));
+        assert_eq!(time.game.elapsed(), ms(20));
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut time = Time::default();
+        time.advance(ms(5000));
+        assert_eq!(time.real.delta(), ms(5000));
+        assert_eq!(time.game.delta(), DEFAULT_MAX_DELTA);
+        assert_eq!(time.ui.delta(), DEFAULT_MAX_DELTA);
+    }
+
+    #[test]
+    fn interpolation_follows_game_time() {
+        let mut time = Time::default();
+        let tween = Interpolation::new_lerp(
+            0.0,
+            100.0,
+            time.game.interpolation_time(),
+            interpolation_time_span(ms(100)),
+        );
+        time.advance(ms(50));
+        assert_eq!(tween.value(time.game.interpolation_time()), 50.0);
+
+        time.pause();
+        time.advance(ms(50));
+        assert_eq!(tween.value(time.game.interpolation_time()), 50.0);
+    }
+}
@@ -6,44 +6,59 @@ use tracing_unwrap::ResultExt;
 use wgpu::rwh::{HasDisplayHandle, HasWindowHandle};
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalPosition,
-    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
-    event_loop::ActiveEventLoop,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, ControlFlow},
     window::Window,
 };
 
-use crate::{camera::Camera, game::Game, render::RenderingResource, scene::frame::Frame};
+use crate::{
+    game::{self, Game},
+    pacing::{EngineConfig, FramePacer},
+    render::RenderingResource,
+    scene::frame::InputBuffer,
+    shader::ShaderWatcher,
+    stats::FrameStats,
+};
 
 pub struct App<'window, G: Game> {
     game: G,
     resource: Option<AppResource<'window>>,
     last_update: Instant,
-    key_events: Vec<KeyEvent>,
-    mouse_clicks: Vec<(ElementState, MouseButton, PhysicalPosition<f64>)>,
-    mouse_wheels: Vec<(MouseScrollDelta, TouchPhase, PhysicalPosition<f64>)>,
-    last_mouse_pos: PhysicalPosition<f64>,
+    input: InputBuffer,
+    stats: FrameStats,
+    pacer: FramePacer,
+    /// [`EngineConfig::shader_hot_reload`] が `None` の場合は `None`
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 impl<G: Game> App<'_, G> {
-    pub fn new(game: G) -> Self {
+    pub fn new(game: G, config: EngineConfig) -> Self {
+        let now = Instant::now();
         Self {
             game,
             resource: None,
-            last_update: Instant::now(),
-            key_events: Vec::new(),
-            mouse_clicks: Vec::new(),
-            mouse_wheels: Vec::new(),
-            last_mouse_pos: PhysicalPosition::new(0.0, 0.0),
+            last_update: now,
+            input: InputBuffer::default(),
+            stats: FrameStats::default(),
+            pacer: FramePacer::new(config.update_mode, now),
+            shader_watcher: config
+                .shader_hot_reload
+                .map(|interval| ShaderWatcher::new(interval, now)),
         }
     }
 
+    pub const fn control_flow(&self) -> ControlFlow {
+        self.pacer.control_flow()
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     fn setup(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         if self.resource.is_none() {
             self.game.init();
             let scene = self.game.get_scene_mut_for_rendering();
-            let r = AppResource::new(event_loop, &scene.camera).unwrap_or_log();
+            let r = AppResource::new(event_loop).unwrap_or_log();
             scene.setup(&r.render);
+            self.input.viewport = r.render.viewport;
 
             self.resource = Some(r);
         }
@@ -52,26 +67,28 @@ impl<G: Game> App<'_, G> {
     fn update(&mut self) {
         if let Some(r) = self.resource.as_mut() {
             let now = Instant::now();
-            let frame = Frame {
-                delta_time: now - self.last_update,
-                now,
-                key_events: self.key_events.as_slice(),
-                mouse_clicks: self.mouse_clicks.as_slice(),
-                mouse_wheels: self.mouse_wheels.as_slice(),
-                mouse_position: self.last_mouse_pos,
-            };
-
-            self.game.update(&frame);
-            let scene = self.game.get_scene_mut_for_rendering();
-            scene.update(&frame, &r.render);
+            let delta_time = now - self.last_update;
+            let frame = self.input.frame(now, delta_time, &self.stats);
+            game::step_frame(&mut self.game, &frame);
+            let update_time = now.elapsed();
 
             self.last_update = now;
-            self.key_events.clear();
-            self.mouse_clicks.clear();
-            self.mouse_wheels.clear();
+            self.input.clear();
 
-            r.render.render(scene);
-            r.window.0.request_redraw();
+            let render_start = Instant::now();
+            let scene = self.game.get_scene_mut_for_rendering();
+            if let Some(watcher) = &mut self.shader_watcher {
+                watcher.poll(render_start, &mut scene.shaders);
+            }
+            let counters = r.render.render(scene, &self.stats);
+            let render_time = render_start.elapsed();
+            self.stats
+                .record(delta_time, update_time, render_time, counters);
+
+            self.pacer.on_frame(now);
+            if self.input.take_redraw_request() {
+                self.pacer.request_redraw();
+            }
         }
     }
 }
@@ -80,10 +97,10 @@ impl<G: Game> ApplicationHandler for App<'_, G> {
     fn new_events(
         &mut self,
         _event_loop: &winit::event_loop::ActiveEventLoop,
-        cause: winit::event::StartCause,
+        _cause: winit::event::StartCause,
     ) {
-        if cause == winit::event::StartCause::Poll
-            && let Some(r) = self.resource.as_ref()
+        if let Some(r) = self.resource.as_ref()
+            && self.pacer.should_redraw(Instant::now())
         {
             r.window.0.request_redraw();
         }
@@ -108,34 +125,56 @@ impl<G: Game> ApplicationHandler for App<'_, G> {
                     && let (Some(width), Some(height)) =
                         (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
                 {
-                    r.render
-                        .resize(width, height, &self.game.get_scene_for_rendering().camera);
+                    r.render.resize(width, height);
+                    self.input.viewport = r.render.viewport;
                     r.window.0.request_redraw();
                 }
             }
             WindowEvent::RedrawRequested => self.update(),
-            WindowEvent::KeyboardInput { event, .. } => {
-                if self.resource.is_some() {
-                    self.key_events.push(event);
-                }
+            WindowEvent::KeyboardInput { event, .. } if self.resource.is_some() => {
+                self.input.key_events.push(event.into());
+                self.pacer.on_input();
             }
             WindowEvent::CursorMoved { position, .. } => {
-                self.last_mouse_pos = position;
+                self.input.mouse_position = position;
+                self.pacer.on_input();
             }
             WindowEvent::MouseInput { state, button, .. } => {
-                self.mouse_clicks.push((state, button, self.last_mouse_pos));
+                let position = self.input.mouse_position;
+                self.input.mouse_clicks.push((state, button, position));
+                self.pacer.on_input();
             }
             WindowEvent::MouseWheel { delta, phase, .. } => {
-                self.mouse_wheels.push((delta, phase, self.last_mouse_pos));
+                let position = self.input.mouse_position;
+                self.input.mouse_wheels.push((delta, phase, position));
+                self.pacer.on_input();
             }
             _ => {}
         }
     }
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
-        if self.resource.is_none() {
+        let Some(r) = self.resource.as_ref() else {
             event_loop.exit();
+            return;
+        };
+        let mut control_flow = self.pacer.control_flow();
+        // 描画を待っている間もシェーダーの書き換えを調べ、読み込み直したら描画する
+        if let Some(watcher) = &mut self.shader_watcher {
+            let scene = self.game.get_scene_mut_for_rendering();
+            if watcher.poll(Instant::now(), &mut scene.shaders) {
+                self.pacer.request_redraw();
+                r.window.0.request_redraw();
+                control_flow = self.pacer.control_flow();
+            }
+            let next_poll = watcher.next_poll();
+            control_flow = match control_flow {
+                ControlFlow::Wait => ControlFlow::WaitUntil(next_poll),
+                ControlFlow::WaitUntil(at) => ControlFlow::WaitUntil(at.min(next_poll)),
+                ControlFlow::Poll => ControlFlow::Poll,
+            };
         }
+        event_loop.set_control_flow(control_flow);
     }
 }
 
@@ -145,7 +184,7 @@ pub struct AppResource<'window> {
 }
 
 impl AppResource<'_> {
-    pub fn new(event_loop: &ActiveEventLoop, camera: &Camera) -> anyhow::Result<Self> {
+    pub fn new(event_loop: &ActiveEventLoop) -> anyhow::Result<Self> {
         let window = event_loop
             .create_window(Window::default_attributes())
             .unwrap_or_log();
@@ -160,13 +199,8 @@ impl AppResource<'_> {
             .try_into()
             .context("error: window inner height is zero")?;
 
-        let render = pollster::block_on(RenderingResource::setup(
-            window.clone(),
-            width,
-            height,
-            camera,
-        ))
-        .context("failed: setup wgpu")?;
+        let render = pollster::block_on(RenderingResource::setup(window.clone(), width, height))
+            .context("failed: setup wgpu")?;
 
         Ok(Self { window, render })
     }
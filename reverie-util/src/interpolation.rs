@@ -7,7 +7,7 @@ pub mod types;
 /// 補間をする構造体
 ///
 /// `begin`から`end`まで、`t_total`の時間をかけて値を変化させる。
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Interpolation<T> {
     begin: T,
     end: T,
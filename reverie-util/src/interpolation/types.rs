@@ -1,3 +1,7 @@
+/// 時刻
+///
+/// 単位は使う側が決める。reverie-engine の `Clock` はミリ秒を使う。
 pub type Time = i64;
+/// 時間の長さ。単位は [`Time`] と同じ。
 pub type TimeSpan = u32;
 pub(crate) type NormalizedTime = f32;